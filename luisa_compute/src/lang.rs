@@ -13,7 +13,7 @@ use bumpalo::Bump;
 use indexmap::IndexMap;
 use luisa_compute_ir::ir::CurveBasisSet;
 
-use crate::runtime::{RawCallable, WeakDevice};
+use crate::runtime::{RaceCheck, RawCallable, WeakDevice};
 
 pub mod ir {
     pub use luisa_compute_ir::context::register_type;
@@ -27,18 +27,32 @@ use ir::{
     Instruction, IrBuilder, ModulePools, Pooled, Type, TypeOf, UserNodeData,
 };
 
+use self::debug::DeviceAsserts;
 use self::index::IntoIndex;
 
 pub mod autodiff;
+pub mod camera;
+pub mod color;
 pub mod control_flow;
 pub mod debug;
 pub mod external;
+pub mod filter;
 pub mod functions;
+pub mod geometry;
 pub mod index;
+pub mod linalg;
+pub mod lighting;
+pub mod material;
+pub mod nn;
+pub mod noise;
 pub mod ops;
 pub mod poly;
 pub mod print;
+pub mod rng;
+pub mod sampling;
+pub mod sdf;
 pub mod soa;
+pub mod stats;
 pub mod types;
 
 pub(crate) trait CallFuncTrait {
@@ -333,6 +347,10 @@ pub(crate) struct FnRecorder {
     pub(crate) index_const_pool: IndexMap<i32, NodeRef>,
     pub(crate) rt: ResourceTracker,
     pub(crate) curve_bases: CurveBasisSet,
+    /// Set while recording a kernel for [`Device::create_kernel_checked`].
+    pub(crate) race_check: Option<RaceCheck>,
+    /// Created by the first [`device_assert!`] of a kernel.
+    pub(crate) device_asserts: Option<DeviceAsserts>,
 }
 pub(crate) type FnRecorderPtr = Rc<RefCell<FnRecorder>>;
 impl FnRecorder {
@@ -456,6 +474,8 @@ impl FnRecorder {
             dtors: vec![],
             const_builder: IrBuilder::new(pools.clone()),
             rt: ResourceTracker::new(),
+            race_check: None,
+            device_asserts: None,
         }
     }
     pub(crate) fn map_captured_vars(&mut self, node0: SafeNodeRef) -> SafeNodeRef {
@@ -2,6 +2,7 @@ use std::cell::RefCell;
 
 use crate::internal_prelude::*;
 
+use super::types::array::VLArrayVar;
 use super::with_recorder;
 
 struct AdContext {
@@ -9,6 +10,8 @@ struct AdContext {
     backward_called: bool,
     is_forward_mode: bool,
     n_forward_grads: usize,
+    /// Run after the adjoint pass, see [`after_backward`].
+    after_backward: Vec<Box<dyn FnOnce()>>,
     // forward: Option<Pooled<BasicBlock>>,
 }
 
@@ -19,6 +22,7 @@ impl AdContext {
             backward_called: false,
             is_forward_mode: false,
             n_forward_grads: 0,
+            after_backward: vec![],
         }
     }
     fn new_fwd(n: usize) -> Self {
@@ -27,6 +31,7 @@ impl AdContext {
             backward_called: false,
             is_forward_mode: true,
             n_forward_grads: n,
+            after_backward: vec![],
         }
     }
     fn reset(&mut self) {
@@ -36,6 +41,21 @@ impl AdContext {
 thread_local! {
     static AD_CONTEXT:RefCell<AdContext> = RefCell::new(AdContext::new_rev());
 }
+
+/// Callables are recorded into their own module, so an enclosing autodiff
+/// section must not be visible while recording one. Otherwise
+/// `requires_grad`/`gradient` inside the callable would be attributed to
+/// the caller's section.
+pub(crate) fn with_isolated_ad_context<R>(f: impl FnOnce() -> R) -> R {
+    let saved = AD_CONTEXT.with(|c| std::mem::replace(&mut *c.borrow_mut(), AdContext::new_rev()));
+    let ret = f();
+    AD_CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        assert!(!c.started, "autodiff section is not closed in callable");
+        *c = saved;
+    });
+    ret
+}
 pub fn requires_grad<V: Value>(var: Expr<V>) {
     AD_CONTEXT.with(|c| {
         let c = c.borrow();
@@ -85,9 +105,77 @@ pub fn backward_with_grad<V: Value>(out: Expr<V>, grad: Expr<V>) {
     __current_scope(|b| {
         b.call(Func::GradientMarker, &[out, grad], Type::void());
         b.call(Func::Backward, &[], Type::void());
+        #[cfg(feature = "autodiff-debug")]
+        record_tape(b.bb());
+    });
+    let after = AD_CONTEXT.with(|c| std::mem::take(&mut c.borrow_mut().after_backward));
+    for f in after {
+        f();
+    }
+}
+
+/// Records `f` to run right after the [`backward`] of the current reverse
+/// mode section, where [`gradient`] is available.
+pub(crate) fn after_backward(f: impl FnOnce() + 'static) {
+    AD_CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        assert!(c.started, "autodiff section is not started");
+        assert!(!c.backward_called, "backward is already called");
+        c.after_backward.push(Box::new(f));
     });
 }
 
+#[cfg(feature = "autodiff-debug")]
+thread_local! {
+    static LAST_TAPE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+#[cfg(feature = "autodiff-debug")]
+fn record_tape(block: &BasicBlock) {
+    use std::collections::HashMap;
+    use std::fmt::Write;
+    let mut ids = HashMap::new();
+    let mut name = |node: NodeRef| {
+        let next = ids.len();
+        format!("%{}", ids.entry(node).or_insert(next))
+    };
+    let mut tape = String::new();
+    for node in block.iter() {
+        let line = match node.get().instruction.as_ref() {
+            Instruction::Call(Func::RequiresGradient, args) => {
+                format!("requires_grad {}", name(args[0]))
+            }
+            Instruction::Call(Func::GradientMarker, args) => {
+                format!("backward {} with adjoint {}", name(args[0]), name(args[1]))
+            }
+            Instruction::Call(Func::Backward, _) => continue,
+            Instruction::Call(f, args) => {
+                let args: Vec<_> = args.iter().map(|a| name(*a)).collect();
+                format!("{} = {:?}({})", name(node), f, args.join(", "))
+            }
+            Instruction::Const(_) => format!("{} = const", name(node)),
+            Instruction::Local { init } => format!("{} = local {}", name(node), name(*init)),
+            _ => format!("{} = <control flow>", name(node)),
+        };
+        writeln!(tape, "{}", line).unwrap();
+    }
+    LAST_TAPE.with(|t| *t.borrow_mut() = Some(tape));
+}
+
+/// Human-readable listing of the reverse mode tape recorded by the last
+/// [`backward`] on this thread: the inputs marked with [`requires_grad`] and
+/// every operation the adjoint pass propagates through, in recording order.
+/// Values are numbered `%0, %1, ...` in order of first use.
+///
+/// Nested control flow is listed as a single opaque entry. Use
+/// [`Kernel::dump`] to see the generated backward code.
+#[cfg(feature = "autodiff-debug")]
+pub fn autodiff_tape_dump() -> String {
+    LAST_TAPE
+        .with(|t| t.borrow().clone())
+        .expect("backward is not called")
+}
+
 /// Gradient of a value in *Reverse mode* AD
 pub fn gradient<V: Value>(var: Expr<V>) -> Expr<V> {
     AD_CONTEXT.with(|c| {
@@ -131,6 +219,119 @@ pub fn detach<T: NodeLike>(v: T) -> T {
     T::from_node(node.into())
 }
 
+/// Whether an autodiff section is being recorded and its gradients are not
+/// computed yet, so that code recorded now is differentiated.
+pub(crate) fn in_autodiff_section() -> bool {
+    AD_CONTEXT.with(|c| {
+        let c = c.borrow();
+        c.started && !c.backward_called
+    })
+}
+
+/// Returns `value`, differentiated as `surrogate()` in an autodiff section.
+///
+/// Only the gradient of `surrogate` is used, not its value. It is only
+/// recorded in an autodiff section, so builtins given another adjoint keep
+/// their builtin everywhere else.
+pub(crate) fn with_gradient_of<X: Linear>(
+    value: Expr<X>,
+    surrogate: impl FnOnce() -> Expr<X>,
+) -> Expr<X> {
+    if !in_autodiff_section() {
+        return value;
+    }
+    let surrogate = surrogate();
+    // `surrogate - detach(surrogate)` is zero in value but carries the
+    // gradient of `surrogate`
+    let zero: Expr<X> = Func::Sub.call2(surrogate, detach(surrogate));
+    Func::Add.call2(detach(value), zero)
+}
+
+/// Evaluates `forward(x)` with a user-defined adjoint in *Reverse mode* AD.
+///
+/// `vjp(x, upstream)` returns the gradient w.r.t. `x` given the gradient
+/// `upstream` of the result. Neither closure is differentiated itself. The
+/// adjoint is recorded by evaluating `vjp` once with `upstream = 1` and
+/// scaling it by the actual upstream gradient, so `vjp` must be linear in
+/// `upstream`, as any vector-Jacobian product is.
+pub fn custom_vjp<X: Linear<Scalar = f32>>(
+    x: Expr<X>,
+    forward: impl FnOnce(Expr<X>) -> Expr<f32>,
+    vjp: impl FnOnce(Expr<X>, Expr<f32>) -> Expr<X>,
+) -> Expr<f32> {
+    let x_detached = detach(x);
+    let y = detach(forward(x_detached));
+    let adjoint = detach(vjp(x_detached, 1.0f32.expr()));
+    // a surrogate that is zero in value but has gradient `adjoint` w.r.t. `x`
+    let surrogate: Expr<f32> = if X::N == 1 {
+        Func::Mul.call2(adjoint, x)
+    } else {
+        Func::Dot.call2(adjoint, x)
+    };
+    let zero: Expr<f32> = Func::Sub.call2(surrogate, detach(surrogate));
+    Func::Add.call2(y, zero)
+}
+
+/// Runs a device `while` loop in *Reverse mode* AD: starting from `init`,
+/// replaces the state `s` by `step(param, s)` while `cond(i, s)` holds for
+/// the number `i` of iterations so far, and returns the final state.
+///
+/// The loop itself is not differentiated. Every state is stored on a tape
+/// of `max_iters` elements, and the adjoint is computed by running the loop
+/// backwards, differentiating one `step` at a time in a nested [`autodiff`]
+/// section. Running more than `max_iters` iterations is an error.
+#[tracked]
+pub fn autodiff_while<P: Linear<Scalar = f32>>(
+    param: Expr<P>,
+    init: Expr<f32>,
+    max_iters: u32,
+    cond: impl Fn(Expr<u32>, Expr<f32>) -> Expr<bool>,
+    step: impl Fn(Expr<P>, Expr<f32>) -> Expr<f32>,
+) -> Expr<f32> {
+    AD_CONTEXT.with(|c| {
+        let c = c.borrow();
+        assert!(c.started, "autodiff section is not started");
+        assert!(
+            !c.is_forward_mode,
+            "autodiff_while() is called in forward mode"
+        );
+    });
+    let p = detach(param).var();
+    let tape = VLArrayVar::<f32>::zero(max_iters as usize);
+    let n = 0u32.var();
+    let s = detach(init).var();
+    while cond(**n, **s) {
+        lc_assert!(n.lt(max_iters), "autodiff_while() exceeds max_iters");
+        tape.write(**n, s);
+        *s = step(**p, **s);
+        *n += 1u32;
+    }
+    // the adjoints of `init` and `param` for an upstream gradient of 1
+    let adj_s = 1.0f32.var();
+    let adj_p = Var::<P>::zeroed();
+    while n > 0u32 {
+        *n -= 1u32;
+        autodiff(|| {
+            let s_t = tape.read(**n);
+            let p_t = **p;
+            requires_grad(s_t);
+            requires_grad(p_t);
+            backward_with_grad(step(p_t, s_t), **adj_s);
+            *adj_s = gradient(s_t);
+            let sum: Expr<P> = Func::Add.call2(**adj_p, gradient(p_t));
+            *adj_p = sum;
+        });
+    }
+    // a surrogate that is zero in value but has the gradients above
+    let surrogate: Expr<f32> = if P::N == 1 {
+        Func::Mul.call2(**adj_p, param)
+    } else {
+        Func::Dot.call2(**adj_p, param)
+    };
+    let surrogate = surrogate + **adj_s * init;
+    **s + (surrogate - detach(surrogate))
+}
+
 /// Start a *Forward mode* AD section that propagates N gradients w.r.t to input
 /// variable
 pub fn forward_autodiff(n_grads: usize, body: impl Fn()) {
@@ -200,13 +401,36 @@ pub fn output_gradients<V: Value>(v: Expr<V>) -> Vec<Expr<V>> {
     grads
 }
 
+/// Tangent of a value in a *Forward mode* AD section propagating a single
+/// gradient, i.e. its derivative along the seed given to
+/// [`propagate_gradient`].
+pub fn tangent<V: Value>(v: Expr<V>) -> Expr<V> {
+    let mut grads = output_gradients(v);
+    assert_eq!(
+        grads.len(),
+        1,
+        "tangent() is called in a section propagating multiple gradients"
+    );
+    grads.pop().unwrap()
+}
+
+/// Start a *Reverse mode* AD section.
+///
+/// Sections can be nested: an inner section has its own `requires_grad`
+/// inputs and `backward`, and the gradients it produces are ordinary values
+/// of the outer section, so the outer `backward` differentiates through them.
+/// This gives second derivatives such as `gradient(gradient(f))`.
 pub fn autodiff(body: impl Fn()) {
-    AD_CONTEXT.with(|c| {
+    let outer = AD_CONTEXT.with(|c| {
         let mut c = c.borrow_mut();
-        assert!(!c.started, "autodiff section is already started");
-        *c = AdContext::new_rev();
-        c.started = true;
+        let mut inner = AdContext::new_rev();
+        inner.started = true;
+        std::mem::replace(&mut *c, inner)
     });
+    assert!(
+        !outer.is_forward_mode,
+        "autodiff section is nested in a forward mode section"
+    );
     with_recorder(|r| {
         let s = &mut r.scopes;
         s.push(IrBuilder::new(r.pools.clone()));
@@ -216,7 +440,7 @@ pub fn autodiff(body: impl Fn()) {
         let mut c = c.borrow_mut();
         assert!(c.started, "autodiff section is not started");
         assert!(c.backward_called, "backward is not called");
-        c.reset();
+        *c = outer;
     });
     let body = __pop_scope();
     __current_scope(|b| {
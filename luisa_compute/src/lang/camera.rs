@@ -0,0 +1,35 @@
+//! Camera projection helpers.
+//!
+//! Screen coordinates `uv` lie in `[0, 1]^2` and map to normalized device
+//! coordinates by `ndc.xy = 2 uv - 1`. `depth` is the NDC depth `ndc.z`, i.e.
+//! the value stored in a depth buffer. Matrices act on column vectors. All
+//! helpers are differentiable with respect to every argument, including the
+//! matrices.
+use crate::internal_prelude::*;
+
+/// Reconstructs the point seen at `uv` with `depth` from the inverse of the
+/// projection matrix, e.g. a view-space position for `inv_proj = P^-1` or a
+/// world-space one for `(P V)^-1`.
+#[tracked]
+pub fn unproject(
+    uv: impl AsExpr<Value = Float2>,
+    depth: impl AsExpr<Value = f32>,
+    inv_proj: impl AsExpr<Value = Mat4>,
+) -> Expr<Float3> {
+    let ndc = uv.as_expr() * 2.0f32 - 1.0f32;
+    let p = inv_proj.as_expr() * Float4::expr(ndc.x, ndc.y, depth.as_expr(), 1.0f32);
+    p.xyz() / p.w
+}
+
+/// Projects `p` with `proj`, returning its screen coordinates and depth.
+/// Inverse of [`unproject`] for `proj = inv_proj^-1`.
+#[tracked]
+pub fn project(
+    p: impl AsExpr<Value = Float3>,
+    proj: impl AsExpr<Value = Mat4>,
+) -> (Expr<Float2>, Expr<f32>) {
+    let p = p.as_expr();
+    let clip = proj.as_expr() * Float4::expr(p.x, p.y, p.z, 1.0f32);
+    let ndc = clip.xyz() / clip.w;
+    (ndc.xy() * 0.5f32 + 0.5f32, ndc.z)
+}
@@ -0,0 +1,94 @@
+//! Color space conversions and tone mapping.
+//!
+//! RGB values are linear Rec.709 / sRGB primaries with a D65 white point.
+//! All conversions are differentiable. The piecewise sRGB transfer functions
+//! clamp the argument of the unused branch so that gradients stay finite on
+//! both sides of the breakpoint.
+use crate::internal_prelude::*;
+
+#[tracked]
+fn srgb_to_linear_1(c: Expr<f32>) -> Expr<f32> {
+    let curve = ((c.max(0.04045f32) + 0.055f32) / 1.055f32).powf(2.4f32);
+    select(c <= 0.04045f32, c / 12.92f32, curve)
+}
+
+#[tracked]
+fn linear_to_srgb_1(c: Expr<f32>) -> Expr<f32> {
+    let curve = 1.055f32 * c.max(0.0031308f32).powf(1.0f32 / 2.4f32) - 0.055f32;
+    select(c <= 0.0031308f32, c * 12.92f32, curve)
+}
+
+/// Decodes sRGB encoded values to linear RGB.
+#[tracked]
+pub fn srgb_to_linear(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(
+        srgb_to_linear_1(v.x),
+        srgb_to_linear_1(v.y),
+        srgb_to_linear_1(v.z),
+    )
+}
+
+/// Encodes linear RGB values with the sRGB transfer function.
+#[tracked]
+pub fn linear_to_srgb(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(
+        linear_to_srgb_1(v.x),
+        linear_to_srgb_1(v.y),
+        linear_to_srgb_1(v.z),
+    )
+}
+
+/// Converts linear RGB to CIE 1931 XYZ.
+#[tracked]
+pub fn rgb_to_xyz(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(
+        0.4124564f32 * v.x + 0.3575761f32 * v.y + 0.1804375f32 * v.z,
+        0.2126729f32 * v.x + 0.7151522f32 * v.y + 0.0721750f32 * v.z,
+        0.0193339f32 * v.x + 0.1191920f32 * v.y + 0.9503041f32 * v.z,
+    )
+}
+
+/// Converts CIE 1931 XYZ to linear RGB.
+#[tracked]
+pub fn xyz_to_rgb(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(
+        3.2404542f32 * v.x - 1.5371385f32 * v.y - 0.4985314f32 * v.z,
+        -0.9692660f32 * v.x + 1.8760108f32 * v.y + 0.0415560f32 * v.z,
+        0.0556434f32 * v.x - 0.2040259f32 * v.y + 1.0572252f32 * v.z,
+    )
+}
+
+#[tracked]
+fn reinhard_1(c: Expr<f32>) -> Expr<f32> {
+    let c = c.max(0.0f32);
+    c / (1.0f32 + c)
+}
+
+#[tracked]
+fn aces_filmic_1(c: Expr<f32>) -> Expr<f32> {
+    let c = c.max(0.0f32);
+    let num = c * (2.51f32 * c + 0.03f32);
+    let den = c * (2.43f32 * c + 0.59f32) + 0.14f32;
+    (num / den).min(1.0f32)
+}
+
+/// Reinhard tone mapping `v / (1 + v)`, per channel. Negative values map to
+/// zero.
+#[tracked]
+pub fn reinhard(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(reinhard_1(v.x), reinhard_1(v.y), reinhard_1(v.z))
+}
+
+/// Narkowicz's fit of the ACES filmic tone curve, per channel, clamped to
+/// `[0, 1]`. Values above about `7.24` saturate to one and have a zero
+/// gradient.
+#[tracked]
+pub fn aces_filmic(v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let v = v.as_expr();
+    Float3::expr(aces_filmic_1(v.x), aces_filmic_1(v.y), aces_filmic_1(v.z))
+}
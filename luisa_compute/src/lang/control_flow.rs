@@ -4,6 +4,7 @@ use crate::internal_prelude::*;
 use ir::SwitchCase;
 
 use super::debug::__unreachable_typed;
+use super::types::core::Numeric;
 
 /**
  * If you want rustfmt to format your code, use if_!(cond, { .. }, { .. })
@@ -38,6 +39,21 @@ macro_rules! loop_ {
         $crate::while_!(true.expr(), $body)
     };
 }
+/// Exits the innermost device loop, like [`break_()`].
+#[macro_export]
+macro_rules! break_ {
+    () => {
+        $crate::lang::control_flow::break_()
+    };
+}
+/// Skips to the next iteration of the innermost device loop, like
+/// [`continue_()`].
+#[macro_export]
+macro_rules! continue_ {
+    () => {
+        $crate::lang::control_flow::continue_()
+    };
+}
 
 #[inline]
 pub fn break_() {
@@ -199,6 +215,22 @@ pub fn select<A: Aggregate>(mask: Expr<bool>, a: A, b: A) -> A {
     A::from_vec_nodes(ret)
 }
 
+/// Adds `value` to `acc` where `cond` is true and leaves it unchanged elsewhere.
+///
+/// This is a `select` rather than a branch around a store, so no phi node is
+/// created and the gradient reaches `value` only where `cond` holds.
+pub fn accumulate_if<X: Linear>(
+    cond: impl AsExpr<Value = bool>,
+    acc: Var<X>,
+    value: impl AsExpr<Value = X>,
+) where
+    X::Scalar: Numeric,
+{
+    let old = acc.load();
+    let sum: Expr<X> = Func::Add.call2(old, value.as_expr());
+    acc.store(select(cond.as_expr(), sum, old));
+}
+
 pub fn generic_loop(
     mut cond: impl FnMut() -> Expr<bool>,
     mut body: impl FnMut(),
@@ -308,12 +340,21 @@ pub fn loop_(body: impl Fn()) {
     });
 }
 
+/// Records `body` once for every item of `iter`, e.g. the indices of a
+/// range known when recording, fully unrolling the loop.
+///
+/// The IR has no unroll hint for the backend, so the loop is unrolled while
+/// recording. As there is no loop left, `body` cannot use [`break_`] or
+/// [`continue_()`].
 pub fn for_unrolled<I: IntoIterator>(iter: I, body: impl Fn(I::Item)) {
     for i in iter {
         body(i);
     }
 }
 
+/// Records a device-side loop calling `body` with each index of `r`, whose
+/// bounds may be expressions only known when the kernel runs. `body` may use
+/// [`break_`] and [`continue_`].
 pub fn for_range<R: ForLoopRange>(r: R, body: impl Fn(Expr<R::Element>)) {
     let start = r.start().get();
     let end = r.end().get();
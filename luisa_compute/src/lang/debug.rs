@@ -23,6 +23,22 @@ macro_rules! lc_unreachable {
         $crate::lang::debug::__unreachable(file!(), line!(), column!())
     };
 }
+/// Stops the calling thread if `cond` is false, recording the message and
+/// the dispatch id of the first failing thread, which the host reads with
+/// [`Kernel::check_asserts`](crate::runtime::Kernel::check_asserts).
+///
+/// Unlike [`lc_assert!`] this does not abort the program. It can only be
+/// used in kernels, and compiles to nothing without the opt-in
+/// `device-assert` feature.
+#[macro_export]
+macro_rules! device_assert {
+    ($arg:expr) => {
+        $crate::lang::debug::__device_assert($arg, stringify!($arg), file!(), line!(), column!())
+    };
+    ($arg:expr, $msg:expr) => {
+        $crate::lang::debug::__device_assert($arg, $msg, file!(), line!(), column!())
+    };
+}
 #[macro_export]
 macro_rules! lc_assert {
     ($arg:expr) => {
@@ -149,6 +165,56 @@ pub fn __assert(cond: impl Into<Expr<bool>>, msg: &str, file: &str, line: u32, c
     });
 }
 
+/// State of the [`device_assert!`]s of a kernel. `state` holds the 1-based
+/// index in `messages` of the first assertion that failed, or 0, followed by
+/// the dispatch id of the thread that failed it.
+pub(crate) struct DeviceAsserts {
+    pub(crate) state: Buffer<u32>,
+    pub(crate) messages: Vec<String>,
+}
+
+#[doc(hidden)]
+pub fn __device_assert(cond: impl Into<Expr<bool>>, msg: &str, file: &str, line: u32, col: u32) {
+    if cfg!(not(feature = "device-assert")) {
+        return;
+    }
+    let cond = cond.into();
+    let (state, index) = with_recorder(|r| {
+        assert!(
+            r.building_kernel,
+            "device_assert! cannot be used in callable!"
+        );
+        let asserts = r.device_asserts.get_or_insert_with(|| {
+            let device = r
+                .device
+                .as_ref()
+                .and_then(|d| d.upgrade())
+                .expect("device_assert! requires a kernel recorded for a device");
+            DeviceAsserts {
+                state: device.create_buffer_from_slice(&[0u32; 4]),
+                messages: vec![],
+            }
+        });
+        asserts
+            .messages
+            .push(format!("{} at {}:{}:{}", msg, file, line, col));
+        (asserts.state.view(..), asserts.messages.len() as u32)
+    });
+    track! {
+        if !cond {
+            let state = state.var();
+            // only the first failure is recorded
+            if state.atomic_compare_exchange(0, 0u32, index) == 0u32 {
+                let id = dispatch_id();
+                state.write(1, id.x);
+                state.write(2, id.y);
+                state.write(3, id.z);
+            }
+            return_();
+        }
+    }
+}
+
 /// Insert a comment to the generated source code.
 /// *Note*: this is only effective when LUISA_DUMP_SOURCE is set to 1
 pub fn comment(msg: &str) {
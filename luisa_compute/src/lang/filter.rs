@@ -0,0 +1,25 @@
+//! Differentiable weights for edge-aware image filters.
+use crate::internal_prelude::*;
+
+/// Weight of `neighbor` in a bilateral filter centered at `center`:
+/// `exp(-spatial_dist^2 / (2 sigma_spatial^2) - |neighbor - center|^2 / (2 sigma_range^2))`.
+///
+/// The range term uses the squared color difference, so the weight is smooth
+/// in the pixel values even where they are equal. Differentiable with
+/// respect to the pixel values and both sigmas.
+#[tracked]
+pub fn bilateral_weight(
+    center: impl AsExpr<Value = Float3>,
+    neighbor: impl AsExpr<Value = Float3>,
+    spatial_dist: impl AsExpr<Value = f32>,
+    sigma_spatial: impl AsExpr<Value = f32>,
+    sigma_range: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let d = neighbor.as_expr() - center.as_expr();
+    let spatial_dist = spatial_dist.as_expr();
+    let sigma_spatial = sigma_spatial.as_expr();
+    let sigma_range = sigma_range.as_expr();
+    let spatial = spatial_dist * spatial_dist / (sigma_spatial * sigma_spatial);
+    let range = d.dot(d) / (sigma_range * sigma_range);
+    (-0.5f32 * (spatial + range)).exp()
+}
@@ -1,6 +1,8 @@
 use crate::{internal_prelude::*, lang::with_recorder};
 
+use super::autodiff::custom_vjp;
 use super::types::core::{Integral, Numeric};
+use super::types::shared::Shared;
 
 pub fn thread_id() -> Expr<Uint3> {
     Expr::<Uint3>::from_node(
@@ -38,6 +40,12 @@ fn check_block_size_for_cpu() {
         );
     });
 }
+/// Waits until every thread of the block reaches this point, and makes
+/// their writes to [`Shared`] memory visible to each other.
+///
+/// Every thread of the block must reach the same call, so it must not be
+/// placed under control flow that diverges within a block, e.g. an `if`
+/// depending on the thread id; otherwise the kernel may hang.
 pub fn sync_block() {
     if is_cpu_backend() {
         check_block_size_for_cpu();
@@ -48,6 +56,16 @@ pub fn sync_block() {
     })
 }
 
+/// Declares an array of `len` elements in memory shared by the threads of a
+/// block. Only available in kernels; the block size is set with
+/// [`set_block_size`].
+pub fn shared<T: Value>(len: usize) -> Shared<T> {
+    with_recorder(|r| {
+        assert!(r.building_kernel, "shared cannot be called in callable!");
+    });
+    Shared::new(len)
+}
+
 pub fn warp_is_first_active_lane() -> Expr<bool> {
     Expr::<bool>::from_node(
         __current_scope(|b| b.call(Func::WarpIsFirstActiveLane, &[], bool::type_())).into(),
@@ -172,6 +190,55 @@ pub fn warp_read_first_active_lane<T: Linear>(v: Expr<T>) -> Expr<T> {
         __current_scope(|b| b.call(Func::WarpReadFirstLane, &[v], v.type_().clone())).into(),
     )
 }
+fn check_warp_ops() {
+    let device = with_recorder(|r| r.device.as_ref().and_then(|d| d.upgrade()));
+    if let Some(device) = device {
+        assert!(
+            device.features().subgroup_ops != Some(false),
+            "warp operations are not supported on the {} backend",
+            device.name()
+        );
+    }
+}
+/// [`warp_active_sum`] of a scalar that also works on the `cpu` backend.
+///
+/// The `cpu` backend runs warps of a single lane and only supports a block
+/// size of 1, where this returns `v`. Panics on other backends reporting no
+/// [`Features::subgroup_ops`](crate::runtime::Features::subgroup_ops).
+pub fn warp_reduce_sum<T: Numeric + VectorElement>(v: Expr<T>) -> Expr<T> {
+    if is_cpu_backend() {
+        check_block_size_for_cpu();
+        return v;
+    }
+    check_warp_ops();
+    warp_active_sum(v)
+}
+/// [`warp_active_max`] of a scalar that also works on the `cpu` backend.
+/// See [`warp_reduce_sum`] for backend support.
+pub fn warp_reduce_max<T: Numeric + VectorElement>(v: Expr<T>) -> Expr<T> {
+    if is_cpu_backend() {
+        check_block_size_for_cpu();
+        return v;
+    }
+    check_warp_ops();
+    warp_active_max(v)
+}
+/// Mask of the active lanes of the warp for which `pred` holds, with lane
+/// `i` in bit `i % 32` of component `i / 32`. See [`warp_reduce_sum`] for
+/// backend support.
+pub fn warp_ballot(pred: impl AsExpr<Value = bool>) -> Expr<Uint4> {
+    let pred = pred.as_expr();
+    if is_cpu_backend() {
+        check_block_size_for_cpu();
+        let bit = select(pred, 1u32.expr(), 0u32.expr());
+        return Uint4::expr(bit, 0u32, 0u32, 0u32);
+    }
+    check_warp_ops();
+    let pred = pred.node().get();
+    Expr::<Uint4>::from_node(
+        __current_scope(|b| b.call(Func::WarpActiveBitMask, &[pred], Uint4::type_())).into(),
+    )
+}
 pub fn set_block_size(size: [u32; 3]) {
     with_recorder(|r| {
         assert!(
@@ -191,6 +258,17 @@ pub fn block_size_expr() -> Expr<Uint3> {
     let sz = block_size();
     Uint3::expr(sz[0], sz[1], sz[2])
 }
+/// A reading of the device's cycle counter, for measuring the cost of kernel
+/// code by taking the difference of two readings in the same thread.
+///
+/// This calls the native `clock64` function of the backend's shader
+/// language, as CUDA provides it. The counter runs per multiprocessor, in
+/// clock cycles rather than time, so readings from different threads are not
+/// comparable. Kernels using it fail to compile on backends without
+/// `clock64`.
+pub fn clock() -> Expr<u64> {
+    crate::runtime::ExternalCallable::<fn() -> Expr<u64>>::new("clock64").call()
+}
 
 pub unsafe fn bitcast<From: Value, To: Value>(expr: Expr<From>) -> Expr<To> {
     assert_eq!(std::mem::size_of::<From>(), std::mem::size_of::<To>());
@@ -199,3 +277,285 @@ pub unsafe fn bitcast<From: Value, To: Value>(expr: Expr<From>) -> Expr<To> {
         __current_scope(|b| b.call(Func::Bitcast, &[expr], <To as TypeOf>::type_())).into(),
     )
 }
+
+/// Result of [`frexp`]: `x == mantissa * 2^exponent`.
+#[derive(Clone, Copy, Debug, Value)]
+#[repr(C)]
+#[value_new(pub)]
+pub struct Frexp {
+    pub mantissa: f32,
+    pub exponent: i32,
+}
+
+/// Splits `x` into a mantissa in `[0.5, 1)` and a power-of-two exponent.
+/// Zero, infinities and NaN are returned unchanged with a zero exponent, as in libm.
+#[tracked]
+pub fn frexp(x: impl AsExpr<Value = f32>) -> Expr<Frexp> {
+    let x = x.as_expr();
+    // subnormals are scaled by 2^32 into the normal range first
+    let is_subnormal = x.abs() < f32::MIN_POSITIVE;
+    let scaled = select(is_subnormal, x * 4294967296.0f32, x);
+    let bias = select(is_subnormal, 32i32.expr(), 0i32.expr());
+    let bits = scaled.bitcast::<u32>();
+    let exponent = ((bits >> 23u32) & 0xffu32).as_i32() - 126 - bias;
+    let mantissa = ((bits & 0x807fffffu32) | 0x3f000000u32).bitcast::<f32>();
+    let special = (x == 0.0f32) | x.is_infinite() | x.is_nan();
+    Frexp::new_expr(
+        select(special, x, mantissa),
+        select(special, 0i32.expr(), exponent),
+    )
+}
+
+/// Computes `m * 2^e`.
+#[tracked]
+pub fn ldexp(m: impl AsExpr<Value = f32>, e: impl AsExpr<Value = i32>) -> Expr<f32> {
+    // 2^k for k in [-126, 127]
+    let exp2i = |k: Expr<i32>| ((k + 127).as_u32() << 23u32).bitcast::<f32>();
+    // any exponent beyond +-300 saturates to zero or infinity anyway; split the
+    // rest into three factors so each one stays a normal float
+    let e = e.as_expr().clamp((-300i32).expr(), 300i32.expr());
+    let e0 = e / 3;
+    let e1 = (e - e0) / 2;
+    let e2 = e - e0 - e1;
+    m.as_expr() * exp2i(e0) * exp2i(e1) * exp2i(e2)
+}
+
+/// Linearly interpolates the table `lut`, whose entries are spread evenly over
+/// `[0, 1]`, at `x`. `x` is clamped to `[0, 1]`; inside that range the
+/// derivative is the slope of the segment containing `x`.
+#[tracked]
+pub fn lut_lerp<const N: usize>(lut: &[f32; N], x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    assert!(N >= 2, "lut_lerp needs at least two entries");
+    let table = (*lut).expr();
+    let last_segment = (N - 2) as u32;
+    let t = x.as_expr().clamp(0.0f32.expr(), 1.0f32.expr()) * (N - 1) as f32;
+    let i = t.floor().as_u32().clamp(0u32.expr(), last_segment.expr());
+    table.read(i).lerp(table.read(i + 1), t - i.as_f32())
+}
+
+/// Interpolates the vertex attributes `v0`, `v1` and `v2` with the barycentric
+/// coordinates `bary`. Differentiable with respect to both the attributes and
+/// `bary`.
+pub fn bary_interp<X: Linear<Scalar = f32>>(
+    v0: impl AsExpr<Value = X>,
+    v1: impl AsExpr<Value = X>,
+    v2: impl AsExpr<Value = X>,
+    bary: impl AsExpr<Value = Float3>,
+) -> Expr<X> {
+    let bary = bary.as_expr();
+    let weighted = |v: Expr<X>, w: Expr<f32>| -> Expr<X> {
+        let w: Expr<X> = if X::N == 1 {
+            Expr::<X>::from_node(w.node())
+        } else {
+            Func::Vec.call(w)
+        };
+        Func::Mul.call2(v, w)
+    };
+    let sum: Expr<X> = Func::Add.call2(
+        weighted(v0.as_expr(), bary.x),
+        weighted(v1.as_expr(), bary.y),
+    );
+    Func::Add.call2(sum, weighted(v2.as_expr(), bary.z))
+}
+
+/// Whether `a` and `b` agree within an absolute tolerance `abs` or a relative
+/// tolerance `rel`, the same test as the autodiff test harness:
+/// `|a - b| <= abs || |a - b| / (|a| + 1e-6) <= rel`. For vectors, all lanes
+/// must agree.
+pub fn approx_eq<X: Linear<Scalar = f32>>(
+    a: impl AsExpr<Value = X>,
+    b: impl AsExpr<Value = X>,
+    rel: impl AsExpr<Value = f32>,
+    abs: impl AsExpr<Value = f32>,
+) -> Expr<bool> {
+    let (a, b) = (a.as_expr(), b.as_expr());
+    let splat = |s: Expr<f32>| -> Expr<X> {
+        if X::N == 1 {
+            Expr::<X>::from_node(s.node())
+        } else {
+            Func::Vec.call(s)
+        }
+    };
+    let diff: Expr<X> = Func::Abs.call(Func::Sub.call2::<X, X, X>(a, b));
+    let scale: Expr<X> = Func::Add.call2(Func::Abs.call::<X, X>(a), splat(1e-6f32.expr()));
+    let rel_bound: Expr<X> = Func::Mul.call2(splat(rel.as_expr()), scale);
+    let close: Expr<X::WithScalar<bool>> = Func::BitOr.call2(
+        Func::Le.call2::<X, X, X::WithScalar<bool>>(diff, splat(abs.as_expr())),
+        Func::Le.call2::<X, X, X::WithScalar<bool>>(diff, rel_bound),
+    );
+    if X::N == 1 {
+        Expr::<bool>::from_node(close.node())
+    } else {
+        Func::All.call(close)
+    }
+}
+
+/// Antialiased step: `0` for `x` well below `threshold`, `1` well above it,
+/// with a smoothstep transition `width` wide centered on `threshold`.
+///
+/// `width` is the filter footprint of `x`, e.g. how much `x` changes across a
+/// pixel. Kernels have no screen-space derivatives to derive it from, so it
+/// is supplied by the caller. Differentiable with respect to all arguments;
+/// the gradient is nonzero only inside the transition band.
+#[tracked]
+pub fn aastep(
+    threshold: impl AsExpr<Value = f32>,
+    x: impl AsExpr<Value = f32>,
+    width: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let width = width.as_expr().max(1e-12f32);
+    let t = (x.as_expr() - threshold.as_expr()) / width + 0.5f32;
+    let t = t.clamp(0.0f32.expr(), 1.0f32.expr());
+    t * t * (3.0f32 - 2.0f32 * t)
+}
+
+/// `max(0, min(1, x))`. See [`saturate_vec`].
+pub fn clamp01(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    saturate_vec(x)
+}
+
+/// Clamps every lane of `v` to `[0, 1]` as `max(0, min(1, v))`.
+///
+/// Unlike [`FloatExpr::saturate`] and `clamp`, which the backend may lower
+/// to a compare and select, this is always a min and a max. The gradient is
+/// one inside `[0, 1]` and zero outside.
+pub fn saturate_vec<X: Linear<Scalar = f32>>(v: impl AsExpr<Value = X>) -> Expr<X> {
+    let splat = |s: f32| -> Expr<X> {
+        if X::N == 1 {
+            Expr::<X>::from_node(s.expr().node())
+        } else {
+            Func::Vec.call(s.expr())
+        }
+    };
+    let upper: Expr<X> = Func::Min.call2(splat(1.0), v.as_expr());
+    Func::Max.call2(splat(0.0), upper)
+}
+
+/// `erfc(|x|)`, Abramowitz and Stegun 7.1.26.
+#[tracked]
+fn erfc_abs(x: Expr<f32>) -> Expr<f32> {
+    let t = 1.0f32 / (1.0f32 + 0.3275911f32 * x.abs());
+    let poly = 1.061405429f32;
+    let poly = poly * t - 1.453152027f32;
+    let poly = poly * t + 1.421413741f32;
+    let poly = poly * t - 0.284496736f32;
+    let poly = poly * t + 0.254829592f32;
+    poly * t * (-x * x).exp()
+}
+
+/// `erfc(|x|)` with a bounded relative error while the result is a normal
+/// float, i.e. for `|x|` up to about 9.2 (Numerical Recipes `erfcc`, a
+/// Chebyshev fit of `erfc(z) * exp(z^2)`).
+#[tracked]
+fn erfc_abs_relative(x: Expr<f32>) -> Expr<f32> {
+    let z = x.abs();
+    let t = 2.0f32 / (2.0f32 + z);
+    let poly = 0.17087277f32;
+    let poly = poly * t - 0.82215223f32;
+    let poly = poly * t + 1.48851587f32;
+    let poly = poly * t - 1.13520398f32;
+    let poly = poly * t + 0.27886807f32;
+    let poly = poly * t - 0.18628806f32;
+    let poly = poly * t + 0.09678418f32;
+    let poly = poly * t + 0.37409196f32;
+    let poly = poly * t + 1.00002368f32;
+    let poly = poly * t - 1.26551223f32;
+    // `exp(-z^2)` loses relative precision with the rounding of `z^2`, so
+    // `z` is split into `hi`, with an exact square, and `lo`
+    let hi = (z * 16.0f32).floor() / 16.0f32;
+    let lo = z - hi;
+    t * (-hi * hi).exp() * (poly - lo * (z + hi)).exp()
+}
+
+/// Error function. The value has an absolute error below `1.5e-7` (Abramowitz
+/// and Stegun 7.1.26); the gradient is the exact `2 / sqrt(pi) * exp(-x^2)`.
+#[tracked]
+pub fn erf(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    custom_vjp(
+        x.as_expr(),
+        |x| {
+            let e = 1.0f32 - erfc_abs(x);
+            select(x < 0.0f32, -e, e)
+        },
+        |x, g| g * std::f32::consts::FRAC_2_SQRT_PI * (-x * x).exp(),
+    )
+}
+
+/// Complementary error function `1 - erf(x)`, evaluated directly so that it
+/// keeps its precision for large `x`: for `x >= 0` the relative error is
+/// below `5e-7` until the result underflows near `x = 9.2`, and for `x < 0`
+/// the absolute error is below `5e-7`. Both bounds assume a correctly
+/// rounded `exp`; each ulp of error in the backend's `exp` adds about
+/// `1.2e-7`. The gradient is the exact `-2 / sqrt(pi) * exp(-x^2)`.
+#[tracked]
+pub fn erfc(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    custom_vjp(
+        x.as_expr(),
+        |x| {
+            let e = erfc_abs_relative(x);
+            select(x < 0.0f32, 2.0f32 - e, e)
+        },
+        |x, g| -g * std::f32::consts::FRAC_2_SQRT_PI * (-x * x).exp(),
+    )
+}
+
+/// Rounds `x`, clamped to `[0, 1]`, to the nearest of `levels` evenly spaced
+/// values `0, 1 / (levels - 1), ..., 1`, with the straight-through gradient
+/// estimator: the gradient is `1` everywhere, as if no rounding happened.
+/// `levels` must be at least 2.
+#[tracked]
+pub fn quantize_ste(x: impl AsExpr<Value = f32>, levels: impl AsExpr<Value = u32>) -> Expr<f32> {
+    let steps = (levels.as_expr() - 1u32).as_f32();
+    custom_vjp(
+        x.as_expr(),
+        |x| (x.clamp(0.0f32.expr(), 1.0f32.expr()) * steps).round() / steps,
+        |_, g| g,
+    )
+}
+
+/// Angle in `[0, pi]` between `a` and `b`, computed as
+/// `atan2(|a x b|, a . b)`. Unlike `acos` of the normalized dot product, both
+/// the value and the gradient stay accurate for nearly parallel and nearly
+/// antiparallel vectors. `a` and `b` need not be normalized.
+#[tracked]
+pub fn angle_between(a: impl AsExpr<Value = Float3>, b: impl AsExpr<Value = Float3>) -> Expr<f32> {
+    let (a, b) = (a.as_expr(), b.as_expr());
+    a.cross(b).length().atan2(a.dot(b))
+}
+
+/// Wraps the angle `theta` to `[-pi, pi)`. The gradient is `1` everywhere
+/// except at the wrap points.
+#[tracked]
+pub fn wrap_angle(theta: impl AsExpr<Value = f32>) -> Expr<f32> {
+    let theta = theta.as_expr();
+    let two_pi = std::f32::consts::TAU;
+    theta - ((theta + std::f32::consts::PI) / two_pi).floor() * two_pi
+}
+
+/// Values that can be linearly interpolated by an `f32` weight. Implemented
+/// for `f32` and float vectors; `#[derive(Lerp)]` implements it field-wise
+/// for `#[derive(Value)]` structs whose fields all implement `Lerp`.
+pub trait Lerp: Value {
+    fn lerp_expr(a: Expr<Self>, b: Expr<Self>, t: Expr<f32>) -> Expr<Self>;
+}
+
+impl<X: Linear<Scalar = f32>> Lerp for X {
+    fn lerp_expr(a: Expr<Self>, b: Expr<Self>, t: Expr<f32>) -> Expr<Self> {
+        let t: Expr<X> = if X::N == 1 {
+            Expr::<X>::from_node(t.node())
+        } else {
+            Func::Vec.call(t)
+        };
+        Func::Lerp.call3(a, b, t)
+    }
+}
+
+/// Linearly interpolates every field of `a` and `b` by `t`:
+/// `a * (1 - t) + b * t`. Differentiable with respect to `a`, `b` and `t`.
+pub fn blend<T: Lerp>(
+    a: impl AsExpr<Value = T>,
+    b: impl AsExpr<Value = T>,
+    t: impl AsExpr<Value = f32>,
+) -> Expr<T> {
+    T::lerp_expr(a.as_expr(), b.as_expr(), t.as_expr())
+}
@@ -0,0 +1,92 @@
+//! Geometric frames and transforms.
+use crate::internal_prelude::*;
+
+/// Transforms the tangent-space normal `normal_ts`, e.g. read from a normal
+/// map, to world space and normalizes it.
+///
+/// The TBN basis is orthonormalized by Gram-Schmidt in the order `normal`,
+/// `tangent`, `bitangent`, so the interpolated vertex frame need not be
+/// orthonormal; only the handedness of `bitangent` is kept. Differentiable
+/// with respect to all arguments.
+#[tracked]
+pub fn tangent_to_world(
+    normal_ts: impl AsExpr<Value = Float3>,
+    tangent: impl AsExpr<Value = Float3>,
+    bitangent: impl AsExpr<Value = Float3>,
+    normal: impl AsExpr<Value = Float3>,
+) -> Expr<Float3> {
+    let v = normal_ts.as_expr();
+    let n = normal.as_expr().normalize();
+    let t = tangent.as_expr();
+    let t = (t - n * n.dot(t)).normalize();
+    let b = bitangent.as_expr();
+    let b = (b - n * n.dot(b) - t * t.dot(b)).normalize();
+    (t * v.x + b * v.y + n * v.z).normalize()
+}
+
+/// A rotation quaternion `w + v.x i + v.y j + v.z k`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Value)]
+#[value_new(pub)]
+pub struct Quat {
+    pub v: Float3,
+    pub w: f32,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Self {
+            v: Float3::new(0.0, 0.0, 0.0),
+            w: 1.0,
+        }
+    }
+    /// Rotation by `angle` radians about the unit vector `axis`.
+    pub fn from_axis_angle(axis: Float3, angle: f32) -> Self {
+        let (s, c) = (0.5 * angle).sin_cos();
+        Self {
+            v: Float3::new(axis.x * s, axis.y * s, axis.z * s),
+            w: c,
+        }
+    }
+    /// Rotation by `angle` radians about `axis`, which is normalized first.
+    #[tracked]
+    pub fn from_axis_angle_expr(
+        axis: impl AsExpr<Value = Float3>,
+        angle: impl AsExpr<Value = f32>,
+    ) -> Expr<Self> {
+        let half = 0.5f32 * angle.as_expr();
+        Self::new_expr(axis.as_expr().normalize() * half.sin(), half.cos())
+    }
+}
+
+impl QuatExpr {
+    #[tracked]
+    pub fn conjugate(&self) -> Expr<Quat> {
+        Quat::new_expr(-self.v, self.w)
+    }
+    #[tracked]
+    pub fn normalize(&self) -> Expr<Quat> {
+        let inv_len = (self.v.dot(self.v) + self.w * self.w).rsqrt();
+        Quat::new_expr(self.v * inv_len, self.w * inv_len)
+    }
+    /// Rotates `p` by this quaternion, which must be normalized.
+    /// Differentiable with respect to both.
+    #[tracked]
+    pub fn rotate(&self, p: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let p = p.as_expr();
+        let t = self.v.cross(p) * 2.0f32;
+        p + t * self.w + self.v.cross(t)
+    }
+}
+
+/// Hamilton product: rotating by `a * b` rotates by `b`, then by `a`.
+impl MulExpr<Expr<Quat>> for Expr<Quat> {
+    type Output = Expr<Quat>;
+    #[tracked]
+    fn mul(self, rhs: Expr<Quat>) -> Self::Output {
+        Quat::new_expr(
+            rhs.v * self.w + self.v * rhs.w + self.v.cross(rhs.v),
+            self.w * rhs.w - self.v.dot(rhs.v),
+        )
+    }
+}
@@ -0,0 +1,62 @@
+//! Lighting helpers.
+use crate::internal_prelude::*;
+
+/// The first 9 real spherical harmonics basis functions (bands 0 to 2),
+/// evaluated at the unit direction `dir`, ordered `Y(l, m)` with `m` from
+/// `-l` to `l`.
+#[tracked]
+pub fn sh_eval_l2(dir: impl AsExpr<Value = Float3>) -> [Expr<f32>; 9] {
+    let d = dir.as_expr();
+    let (x, y, z) = (d.x, d.y, d.z);
+    [
+        0.282095f32.expr(),
+        0.488603f32 * y,
+        0.488603f32 * z,
+        0.488603f32 * x,
+        1.092548f32 * x * y,
+        1.092548f32 * y * z,
+        0.315392f32 * (3.0f32 * z * z - 1.0f32),
+        1.092548f32 * x * z,
+        0.546274f32 * (x * x - y * y),
+    ]
+}
+
+/// Evaluates the band-limited function with SH `coeffs` (in the order of
+/// [`sh_eval_l2`]) at the unit direction `dir`. Differentiable with respect
+/// to both `coeffs` and `dir`.
+#[tracked]
+pub fn sh_dot(coeffs: [Expr<f32>; 9], dir: impl AsExpr<Value = Float3>) -> Expr<f32> {
+    let basis = sh_eval_l2(dir);
+    let sum = 0.0f32.var();
+    for_unrolled(0..9, |i| {
+        *sum += coeffs[i] * basis[i];
+    });
+    **sum
+}
+
+/// Soft visibility of a light through the cone of shadow rays towards it,
+/// past one occluder.
+///
+/// `closest_hit_distance` is how far the shadow ray passes from the occluder
+/// at its closest approach, negative if the ray hits it, e.g. the smallest
+/// SDF value found while marching the ray. `ray_length` is the distance
+/// along the ray of that closest approach, and `light_radius` the radius of
+/// the light divided by its distance. The cone of rays towards the light has
+/// the radius `light_radius * ray_length` there: the light is fully visible
+/// if the occluder clears the cone, fully blocked if it covers the cone, and
+/// a smoothstep in between.
+///
+/// For several occluders, take the minimum of their visibilities.
+/// Differentiable with respect to all arguments; the gradient is nonzero only
+/// in the penumbra.
+#[tracked]
+pub fn soft_visibility(
+    closest_hit_distance: impl AsExpr<Value = f32>,
+    ray_length: impl AsExpr<Value = f32>,
+    light_radius: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let cone = (light_radius.as_expr() * ray_length.as_expr()).max(1e-12f32);
+    let s = 0.5f32 + 0.5f32 * closest_hit_distance.as_expr() / cone;
+    let s = s.clamp(0.0f32.expr(), 1.0f32.expr());
+    s * s * (3.0f32 - 2.0f32 * s)
+}
@@ -0,0 +1,66 @@
+//! Small dense linear algebra on local arrays.
+use crate::internal_prelude::*;
+
+/// A vector of `N` floats.
+pub type VecN<const N: usize> = [f32; N];
+/// An `N x N` matrix stored as `N` rows.
+pub type MatN<const N: usize> = [[f32; N]; N];
+
+/// Solves `a * x = b` by LU decomposition with partial pivoting.
+///
+/// The decomposition is fully unrolled, with row exchanges done by
+/// `select`, so it is meant for small systems (`N` up to about 8) and is
+/// differentiable with respect to both `a` and `b`. The result is undefined
+/// if `a` is singular.
+pub fn solve<const N: usize>(
+    a: impl AsExpr<Value = MatN<N>>,
+    b: impl AsExpr<Value = VecN<N>>,
+) -> Expr<VecN<N>> {
+    let (a, b) = (a.as_expr(), b.as_expr());
+    let mut a: Vec<Vec<Expr<f32>>> = (0..N)
+        .map(|i| {
+            let row = a.read(i as u32);
+            (0..N).map(|j| row.read(j as u32)).collect()
+        })
+        .collect();
+    let mut b: Vec<Expr<f32>> = (0..N).map(|i| b.read(i as u32)).collect();
+    for k in 0..N {
+        // find the row with the largest pivot candidate
+        let mut best = a[k][k].abs();
+        let mut pivot = (k as u32).expr();
+        for i in k + 1..N {
+            let candidate = a[i][k].abs();
+            let better = candidate.gt(best);
+            best = select(better, candidate, best);
+            pivot = select(better, (i as u32).expr(), pivot);
+        }
+        // exchange it with row k; at most one row matches
+        for i in k + 1..N {
+            let swap = pivot.eq(i as u32);
+            for j in k..N {
+                let (x, y) = (a[k][j], a[i][j]);
+                a[k][j] = select(swap, y, x);
+                a[i][j] = select(swap, x, y);
+            }
+            let (x, y) = (b[k], b[i]);
+            b[k] = select(swap, y, x);
+            b[i] = select(swap, x, y);
+        }
+        for i in k + 1..N {
+            let f = a[i][k] / a[k][k];
+            for j in k + 1..N {
+                a[i][j] = a[i][j] - f * a[k][j];
+            }
+            b[i] = b[i] - f * b[k];
+        }
+    }
+    let mut x = vec![0.0f32.expr(); N];
+    for i in (0..N).rev() {
+        let mut s = b[i];
+        for j in i + 1..N {
+            s = s - a[i][j] * x[j];
+        }
+        x[i] = s / a[i][i];
+    }
+    <VecN<N>>::from_elems_expr(std::array::from_fn(|i| x[i]))
+}
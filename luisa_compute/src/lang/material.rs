@@ -0,0 +1,50 @@
+//! Differentiable material models.
+use std::f32::consts::FRAC_1_PI;
+
+use crate::internal_prelude::*;
+
+/// Smith's masking term `G1` for the GGX distribution with `alpha = roughness^2`.
+#[tracked]
+fn ggx_smith_g1(cos_theta: Expr<f32>, alpha2: Expr<f32>) -> Expr<f32> {
+    let cos2 = cos_theta * cos_theta;
+    2.0f32 * cos_theta / (cos_theta + (alpha2 + (1.0f32 - alpha2) * cos2).sqrt())
+}
+
+/// Cook-Torrance specular BRDF with the GGX (Trowbridge-Reitz) distribution,
+/// the separable Smith masking-shadowing term and Schlick's Fresnel
+/// approximation.
+///
+/// `wo` and `wi` point away from the surface and, like `normal`, must be
+/// normalized. `roughness` is the perceptual roughness: the distribution is
+/// evaluated with `alpha = roughness^2`. `f0` is the reflectance at normal
+/// incidence. Returns zero when either direction is below the surface.
+/// Differentiable with respect to all arguments.
+#[tracked]
+pub fn ggx_brdf(
+    wo: impl AsExpr<Value = Float3>,
+    wi: impl AsExpr<Value = Float3>,
+    normal: impl AsExpr<Value = Float3>,
+    roughness: impl AsExpr<Value = f32>,
+    f0: impl AsExpr<Value = Float3>,
+) -> Expr<Float3> {
+    let (wo, wi, n) = (wo.as_expr(), wi.as_expr(), normal.as_expr());
+    let (roughness, f0) = (roughness.as_expr(), f0.as_expr());
+    let cos_o = n.dot(wo);
+    let cos_i = n.dot(wi);
+    let h = (wo + wi).normalize();
+    let cos_h = n.dot(h).max(0.0f32);
+    let alpha = roughness * roughness;
+    let alpha2 = (alpha * alpha).max(1e-7f32);
+    let t = cos_h * cos_h * (alpha2 - 1.0f32) + 1.0f32;
+    let d = alpha2 * FRAC_1_PI / (t * t);
+    let g = ggx_smith_g1(cos_o.max(0.0f32), alpha2) * ggx_smith_g1(cos_i.max(0.0f32), alpha2);
+    let m = (1.0f32 - wo.dot(h).max(0.0f32)).max(0.0f32);
+    let m5 = m * m * m * m * m;
+    let f = f0 + (1.0f32 - f0) * m5;
+    let spec = f * (d * g / (4.0f32 * cos_o * cos_i).max(1e-7f32));
+    select(
+        (cos_o > 0.0f32) & (cos_i > 0.0f32),
+        spec,
+        Float3::splat_expr(0.0f32),
+    )
+}
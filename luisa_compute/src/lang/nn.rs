@@ -0,0 +1,47 @@
+//! Activation functions commonly used in neural networks.
+//!
+//! All of them are built from differentiable primitives and are arranged so
+//! that neither the value nor the gradient overflows for large `|x|`.
+use crate::internal_prelude::*;
+
+/// Logistic function `1 / (1 + exp(-x))`.
+#[tracked]
+pub fn sigmoid(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    let x = x.as_expr();
+    // exp(-|x|) <= 1, so neither branch can overflow
+    let e = (-x.abs()).exp();
+    select(x >= 0.0f32, 1.0f32 / (1.0f32 + e), e / (1.0f32 + e))
+}
+
+/// `ln(1 + y)`, accurate even where `1 + y` rounds to 1.
+#[tracked]
+fn ln_1p(y: Expr<f32>) -> Expr<f32> {
+    let u = 1.0f32 + y;
+    // `u - 1` is exact, so `y / (u - 1)` corrects the rounding of `u`; the
+    // denominator is kept nonzero so the unused branch has a finite gradient
+    let rounded = u == 1.0f32;
+    let d = select(rounded, 1.0f32.expr(), u - 1.0f32);
+    select(rounded, y, u.ln() * (y / d))
+}
+
+/// `ln(1 + exp(x))`, computed as `max(x, 0) + ln_1p(exp(-|x|))`.
+#[tracked]
+pub fn softplus(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    let x = x.as_expr();
+    select(x > 0.0f32, x, 0.0f32.expr()) + ln_1p((-x.abs()).exp())
+}
+
+/// Sigmoid linear unit `x * sigmoid(x)`.
+#[tracked]
+pub fn silu(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    let x = x.as_expr();
+    x * sigmoid(x)
+}
+
+/// Gaussian error linear unit, tanh approximation.
+#[tracked]
+pub fn gelu(x: impl AsExpr<Value = f32>) -> Expr<f32> {
+    let x = x.as_expr();
+    const SQRT_2_OVER_PI: f32 = 0.7978846;
+    0.5f32 * x * (1.0f32 + (SQRT_2_OVER_PI * (x + 0.044715f32 * x * x * x)).tanh())
+}
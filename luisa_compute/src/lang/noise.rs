@@ -0,0 +1,76 @@
+//! Gradient noise.
+//!
+//! The gradient at each lattice point is picked by hashing its integer
+//! coordinates with [`pcg_hash`], so the noise is deterministic. Both
+//! functions are C1 continuous, also across cell boundaries, and are
+//! differentiable with respect to `p`.
+use std::f32::consts::{SQRT_2, TAU};
+
+use super::rng::{hash_to_float, pcg_hash};
+use super::sampling::sample_uniform_sphere;
+use crate::internal_prelude::*;
+
+/// Skew and unskew factors of the 3D simplex lattice.
+const F3: f32 = 1.0 / 3.0;
+const G3: f32 = 1.0 / 6.0;
+
+/// Hash of the lattice point with the integral coordinates `(x, y, z)`.
+#[tracked]
+fn lattice_hash(x: Expr<f32>, y: Expr<f32>, z: Expr<f32>) -> Expr<u32> {
+    let h = pcg_hash(z.as_i32().as_u32());
+    let h = pcg_hash(y.as_i32().as_u32() ^ h);
+    pcg_hash(x.as_i32().as_u32() ^ h)
+}
+
+/// 2D Perlin noise with a quintic fade, roughly in `[-1, 1]`. Zero at the
+/// lattice points.
+#[tracked]
+pub fn perlin2(p: impl AsExpr<Value = Float2>) -> Expr<f32> {
+    let p = p.as_expr();
+    let i = p.floor();
+    let f = p - i;
+    let u = f * f * f * (f * (f * 6.0f32 - 15.0f32) + 10.0f32);
+    let corner = |dx: f32, dy: f32| {
+        let h = lattice_hash(i.x + dx, i.y + dy, 0.0f32.expr());
+        let theta = TAU * hash_to_float(h);
+        theta.cos() * (f.x - dx) + theta.sin() * (f.y - dy)
+    };
+    let (n00, n10) = (corner(0.0, 0.0), corner(1.0, 0.0));
+    let (n01, n11) = (corner(0.0, 1.0), corner(1.0, 1.0));
+    let n0 = n00 + (n10 - n00) * u.x;
+    let n1 = n01 + (n11 - n01) * u.x;
+    SQRT_2 * (n0 + (n1 - n0) * u.y)
+}
+
+/// 3D simplex noise, roughly in `[-1, 1]`.
+///
+/// Each corner of the simplex containing `p` contributes with the falloff
+/// `(0.5 - r^2)^4`, which vanishes before reaching the neighbouring
+/// simplices. The common `0.6` radius makes the noise discontinuous across
+/// simplex faces.
+#[tracked]
+pub fn simplex3(p: impl AsExpr<Value = Float3>) -> Expr<f32> {
+    let p = p.as_expr();
+    // skew to the cubic lattice to find the simplex, then unskew back
+    let i = (p + p.reduce_sum() * F3).floor();
+    let x0 = p - i + i.reduce_sum() * G3;
+    // offsets of the second and third corners, by the order of x0's lanes
+    let g = x0.step(x0.yzx());
+    let l = 1.0f32 - g;
+    let i1 = g.min(l.zxy());
+    let i2 = g.max(l.zxy());
+    let corner = |offset: Expr<Float3>, x: Expr<Float3>| {
+        let c = i + offset;
+        let h = lattice_hash(c.x, c.y, c.z);
+        let u = Float2::expr(hash_to_float(h), hash_to_float(pcg_hash(h)));
+        let (grad, _) = sample_uniform_sphere(u);
+        let m = (0.5f32 - x.dot(x)).max(0.0f32);
+        let m2 = m * m;
+        m2 * m2 * grad.dot(x)
+    };
+    let n = corner(Float3::splat_expr(0.0f32), x0)
+        + corner(i1, x0 - i1 + G3)
+        + corner(i2, x0 - i2 + 2.0f32 * G3)
+        + corner(Float3::splat_expr(1.0f32), x0 - 1.0f32 + 3.0f32 * G3);
+    104.0f32 * n
+}
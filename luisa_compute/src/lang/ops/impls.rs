@@ -1,3 +1,4 @@
+use crate::lang::autodiff::{detach, with_gradient_of};
 use crate::lang::types::{vector, ExprType, ValueType};
 
 use super::*;
@@ -84,7 +85,15 @@ impl_ops_trait!([X: Linear] RemEuclidExpr[RemEuclidThis] for Expr<X> where [X::S
 });
 
 impl_ops_trait!([X: Linear] ClampExpr[ClampThis] for Expr<X> where [X::Scalar: Numeric] {
-    fn clamp[_clamp](self, min, max) { Func::Clamp.call3(self, min, max) }
+    fn clamp[_clamp](self, min, max) {
+        with_gradient_of(Func::Clamp.call3(self, min, max), || {
+            // `min(max(x, lo), hi)` written as selects: the gradient flows
+            // to whichever operand is selected, so it passes through `x`
+            // inside `[lo, hi]` including its boundary and is zero outside
+            let lower: Expr<X> = Func::Select.call3(self._lt(min), min, self);
+            Func::Select.call3(lower._gt(max), max, lower)
+        })
+    }
 });
 
 impl<X: Linear> AbsExpr for Expr<X>
@@ -252,6 +261,11 @@ where
             .into(),
         )
     }
+    fn recip_fast(&self) -> Self {
+        // the division is lowered to the backend's approximate reciprocal
+        // under `MathMode::Fast`
+        self.recip()
+    }
     fn sin_cos(&self) -> (Self, Self) {
         (self.sin(), self.cos())
     }
@@ -366,15 +380,83 @@ impl_ops_trait!([X: Linear] FloatCopySignExpr[FloatCopySignThis] for Expr<X> whe
 });
 
 impl_ops_trait!([X: Linear] FloatStepExpr[FloatStepThis] for Expr<X> where [X::Scalar: Floating] {
-    fn step[_step](self, edge) { Func::Step.call2(edge, self) }
+    fn step[_step](self, edge) {
+        {
+            // a select of constants: the gradient is zero everywhere,
+            // including the jump at `edge`
+            let zero = float_const::<X>(0.0);
+            let one = float_const::<X>(1.0);
+            Func::Select.call3(self._lt(edge), zero, one)
+        }
+    }
 });
 
 impl_ops_trait!([X: Linear] FloatSmoothStepExpr[FloatSmoothStepThis] for Expr<X> where [X::Scalar: Floating] {
-    fn smooth_step[_smooth_step](self, edge0, edge1) { Func::SmoothStep.call3(edge0, edge1, self) }
+    fn smooth_step[_smooth_step](self, edge0, edge1) {
+        {
+            // the gradient is `6t(1 - t) / (edge1 - edge0)` between the
+            // edges and exactly zero beyond them
+            let t = self._linear_step(edge0, edge1);
+            let c2 = float_const::<X>(2.0);
+            let c3 = float_const::<X>(3.0);
+            track!(t * t * (c3 - c2 * t))
+        }
+    }
+});
+
+// Splats a float constant to `X`.
+fn float_const<X: Linear>(v: f32) -> Expr<X>
+where
+    X::Scalar: Floating,
+{
+    let s = v.expr().cast::<X::Scalar>();
+    if X::N == 1 {
+        Expr::<X>::from_node(s.node())
+    } else {
+        Func::Vec.call(s)
+    }
+}
+
+impl_ops_trait!([X: Linear] FloatLinearStepExpr[FloatLinearStepThis] for Expr<X> where [X::Scalar: Floating] {
+    fn linear_step[_linear_step](self, edge0, edge1) {
+        {
+            // selecting constants instead of clamping makes the gradient
+            // exactly zero at and beyond the edges
+            let zero = float_const::<X>(0.0);
+            let one = float_const::<X>(1.0);
+            let t = track!((self - edge0) / (edge1 - edge0));
+            let upper: Expr<X> = Func::Select.call3(t._ge(one), one, t);
+            Func::Select.call3(t._le(zero), zero, upper)
+        }
+    }
+});
+
+impl_ops_trait!([X: Linear] FloatSmootherStepExpr[FloatSmootherStepThis] for Expr<X> where [X::Scalar: Floating] {
+    fn smoother_step[_smoother_step](self, edge0, edge1) {
+        {
+            let t = self._linear_step(edge0, edge1);
+            let c6 = float_const::<X>(6.0);
+            let c15 = float_const::<X>(15.0);
+            let c10 = float_const::<X>(10.0);
+            track!(t * t * t * (t * (t * c6 - c15) + c10))
+        }
+    }
 });
 
 impl_ops_trait!([X: Linear] FloatArcTan2Expr[FloatArcTan2This] for Expr<X> where [X::Scalar: Floating] {
-    fn atan2[_atan2](self, other) { Func::Atan2.call2(self, other) }
+    fn atan2[_atan2](self, other) {
+        with_gradient_of(Func::Atan2.call2(self, other), || {
+            // the partials are `x / (x^2 + y^2)` for `y = self` and
+            // `-y / (x^2 + y^2)` for `x = other`, and zero at the origin,
+            // where the angle is undefined
+            let zero = float_const::<X>(0.0);
+            let r2 = track!(self * self + other * other);
+            let inv: Expr<X> = Func::Select.call3(r2._gt(zero), r2.recip(), zero);
+            let dy = detach(track!(other * inv));
+            let dx = detach(track!((zero - self) * inv));
+            track!(dy * self + dx * other)
+        })
+    }
 });
 
 impl_ops_trait!([X: Linear] FloatLogExpr[FloatLogThis] for Expr<X> where [X::Scalar: Floating] {
@@ -338,6 +338,26 @@ where
         Expr::<T>::_smooth_step(self, S::lift_self(edge0), S::lift_other(edge1))
     }
 }
+impl<T: Value, S, U> FloatLinearStepExpr<S, U> for Expr<T>
+where
+    S: SpreadOps<U, Join = T>,
+    Expr<T>: FloatLinearStepThis,
+{
+    type Output = Expr<T>;
+    fn linear_step(self, edge0: S, edge1: U) -> Self::Output {
+        Expr::<T>::_linear_step(self, S::lift_self(edge0), S::lift_other(edge1))
+    }
+}
+impl<T: Value, S, U> FloatSmootherStepExpr<S, U> for Expr<T>
+where
+    S: SpreadOps<U, Join = T>,
+    Expr<T>: FloatSmootherStepThis,
+{
+    type Output = Expr<T>;
+    fn smoother_step(self, edge0: S, edge1: U) -> Self::Output {
+        Expr::<T>::_smoother_step(self, S::lift_self(edge0), S::lift_other(edge1))
+    }
+}
 impl<T, S> FloatArcTan2Expr<S> for T
 where
     T: SpreadOps<S>,
@@ -252,6 +252,10 @@ pub trait FloatExpr: Sized {
     fn sqr(&self) -> Self;
     fn cube(&self) -> Self;
     fn recip(&self) -> Self;
+    /// Approximate `1 / x`. Under [`MathMode::Fast`](crate::runtime::MathMode),
+    /// the default, the backend emits its approximate reciprocal instead of a
+    /// full-precision division; otherwise this is [`recip`](Self::recip).
+    fn recip_fast(&self) -> Self;
     fn sin_cos(&self) -> (Self, Self);
     fn signum(&self) -> Self;
 }
@@ -322,6 +326,14 @@ ops_trait!(FloatSmoothStepExpr<T, S>[FloatSmoothStepThis] {
     fn smooth_step[_smooth_step](self, edge0: T, edge1: S);
 });
 
+ops_trait!(FloatLinearStepExpr<T, S>[FloatLinearStepThis] {
+    fn linear_step[_linear_step](self, edge0: T, edge1: S);
+});
+
+ops_trait!(FloatSmootherStepExpr<T, S>[FloatSmootherStepThis] {
+    fn smoother_step[_smoother_step](self, edge0: T, edge1: S);
+});
+
 ops_trait!(FloatArcTan2Expr<T>[FloatArcTan2This] {
     fn atan2[_atan2](self, other: T);
 });
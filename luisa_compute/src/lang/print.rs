@@ -58,3 +58,21 @@ macro_rules! device_log {
         fmt.print();
     }};
 }
+
+/// Like [`device_log!`], prefixing the message with the
+/// [`dispatch_id`](crate::lang::functions::dispatch_id) of the printing
+/// thread. The output is flushed by the backend when the stream
+/// synchronizes.
+#[macro_export]
+macro_rules! device_print {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let mut fmt = $crate::lang::print::DevicePrintFormatter::new();
+        fmt.push_str("[{}] ");
+        $crate::lang::print::DevicePrint::fmt(&$crate::lang::functions::dispatch_id(), &mut fmt);
+        fmt.push_str($fmt);
+        $(
+            $crate::lang::print::DevicePrint::fmt(&$arg, &mut fmt);
+        )*
+        fmt.print();
+    }};
+}
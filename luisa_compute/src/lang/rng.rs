@@ -0,0 +1,63 @@
+//! Counter-based random number generation.
+//!
+//! Every generator here is a pure function of its inputs. Pass the seed to a
+//! kernel as a uniform argument and the same compiled kernel gives a
+//! different, but reproducible, sequence for each `(seed, dispatch_id)`.
+use crate::internal_prelude::*;
+
+/// Tiny Encryption Algorithm based hash of `(v0, v1)`.
+#[tracked]
+pub fn tea(v0: impl AsExpr<Value = u32>, v1: impl AsExpr<Value = u32>) -> Expr<u32> {
+    let v0 = v0.as_expr().var();
+    let v1 = v1.as_expr().var();
+    let s0 = 0u32.var();
+    for_unrolled(0..4, |_| {
+        *s0 += 0x9e3779b9u32;
+        *v0 += ((v1 << 4u32) + 0xa341316cu32) ^ (v1 + s0) ^ ((v1 >> 5u32) + 0xc8013ea4u32);
+        *v1 += ((v0 << 4u32) + 0xad90777du32) ^ (v0 + s0) ^ ((v0 >> 5u32) + 0x7e95761eu32);
+    });
+    **v0
+}
+
+/// Initial random state of the current thread for a per-dispatch `seed`.
+#[tracked]
+pub fn dispatch_seed(seed: impl AsExpr<Value = u32>) -> Expr<u32> {
+    let id = dispatch_id();
+    let size = dispatch_size();
+    tea(id.x + size.x * (id.y + size.y * id.z), seed)
+}
+
+/// Advances `state` and returns a uniform float in `[0, 1)`.
+#[tracked]
+pub fn lcg(state: Var<u32>) -> Expr<f32> {
+    const LCG_A: u32 = 1664525u32;
+    const LCG_C: u32 = 1013904223u32;
+    *state = LCG_A * state + LCG_C;
+    (state & 0x00ffffffu32).as_f32() * (1.0f32 / 0x01000000u32 as f32)
+}
+
+/// Thomas Wang's 32-bit integer hash.
+#[tracked]
+pub fn wang_hash(v: impl AsExpr<Value = u32>) -> Expr<u32> {
+    let v = v.as_expr();
+    let v = (v ^ 61u32) ^ (v >> 16u32);
+    let v = v * 9u32;
+    let v = v ^ (v >> 4u32);
+    let v = v * 0x27d4eb2du32;
+    v ^ (v >> 15u32)
+}
+
+/// The PCG-RXS-M-XS hash from Jarzynski and Olano, "Hash Functions for GPU
+/// Rendering" (JCGT 2020).
+#[tracked]
+pub fn pcg_hash(v: impl AsExpr<Value = u32>) -> Expr<u32> {
+    let state = v.as_expr() * 747796405u32 + 2891336453u32;
+    let word = ((state >> ((state >> 28u32) + 4u32)) ^ state) * 277803737u32;
+    (word >> 22u32) ^ word
+}
+
+/// Maps a hash to a uniform float in `[0, 1)` using its upper 24 bits.
+#[tracked]
+pub fn hash_to_float(h: impl AsExpr<Value = u32>) -> Expr<f32> {
+    (h.as_expr() >> 8u32).as_f32() * (1.0f32 / 0x01000000u32 as f32)
+}
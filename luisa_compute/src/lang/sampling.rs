@@ -0,0 +1,47 @@
+//! Warps from uniform random numbers to common sampling domains.
+//!
+//! Every warp is a smooth function of `u`, so a sample can be differentiated
+//! with respect to the random numbers that produced it, e.g. for
+//! reparameterized gradients.
+use std::f32::consts::{FRAC_1_PI, PI, TAU};
+
+use crate::internal_prelude::*;
+
+const INV_4PI: f32 = 0.25 / PI;
+
+/// Maps `u` in `[0, 1)^2` to a point on the unit disk with polar coordinates
+/// `(sqrt(u.x), 2 pi u.y)`. Returns the point and its pdf `1 / pi` with
+/// respect to area.
+#[tracked]
+pub fn sample_uniform_disk(u: impl AsExpr<Value = Float2>) -> (Expr<Float2>, Expr<f32>) {
+    let u = u.as_expr();
+    let r = u.x.sqrt();
+    let phi = TAU * u.y;
+    (Float2::expr(r * phi.cos(), r * phi.sin()), FRAC_1_PI.expr())
+}
+
+/// Maps `u` in `[0, 1)^2` to a direction in the `+z` hemisphere distributed
+/// proportionally to `cos(theta)`, by projecting [`sample_uniform_disk`] up
+/// onto the hemisphere. Returns the direction and its pdf `cos(theta) / pi`
+/// with respect to solid angle.
+#[tracked]
+pub fn sample_cosine_hemisphere(u: impl AsExpr<Value = Float2>) -> (Expr<Float3>, Expr<f32>) {
+    let (p, _) = sample_uniform_disk(u);
+    let z = (1.0f32 - p.x * p.x - p.y * p.y).max(0.0f32).sqrt();
+    (Float3::expr(p.x, p.y, z), z * FRAC_1_PI)
+}
+
+/// Maps `u` in `[0, 1)^2` to a uniformly distributed direction on the unit
+/// sphere, with `z = 1 - 2 u.x` and azimuth `2 pi u.y`. Returns the direction
+/// and its pdf `1 / (4 pi)` with respect to solid angle.
+#[tracked]
+pub fn sample_uniform_sphere(u: impl AsExpr<Value = Float2>) -> (Expr<Float3>, Expr<f32>) {
+    let u = u.as_expr();
+    let z = 1.0f32 - 2.0f32 * u.x;
+    let r = (1.0f32 - z * z).max(0.0f32).sqrt();
+    let phi = TAU * u.y;
+    (
+        Float3::expr(r * phi.cos(), r * phi.sin(), z),
+        INV_4PI.expr(),
+    )
+}
@@ -0,0 +1,38 @@
+//! Signed distance functions for 2D vector graphics.
+//!
+//! All of them are differentiable with respect to the query point and the
+//! shape parameters.
+use crate::internal_prelude::*;
+
+/// Distance from `p` to the segment from `a` to `b`.
+///
+/// `p` is projected onto the segment and the projection clamped to its
+/// endpoints. Beyond an endpoint the gradient is that of the distance to the
+/// endpoint, so it is zero with respect to the other one. A degenerate
+/// segment gives the distance to `a`.
+#[tracked]
+pub fn sdf_segment(
+    p: impl AsExpr<Value = Float2>,
+    a: impl AsExpr<Value = Float2>,
+    b: impl AsExpr<Value = Float2>,
+) -> Expr<f32> {
+    let a = a.as_expr();
+    let pa = p.as_expr() - a;
+    let ba = b.as_expr() - a;
+    let h = (pa.dot(ba) / ba.dot(ba).max(1e-12f32)).clamp(0.0f32.expr(), 1.0f32.expr());
+    (pa - ba * h).length()
+}
+
+/// Signed distance from `p` to the infinite line through `a` and `b`,
+/// positive to the left of the direction from `a` to `b`.
+#[tracked]
+pub fn sdf_line(
+    p: impl AsExpr<Value = Float2>,
+    a: impl AsExpr<Value = Float2>,
+    b: impl AsExpr<Value = Float2>,
+) -> Expr<f32> {
+    let a = a.as_expr();
+    let pa = p.as_expr() - a;
+    let ba = b.as_expr() - a;
+    (ba.x * pa.y - ba.y * pa.x) / ba.length()
+}
@@ -0,0 +1,53 @@
+//! Differentiable statistics.
+use crate::internal_prelude::*;
+
+/// Gaussian weight `exp(-((value - center) / bandwidth)^2 / 2)` of `value` in
+/// the bin around `center`.
+#[tracked]
+pub fn soft_bin_weight(
+    value: impl AsExpr<Value = f32>,
+    center: impl AsExpr<Value = f32>,
+    bandwidth: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let d = (value.as_expr() - center.as_expr()) / bandwidth.as_expr();
+    (-0.5f32 * d * d).exp()
+}
+
+/// Atomically adds the [`soft_bin_weight`] of `value` in every bin of
+/// `bin_centers` to the matching element of `hist`.
+///
+/// Atomics are not differentiable, so gradients are propagated with
+/// [`soft_bin_backward`].
+#[tracked]
+pub fn soft_bin(
+    value: impl AsExpr<Value = f32>,
+    bin_centers: &BufferVar<f32>,
+    bandwidth: impl AsExpr<Value = f32>,
+    hist: &BufferVar<f32>,
+) {
+    let value = value.as_expr();
+    let bandwidth = bandwidth.as_expr();
+    for_range(0u32.expr()..bin_centers.len_expr_u32(), |k| {
+        hist.atomic_fetch_add(k, soft_bin_weight(value, bin_centers.read(k), bandwidth));
+    });
+}
+
+/// Backward pass of [`soft_bin`]: the gradient with respect to `value` of
+/// a loss whose gradient with respect to the histogram is `grad_hist`.
+#[tracked]
+pub fn soft_bin_backward(
+    value: impl AsExpr<Value = f32>,
+    bin_centers: &BufferVar<f32>,
+    bandwidth: impl AsExpr<Value = f32>,
+    grad_hist: &BufferVar<f32>,
+) -> Expr<f32> {
+    let value = value.as_expr();
+    let bandwidth = bandwidth.as_expr();
+    let grad = 0.0f32.var();
+    for_range(0u32.expr()..bin_centers.len_expr_u32(), |k| {
+        let center = bin_centers.read(k);
+        let w = soft_bin_weight(value, center, bandwidth);
+        *grad += grad_hist.read(k) * w * (center - value) / (bandwidth * bandwidth);
+    });
+    **grad
+}
@@ -56,6 +56,49 @@ pub trait SoaValue: Value {
     type SoaBuffer: SoaBufferProxy<Value = Self>;
 }
 
+/// Memory layout of a [`Value`] as the kernel sees it. See [`layout_of`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructLayout {
+    pub size: usize,
+    pub alignment: usize,
+    /// The fields in declaration order. Empty for non-struct types.
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub offset: usize,
+    pub size: usize,
+    pub alignment: usize,
+}
+
+/// Returns the layout of `T` computed from its IR type: each field is placed
+/// at the next offset that is a multiple of its alignment. Compare it with
+/// [`std::mem::offset_of!`] to catch a host struct whose layout differs from
+/// what kernels read and write.
+pub fn layout_of<T: Value>() -> StructLayout {
+    let ty = T::type_();
+    let mut fields = vec![];
+    if let Type::Struct(st) = ty.as_ref() {
+        let mut offset = 0;
+        for field in st.fields.as_ref() {
+            let alignment = field.alignment();
+            offset = (offset + alignment - 1) / alignment * alignment;
+            fields.push(FieldLayout {
+                offset,
+                size: field.size(),
+                alignment,
+            });
+            offset += field.size();
+        }
+    }
+    StructLayout {
+        size: ty.size(),
+        alignment: ty.alignment(),
+        fields,
+    }
+}
+
 /// A trait for implementing remote impls on top of an [`Expr`] using [`Deref`].
 ///
 /// For example, `Expr<[f32; 4]>` dereferences to `ArrayExpr<f32, 4>`, which
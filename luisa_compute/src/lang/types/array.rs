@@ -76,6 +76,14 @@ impl<T: Value, const N: usize> ArrayExpr<T, N> {
     }
 }
 
+impl<T: Value, const N: usize> ArrayVar<T, N> {
+    /// Returns the element at `i` as a [`Var`], so it can be updated in place.
+    /// `i` may be a dynamic index.
+    pub fn index<X: IntoIndex>(&self, i: X) -> Var<T> {
+        *<Self as Index<X>>::index(self, i)
+    }
+}
+
 impl<T: Value, const N: usize, X: IntoIndex> Index<X> for ArrayExpr<T, N> {
     type Output = Expr<T>;
     fn index(&self, i: X) -> &Self::Output {
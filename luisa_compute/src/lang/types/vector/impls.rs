@@ -333,7 +333,7 @@ impl SquareMatrix<4> {
     }
 }
 macro_rules! impl_mat_proxy {
-    ($M:ident, $V:ty, $N:literal: $($xs:ident),+) => {
+    ($M:ident, $V:ty, $N:literal: $($xs:ident),+; inverse = $inverse:path) => {
         impl $M {
             pub fn expr($($xs: impl AsExpr<Value = $V>),+) -> Expr<Self> {
                 Self::from_elems_expr([$($xs.as_expr()),+])
@@ -439,7 +439,7 @@ macro_rules! impl_mat_proxy {
                 Func::Determinant.call(*self)
             }
             fn inverse(&self) -> Self {
-                Func::Inverse.call(*self)
+                $inverse(*self)
             }
         }
         impl Expr<$M> {
@@ -512,6 +512,23 @@ macro_rules! impl_mat_proxy {
         }
     }
 }
-impl_mat_proxy!(Mat2, Vec2<f32>,2: x, y);
-impl_mat_proxy!(Mat3, Vec3<f32>,3: x, y, z);
-impl_mat_proxy!(Mat4, Vec4<f32>,4: x, y, z, w);
+impl_mat_proxy!(Mat2, Vec2<f32>,2: x, y; inverse = builtin_inverse);
+impl_mat_proxy!(Mat3, Vec3<f32>,3: x, y, z; inverse = mat3_inverse);
+impl_mat_proxy!(Mat4, Vec4<f32>,4: x, y, z, w; inverse = builtin_inverse);
+
+fn builtin_inverse<M: Value>(m: Expr<M>) -> Expr<M> {
+    Func::Inverse.call(m)
+}
+
+/// The adjugate over the determinant. Unlike the `Inverse` builtin this is
+/// made of differentiable operations, giving the gradient `-A^-T G A^-T` for
+/// an upstream gradient `G`. A singular matrix gives infinities or NaNs.
+#[tracked]
+fn mat3_inverse(m: Expr<Mat3>) -> Expr<Mat3> {
+    let (a, b, c) = (m.col(0), m.col(1), m.col(2));
+    // the rows of the inverse, scaled by the determinant
+    let r0 = b.cross(c);
+    let r1 = c.cross(a);
+    let r2 = a.cross(b);
+    Mat3::expr(r0, r1, r2).transpose() / a.dot(r0)
+}
@@ -5,6 +5,7 @@ extern crate self as luisa_compute;
 use std::any::Any;
 use std::backtrace::Backtrace;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 pub mod lang;
@@ -20,19 +21,22 @@ pub mod prelude {
     pub use half::f16;
 
     pub use crate::lang::control_flow::{
-        break_, continue_, for_range, for_unrolled, return_, return_v, select, switch,
+        accumulate_if, break_, continue_, for_range, for_unrolled, return_, return_v, select,
+        switch,
+    };
+    pub use crate::lang::functions::{
+        block_size, dispatch_id, dispatch_size, set_block_size, shared, sync_block,
     };
-    pub use crate::lang::functions::{block_size, dispatch_id, dispatch_size, set_block_size};
     pub use crate::lang::index::{IndexRead, IndexWrite};
     pub use crate::lang::ops::{
         AbsExpr, ActivateMaybeExpr, AddAssignExpr, AddExpr, ArrayNewExpr, BitAndAssignExpr,
         BitAndExpr, BitOrAssignExpr, BitOrExpr, BitXorAssignExpr, BitXorExpr, ClampExpr, CmpExpr,
         CrossExpr, DivAssignExpr, DivExpr, DotExpr, EqExpr, FloatArcTan2Expr, FloatCopySignExpr,
-        FloatExpr, FloatLerpExpr, FloatLogExpr, FloatMulAddExpr, FloatPowfExpr, FloatPowiExpr,
-        FloatSmoothStepExpr, FloatStepExpr, IntExpr, LazyBoolMaybeExpr, LoopMaybeExpr, MatExpr,
-        MinMaxExpr, MulAssignExpr, MulExpr, NormExpr, OuterProductExpr, ReduceExpr, RemAssignExpr,
-        RemEuclidExpr, RemExpr, SelectMaybeExpr, ShlAssignExpr, ShlExpr, ShrAssignExpr, ShrExpr,
-        SubAssignExpr, SubExpr,
+        FloatExpr, FloatLerpExpr, FloatLinearStepExpr, FloatLogExpr, FloatMulAddExpr,
+        FloatPowfExpr, FloatPowiExpr, FloatSmoothStepExpr, FloatSmootherStepExpr, FloatStepExpr,
+        IntExpr, LazyBoolMaybeExpr, LoopMaybeExpr, MatExpr, MinMaxExpr, MulAssignExpr, MulExpr,
+        NormExpr, OuterProductExpr, ReduceExpr, RemAssignExpr, RemEuclidExpr, RemExpr,
+        SelectMaybeExpr, ShlAssignExpr, ShlExpr, ShrAssignExpr, ShrExpr, SubAssignExpr, SubExpr,
     };
     pub use crate::lang::types::vector::swizzle::*;
     pub use crate::lang::types::vector::VectorExprProxy;
@@ -41,12 +45,13 @@ pub mod prelude {
     pub use crate::resource::{IoTexel, StorageTexel, *};
     pub use crate::runtime::api::StreamTag;
     pub use crate::runtime::{
-        Callable, Command, Device, DynCallable, Kernel, KernelBuildOptions, KernelDef, Scope,
-        Stream, Swapchain,
+        Callable, Command, CommandBuffer, CompileState, Device, DynCallable, Kernel,
+        KernelBuildOptions, KernelDef, MathMode, OptLevel, RecordedCommands, Scope, Stream,
+        Swapchain,
     };
     pub use crate::{
-        cpu_dbg, device_log, if_, lc_assert, lc_comment_lineno, lc_unreachable, loop_, while_,
-        Context,
+        break_, continue_, cpu_dbg, device_assert, device_log, device_print, if_, lc_assert,
+        lc_comment_lineno, lc_unreachable, loop_, while_, Context,
     };
 
     pub use luisa_compute_derive::*;
@@ -90,13 +95,15 @@ pub use {luisa_compute_backend as backend, luisa_compute_sys as sys};
 
 use lazy_static::lazy_static;
 use luisa_compute_backend::Backend;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use runtime::{Device, DeviceHandle, StreamHandle};
 use std::collections::HashMap;
 use std::sync::Weak;
 
 pub struct Context {
     inner: Arc<backend::Context>,
+    /// See [`Context::preload_cache`].
+    preloaded: Arc<runtime::PreloadedKernels>,
 }
 
 pub fn init_logger() {
@@ -110,9 +117,9 @@ pub fn init_logger_verbose() {
         .format_timestamp_secs()
         .init();
 }
+type CachedContext = (Weak<backend::Context>, Arc<runtime::PreloadedKernels>);
 lazy_static! {
-    static ref CTX_CACHE: Mutex<HashMap<String, Weak<backend::Context>>> =
-        Mutex::new(HashMap::new());
+    static ref CTX_CACHE: Mutex<HashMap<String, CachedContext>> = Mutex::new(HashMap::new());
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum DeviceType {
@@ -173,18 +180,25 @@ impl Context {
         if lib_path.is_file() {
             lib_path = lib_path.parent().unwrap().to_path_buf();
         }
-        let inner = {
+        let (inner, preloaded) = {
             let mut cache = CTX_CACHE.lock();
-            if let Some(ctx) = cache.get(lib_path.to_str().unwrap()) {
+            if let Some((ctx, preloaded)) = cache.get(lib_path.to_str().unwrap()) {
                 if let Some(ctx) = ctx.upgrade() {
-                    return Self { inner: ctx.clone() };
+                    return Self {
+                        inner: ctx,
+                        preloaded: preloaded.clone(),
+                    };
                 }
             }
             let ctx = Arc::new(backend::Context::new(lib_path.clone()));
-            cache.insert(lib_path.to_str().unwrap().to_string(), Arc::downgrade(&ctx));
-            ctx
+            let preloaded = Arc::new(runtime::PreloadedKernels::default());
+            cache.insert(
+                lib_path.to_str().unwrap().to_string(),
+                (Arc::downgrade(&ctx), preloaded.clone()),
+            );
+            (ctx, preloaded)
         };
-        Self { inner }
+        Self { inner, preloaded }
     }
     #[inline]
     pub fn create_cpu_device(&self) -> Device {
@@ -206,7 +220,7 @@ impl Context {
     ) -> Device {
         let backend = self.inner.create_device(&device.into_device_name(), config);
         let default_stream = backend.create_stream(api::StreamTag::Graphics);
-        Device {
+        let device = Device {
             inner: Arc::new_cyclic(|weak| DeviceHandle {
                 backend,
                 default_stream: Some(Arc::new(StreamHandle::Default {
@@ -215,8 +229,16 @@ impl Context {
                     device: weak.clone(),
                 })),
                 ctx: self.inner.clone(),
+                compile_progress: RwLock::new(None),
+                cache_enabled: AtomicBool::new(true),
+                cache_dir: RwLock::new(None),
+                streams: Mutex::new(Vec::new()),
+                shared_kernels: Mutex::new(Default::default()),
+                shared_callables: Mutex::new(HashMap::new()),
             }),
-        }
+        };
+        self.preloaded.add_device(&device);
+        device
     }
 }
 
@@ -267,6 +289,29 @@ impl ResourceTracker {
             weak_refs: vec![],
         }
     }
+    /// The same resources, without keeping them alive.
+    pub(crate) fn downgrade(&self) -> Self {
+        let mut weak_refs = self.weak_refs.clone();
+        weak_refs.extend(self.strong_refs.iter().map(Arc::downgrade));
+        Self {
+            strong_refs: vec![],
+            weak_refs,
+        }
+    }
+    /// Like [`ResourceTracker::upgrade`], but returns `None` if a resource
+    /// was dropped.
+    pub(crate) fn try_upgrade(&self) -> Option<Self> {
+        let mut strong_refs = self
+            .weak_refs
+            .iter()
+            .map(Weak::upgrade)
+            .collect::<Option<Vec<_>>>()?;
+        strong_refs.extend(self.strong_refs.iter().cloned());
+        Some(Self {
+            strong_refs,
+            weak_refs: vec![],
+        })
+    }
 }
 
 unsafe impl Send for ResourceTracker {}
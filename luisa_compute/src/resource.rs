@@ -17,6 +17,9 @@ use crate::runtime::*;
 use api::{BufferDownloadCommand, BufferUploadCommand, INVALID_RESOURCE_HANDLE};
 use std::ffi::c_void;
 
+#[cfg(feature = "image")]
+mod image;
+
 pub type ByteBuffer = Buffer<u8>;
 pub type ByteBufferView = BufferView<u8>;
 pub type ByteBufferVar = BufferVar<u8>;
@@ -317,6 +320,9 @@ impl<T: Value + fmt::Debug> fmt::Debug for Buffer<T> {
     }
 }
 
+/// Dropping the handle destroys `handle`. For buffers over external memory,
+/// e.g. from [`Device::import_buffer`], this releases only the device's
+/// resource: the memory behind `native_handle` is never freed by the crate.
 pub(crate) struct BufferHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Buffer,
@@ -381,6 +387,10 @@ impl_resource_deref_to_var!(Tex2d, Tex2dVar [T: IoTexel]);
 impl_resource_deref_to_var!(Tex3d, Tex3dVar [T: IoTexel]);
 impl_resource_deref_to_var!(BindlessArray, BindlessArrayVar);
 
+/// Number of 32-bit words, or partial hashes, each thread of
+/// [`BufferView::hash_command`] folds into one hash.
+pub const HASH_CHUNK: u32 = 256;
+
 impl<T: Value> BufferView<T> {
     /// reinterpret the buffer as a different type
     /// must satisfy `std::mem::size_of::<T>() * self.len() % std::mem::size_of::<U>() == 0`
@@ -473,14 +483,53 @@ impl<T: Value> BufferView<T> {
     pub fn copy_from(&self, data: &[T]) {
         submit_default_stream_and_sync(&self.device, [self.copy_from_async(data)]);
     }
+    /// Like [`BufferView::view_at`], but panics if the elements do not fit.
+    fn sub_view(&self, offset: usize, len: usize) -> BufferView<T> {
+        self.view_at(offset, len)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+    /// Uploads `data` to the elements starting at `offset`.
+    pub fn copy_from_at(&self, offset: usize, data: &[T]) {
+        self.sub_view(offset, data.len()).copy_from(data);
+    }
+    /// Downloads the elements starting at `offset` into `data`.
+    pub fn copy_to_at(&self, offset: usize, data: &mut [T]) {
+        self.sub_view(offset, data.len()).copy_to(data);
+    }
+    /// Views the elements as a row-major `width x height` image, indexed by
+    /// `(x, y)` instead of `y * width + x`.
+    pub fn as_image2d(&self, width: u32, height: u32) -> BufferImage2d<T> {
+        assert_eq!(
+            width as usize * height as usize,
+            self.len,
+            "a {}x{} image does not match a view of {} elements",
+            width,
+            height,
+            self.len
+        );
+        BufferImage2d {
+            view: self.clone(),
+            width,
+            height,
+        }
+    }
     pub fn fill_fn<F: FnMut(usize) -> T>(&self, f: F) {
         self.copy_from(&(0..self.len).map(f).collect::<Vec<_>>());
     }
     pub fn fill(&self, value: T) {
         self.fill_fn(|_| value);
     }
+    /// Copies `self` into `dst` on device, without going through the host.
+    /// Accepts whole buffers too, e.g. `src.copy_to_buffer_async(&dst)`.
     pub fn copy_to_buffer_async(&self, dst: &BufferView<T>) -> Command<'static, 'static> {
-        assert_eq!(self.len, dst.len);
+        assert!(
+            self.device == dst.device,
+            "Cannot copy between buffers on different devices"
+        );
+        assert_eq!(
+            self.len, dst.len,
+            "Source and destination views have different lengths"
+        );
         let mut rt = ResourceTracker::new();
         rt.add(self._handle());
         rt.add(dst._handle());
@@ -500,6 +549,104 @@ impl<T: Value> BufferView<T> {
     pub fn copy_to_buffer(&self, dst: &BufferView<T>) {
         submit_default_stream_and_sync(&self.device, [self.copy_to_buffer_async(dst)]);
     }
+    /// Copies `src` into `self` on device, without going through the host.
+    pub fn copy_from_view_async(&self, src: &BufferView<T>) -> Command<'static, 'static> {
+        src.copy_to_buffer_async(self)
+    }
+    pub fn copy_from_view(&self, src: &BufferView<T>) {
+        submit_default_stream_and_sync(&self.device, [self.copy_from_view_async(src)]);
+    }
+    /// Returns the commands hashing the contents of this view on the device,
+    /// and the one-element buffer the 64-bit hash is written to.
+    ///
+    /// The view is split into chunks of [`HASH_CHUNK`] 32-bit words, each
+    /// hashed with FNV-1a over its bytes. The chunk hashes are combined the
+    /// same way, chunk by chunk, until one is left. The hash thus depends on
+    /// the order of the bytes, but is not the FNV-1a of the whole view.
+    pub fn hash_command(&self) -> (Vec<Command<'static, 'static>>, Buffer<u64>) {
+        let offset_bytes = self.offset * std::mem::size_of::<T>();
+        let size_bytes = self.size_bytes();
+        assert!(
+            offset_bytes % 4 == 0 && size_bytes % 4 == 0,
+            "hash_command requires a view that is 4-byte aligned and sized"
+        );
+        let device = &self.device;
+        let hash = device.create_buffer_from_slice(&[FNV_OFFSET_BASIS]);
+        let words = (size_bytes / 4) as u32;
+        if words == 0 {
+            return (vec![], hash);
+        }
+        let bytes = BufferView::<u8> {
+            device: device.clone(),
+            handle: self.handle.clone(),
+            offset: offset_bytes,
+            len: size_bytes,
+            total_size_bytes: self.total_size_bytes,
+            _marker: PhantomData,
+        };
+        let hash_words = device.shared_kernel("hash_command/words".to_string(), || {
+            Kernel::<fn(Buffer<u8>, u32, Buffer<u64>)>::new(
+                device,
+                &track!(|bytes, words, out| {
+                    let chunk = dispatch_id().x;
+                    let h = FNV_OFFSET_BASIS.var();
+                    let end = (chunk * HASH_CHUNK + HASH_CHUNK).min_(words);
+                    for i in chunk * HASH_CHUNK..end {
+                        let word = bytes.read_as::<u32>(i * 4u32);
+                        for b in 0u32..4u32 {
+                            *h = (**h ^ ((word >> (b * 8u32)) & 0xffu32).as_u64()) * FNV_PRIME;
+                        }
+                    }
+                    out.write(chunk, **h);
+                }),
+            )
+        });
+        let hash_hashes = device.shared_kernel("hash_command/hashes".to_string(), || {
+            Kernel::<fn(Buffer<u64>, u32, Buffer<u64>)>::new(
+                device,
+                &track!(|hashes, count, out| {
+                    let chunk = dispatch_id().x;
+                    let h = FNV_OFFSET_BASIS.var();
+                    let end = (chunk * HASH_CHUNK + HASH_CHUNK).min_(count);
+                    for i in chunk * HASH_CHUNK..end {
+                        let x = hashes.read(i);
+                        for b in 0u64..8u64 {
+                            *h = (**h ^ ((x >> (b * 8u64)) & 0xffu64)) * FNV_PRIME;
+                        }
+                    }
+                    out.write(chunk, **h);
+                }),
+            )
+        });
+        let chunks = |n: u32| (n + HASH_CHUNK - 1) / HASH_CHUNK;
+        let scratch = |n: u32| {
+            if n == 1 {
+                Buffer {
+                    handle: hash.handle.clone(),
+                    full_view: hash.full_view.clone(),
+                }
+            } else {
+                device.create_buffer::<u64>(n as usize)
+            }
+        };
+        let mut count = chunks(words);
+        let mut hashes = scratch(count);
+        let mut command = hash_words.dispatch_async([count, 1, 1], &bytes, &words, &hashes);
+        // kernel arguments are not tracked by the command
+        command.resource_tracker.add(hashes.handle.clone());
+        let mut commands = vec![command];
+        while count > 1 {
+            let next = scratch(chunks(count));
+            let mut command =
+                hash_hashes.dispatch_async([chunks(count), 1, 1], &hashes, &count, &next);
+            command.resource_tracker.add(hashes.handle.clone());
+            command.resource_tracker.add(next.handle.clone());
+            commands.push(command);
+            count = chunks(count);
+            hashes = next;
+        }
+        (commands, hash)
+    }
     pub fn view<S: RangeBounds<usize>>(&self, range: S) -> BufferView<T> {
         let lower = range.start_bound();
         let upper = range.end_bound();
@@ -518,12 +665,24 @@ impl<T: Value> BufferView<T> {
         BufferView {
             device: self.device.clone(),
             handle: self.handle.clone(),
-            offset: lower,
+            offset: self.offset + lower,
             len: upper - lower,
             total_size_bytes: self.total_size_bytes,
             _marker: PhantomData,
         }
     }
+    /// The `len` elements starting at element `offset` of this view, or an
+    /// error if they do not all lie inside it.
+    pub fn view_at(&self, offset: usize, len: usize) -> Result<BufferView<T>, ResourceError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(self.view(offset..end)),
+            _ => Err(ResourceError::ViewOutOfBounds {
+                offset,
+                len,
+                view_len: self.len,
+            }),
+        }
+    }
 }
 
 pub(crate) struct BindlessArrayHandle {
@@ -812,7 +971,6 @@ pub(crate) struct TextureHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Texture,
     pub(crate) native_handle: *mut std::ffi::c_void,
-    #[allow(dead_code)]
     pub(crate) format: PixelFormat,
     pub(crate) storage: PixelStorage,
     pub(crate) width: u32,
@@ -1050,6 +1208,56 @@ impl<T: IoTexel> Tex2d<T> {
     }
 }
 
+impl<T: IoTexel<RwType = Float4>> Tex2d<T> {
+    /// Fills every mip level after the first on `stream`, box filtering
+    /// each level down from the previous one.
+    ///
+    /// Returns without waiting for the device: commands submitted to
+    /// `stream` afterwards, including reads of the texture, run after the
+    /// mip levels are filled.
+    ///
+    /// A texel averages the texels of the previous level it covers; along an
+    /// odd dimension, the footprints of neighboring texels overlap by one.
+    pub fn generate_mipmaps(&self, stream: &Stream) {
+        let levels = self.handle.levels;
+        if levels <= 1 {
+            return;
+        }
+        let device = &self.handle.device;
+        let key = format!("generate_mipmaps/{:?}", self.storage());
+        let kernel = device.shared_kernel(key, || {
+            Kernel::<fn(Tex2d<Float4>, Tex2d<Float4>)>::new(
+                device,
+                &track!(|src, dst| {
+                    let p = dispatch_id().xy();
+                    let (src_size, dst_size) = (src.size(), dst.size());
+                    let lo = p * src_size / dst_size;
+                    let hi = ((p + 1u32) * src_size + dst_size - 1u32) / dst_size;
+                    let sum = Float4::var_zeroed();
+                    for y in lo.y..hi.y {
+                        for x in lo.x..hi.x {
+                            *sum += src.read(Uint2::expr(x, y));
+                        }
+                    }
+                    let n = (hi - lo).as_float2();
+                    dst.write(p, **sum / (n.x * n.y));
+                }),
+            )
+        });
+        // the commands keep the texture alive until they complete, so the
+        // scope need not synchronize
+        let scope: Scope<'static> = stream.scope();
+        scope.submit((1..levels).map(|level| {
+            let (src, dst) = (self.view(level - 1), self.view(level));
+            let [w, h, _] = dst.size();
+            let mut command = kernel.dispatch_async([w, h, 1], &src.as_float4(), &dst.as_float4());
+            command.resource_tracker.add(self.handle.clone());
+            command
+        }));
+        scope.detach();
+    }
+}
+
 impl<T: IoTexel + fmt::Debug> fmt::Debug for Tex2d<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -1327,6 +1535,22 @@ impl<T: IoTexel> Tex2dView<T> {
     }
 }
 impl_tex_view!(Tex2dView);
+impl<T: IoTexel<RwType = Float4>> Tex2dView<T> {
+    /// The same view read as [`Float4`], e.g. to share a kernel between
+    /// float textures of any channel count.
+    pub(crate) fn as_float4(&self) -> Tex2dView<Float4> {
+        Tex2dView {
+            device: self.device.clone(),
+            width: self.width,
+            height: self.height,
+            storage: self.storage,
+            format: self.format,
+            handle: self.handle.clone(),
+            level: self.level,
+            marker: PhantomData,
+        }
+    }
+}
 impl<T: IoTexel> Tex3dView<T> {
     pub fn handle(&self) -> api::Texture {
         self._handle().handle
@@ -1895,6 +2119,16 @@ impl BindlessArrayVar {
         }
         v
     }
+    /// Reads element `index` of the buffer in slot `buffer_index`, which
+    /// must hold a buffer of `T`. Shorthand for
+    /// `self.buffer::<T>(buffer_index).read(index)`.
+    pub fn buffer_read<T: Value>(
+        &self,
+        buffer_index: impl AsExpr<Value = u32>,
+        index: impl IntoIndex,
+    ) -> Expr<T> {
+        self.buffer::<T>(buffer_index).read(index)
+    }
 
     pub fn new(array: &BindlessArray) -> Self {
         let node = with_recorder(|r| {
@@ -1924,6 +2158,104 @@ impl<T: Value> std::ops::Deref for Buffer<T> {
         &self.full_view
     }
 }
+/// A buffer accessible from both host and device without copies.
+/// See [`Device::create_unified_buffer`].
+pub struct UnifiedBuffer<T: Value> {
+    pub(crate) buffer: Buffer<T>,
+}
+impl<T: Value> UnifiedBuffer<T> {
+    /// Waits for every stream of the device, then calls `f` with the
+    /// contents.
+    ///
+    /// The buffer stays mutably borrowed while `f` runs, so it cannot be
+    /// passed to a kernel until `f` returns. Kernels that captured it must
+    /// not be dispatched from `f`.
+    pub fn with_slice<R>(&mut self, f: impl FnOnce(&mut [T]) -> R) -> R {
+        self.buffer.device.synchronize();
+        let slice = unsafe {
+            std::slice::from_raw_parts_mut(self.buffer.native_handle() as *mut T, self.buffer.len())
+        };
+        f(slice)
+    }
+    pub fn buffer(&self) -> &Buffer<T> {
+        &self.buffer
+    }
+}
+impl<T: Value> std::ops::Deref for UnifiedBuffer<T> {
+    type Target = Buffer<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+/// A buffer view holding a row-major image. See [`BufferView::as_image2d`].
+#[derive(Clone)]
+pub struct BufferImage2d<T: Value> {
+    view: BufferView<T>,
+    width: u32,
+    height: u32,
+}
+impl<T: Value> BufferImage2d<T> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn view(&self) -> &BufferView<T> {
+        &self.view
+    }
+    /// Downloads the image, row by row.
+    pub fn copy_to_vec(&self) -> Vec<T> {
+        self.view.copy_to_vec()
+    }
+    /// Uploads the image, row by row.
+    pub fn copy_from(&self, data: &[T]) {
+        self.view.copy_from(data)
+    }
+    #[inline]
+    pub fn var(&self) -> BufferImage2dVar<T> {
+        BufferImage2dVar {
+            buffer: self.view.var(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+/// In-kernel access to a [`BufferImage2d`] by `(x, y)` coordinates.
+///
+/// Coordinates are checked to lie in the image when runtime checks are
+/// enabled, like buffer indices.
+pub struct BufferImage2dVar<T: Value> {
+    buffer: BufferVar<T>,
+    width: u32,
+    height: u32,
+}
+impl<T: Value> BufferImage2dVar<T> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    fn index(&self, x: Expr<u32>, y: Expr<u32>) -> Expr<u64> {
+        let (width, height) = (self.width, self.height);
+        if need_runtime_check() {
+            lc_assert!(track!(x < width && y < height));
+        }
+        track!(y.as_u64() * width as u64 + x.as_u64())
+    }
+    pub fn read(&self, x: impl AsExpr<Value = u32>, y: impl AsExpr<Value = u32>) -> Expr<T> {
+        self.buffer.read(self.index(x.as_expr(), y.as_expr()))
+    }
+    pub fn write(
+        &self,
+        x: impl AsExpr<Value = u32>,
+        y: impl AsExpr<Value = u32>,
+        v: impl AsExpr<Value = T>,
+    ) {
+        self.buffer.write(self.index(x.as_expr(), y.as_expr()), v)
+    }
+}
 impl<T: Value> ToNode for Buffer<T> {
     fn node(&self) -> SafeNodeRef {
         self.var().node()
@@ -1972,9 +2304,10 @@ impl<T: Value> IndexWrite for BufferVar<T> {
         if need_runtime_check() {
             lc_assert!(i.lt(self.len_expr()));
         }
-        let i = i.node().get();
+        let index = i.node().get();
         let self_node = self.node.get();
-        __current_scope(|b| b.call(Func::BufferWrite, &[self_node, i, v], Type::void()));
+        __current_scope(|b| b.call(Func::BufferWrite, &[self_node, index, v], Type::void()));
+        check_buffer_write(self_node, i);
     }
 }
 impl<T: Value> BufferVar<T> {
@@ -1991,9 +2324,13 @@ impl<T: Value> BufferVar<T> {
                     b, a
                 );
             }
-            r.capture_or_get(binding, &buffer.handle, || {
+            let node = r.capture_or_get(binding, &buffer.handle, || {
                 Node::new(CArc::new(Instruction::Buffer), T::type_())
-            })
+            });
+            if let Some(check) = &mut r.race_check {
+                check.register(node, &buffer.device, buffer.len);
+            }
+            node
         })
         .into();
         Self {
@@ -2028,6 +2365,30 @@ impl<T: Value> BufferVar<T> {
     }
 }
 
+/// Element types that support atomic addition in buffers.
+pub trait AtomicAdd: Value {
+    fn buffer_atomic_add(buffer: &BufferVar<Self>, index: Expr<u32>, value: Expr<Self>);
+}
+
+impl<T: AtomicAdd> BufferVar<T> {
+    /// Reads element `i` as an input of the current [`autodiff`] section.
+    /// After [`backward`], its gradient is atomically added to element `i`
+    /// of `grad`, so threads reading the same element accumulate theirs.
+    ///
+    /// [`autodiff`]: crate::lang::autodiff::autodiff
+    /// [`backward`]: crate::lang::autodiff::backward
+    pub fn read_grad(&self, i: impl AsExpr<Value = u32>, grad: &BufferVar<T>) -> Expr<T> {
+        let i = i.as_expr();
+        let v = self.read(i);
+        crate::lang::autodiff::requires_grad(v);
+        let grad = grad.clone();
+        crate::lang::autodiff::after_backward(move || {
+            T::buffer_atomic_add(&grad, i, crate::lang::autodiff::gradient(v));
+        });
+        v
+    }
+}
+
 macro_rules! impl_atomic {
     ($t:ty) => {
         impl BufferVar<$t> {
@@ -2163,6 +2524,11 @@ macro_rules! impl_atomic {
                 )
             }
         }
+        impl AtomicAdd for $t {
+            fn buffer_atomic_add(buffer: &BufferVar<Self>, index: Expr<u32>, value: Expr<Self>) {
+                buffer.atomic_fetch_add(index, value);
+            }
+        }
     };
 }
 macro_rules! impl_atomic_bit {
@@ -2277,7 +2643,11 @@ impl<T: IoTexel> Tex2dVar<T> {
         }
     }
     pub fn read(&self, uv: impl AsExpr<Value = Uint2>) -> Expr<T> {
-        let uv = uv.as_expr().node().get();
+        let uv = uv.as_expr();
+        if need_runtime_check() {
+            check_texel_coord(uv.lt(self.size()).all(), uv);
+        }
+        let uv = uv.node().get();
         let self_node = self.node.get();
         T::convert_from_read(Expr::<T::RwType>::from_node(
             __current_scope(|b| b.call(Func::Texture2dRead, &[self_node, uv], T::RwType::type_()))
@@ -2285,7 +2655,11 @@ impl<T: IoTexel> Tex2dVar<T> {
         ))
     }
     pub fn write(&self, uv: impl AsExpr<Value = Uint2>, v: impl AsExpr<Value = T>) {
-        let uv = uv.as_expr().node().get();
+        let uv = uv.as_expr();
+        if need_runtime_check() {
+            check_texel_coord(uv.lt(self.size()).all(), uv);
+        }
+        let uv = uv.node().get();
         let v = v.as_expr();
         let v = T::convert_to_write(v).node().get();
         let self_node = self.node.get();
@@ -2301,6 +2675,94 @@ impl<T: IoTexel> Tex2dVar<T> {
     }
 }
 
+/// Traps when a texel coordinate lies outside the texture, logging the
+/// coordinate and the dispatch id of the offending thread.
+#[tracked]
+fn check_texel_coord<V: Value>(in_bounds: Expr<bool>, coord: Expr<V>) {
+    if !in_bounds {
+        device_log!(
+            "texel coordinate {} out of bounds, dispatch_id = {}",
+            coord,
+            dispatch_id()
+        );
+    }
+    lc_assert!(in_bounds);
+}
+
+/// How [`Tex2dVar::sample`] maps texel coordinates outside the texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    /// Repeats the edge texels.
+    Clamp,
+    /// Tiles the texture.
+    Wrap,
+}
+
+impl Tex2dVar<Float4> {
+    /// The four texels around `uv` and their bilinear weights.
+    #[tracked]
+    fn bilinear_taps(&self, uv: Expr<Float2>, mode: AddressMode) -> [(Expr<Uint2>, Expr<f32>); 4] {
+        let size = self.size().as_int2();
+        let p = uv * size.as_float2() - 0.5f32;
+        let p0 = p.floor();
+        let t = p - p0;
+        let i0 = p0.as_int2();
+        let tap = |dx: i32, dy: i32, w: Expr<f32>| {
+            let c = i0 + Int2::expr(dx, dy);
+            let c = match mode {
+                AddressMode::Clamp => c.clamp(Int2::splat_expr(0i32), size - 1i32),
+                AddressMode::Wrap => c.rem_euclid(size),
+            };
+            (c.as_uint2(), w)
+        };
+        [
+            tap(0, 0, (1.0f32 - t.x) * (1.0f32 - t.y)),
+            tap(1, 0, t.x * (1.0f32 - t.y)),
+            tap(0, 1, (1.0f32 - t.x) * t.y),
+            tap(1, 1, t.x * t.y),
+        ]
+    }
+    /// Bilinearly filtered value at `uv`, where texel `(x, y)` is centered
+    /// at `(x + 0.5, y + 0.5) / size`. Differentiable with respect to `uv`.
+    pub fn sample(&self, uv: impl AsExpr<Value = Float2>, mode: AddressMode) -> Expr<Float4> {
+        let mut v = Float4::splat_expr(0.0f32);
+        for (c, w) in self.bilinear_taps(uv.as_expr(), mode) {
+            v = track!(v + self.read(c) * w);
+        }
+        v
+    }
+    /// [`Tex2dVar::sample`] whose texels are differentiable: inside
+    /// [`autodiff`], after [`backward`], the gradient of texel `(x, y)` is
+    /// atomically added to elements `(y * width + x) * 4..` of `grad`.
+    ///
+    /// [`autodiff`]: crate::lang::autodiff::autodiff
+    /// [`backward`]: crate::lang::autodiff::backward
+    pub fn sample_with_grad(
+        &self,
+        uv: impl AsExpr<Value = Float2>,
+        mode: AddressMode,
+        grad: &BufferVar<f32>,
+    ) -> Expr<Float4> {
+        let width = self.size().x;
+        let mut v = Float4::splat_expr(0.0f32);
+        for (c, w) in self.bilinear_taps(uv.as_expr(), mode) {
+            let texel = self.read(c);
+            crate::lang::autodiff::requires_grad(texel);
+            let grad = grad.clone();
+            crate::lang::autodiff::after_backward(move || {
+                let g = crate::lang::autodiff::gradient(texel);
+                let i = track!((c.y * width + c.x) * 4u32);
+                grad.atomic_fetch_add(i, g.x);
+                grad.atomic_fetch_add(track!(i + 1u32), g.y);
+                grad.atomic_fetch_add(track!(i + 2u32), g.z);
+                grad.atomic_fetch_add(track!(i + 3u32), g.w);
+            });
+            v = track!(v + texel * w);
+        }
+        v
+    }
+}
+
 impl<T: IoTexel> Tex3dVar<T> {
     pub fn new(view: Tex3dView<T>) -> Self {
         let node = with_recorder(|r| {
@@ -2328,7 +2790,11 @@ impl<T: IoTexel> Tex3dVar<T> {
         }
     }
     pub fn read(&self, uv: impl AsExpr<Value = Uint3>) -> Expr<T> {
-        let uv = uv.as_expr().node().get();
+        let uv = uv.as_expr();
+        if need_runtime_check() {
+            check_texel_coord(uv.lt(self.size()).all(), uv);
+        }
+        let uv = uv.node().get();
         let self_node = self.node.get();
         T::convert_from_read(Expr::<T::RwType>::from_node(
             __current_scope(|b| b.call(Func::Texture3dRead, &[self_node, uv], T::RwType::type_()))
@@ -2336,7 +2802,11 @@ impl<T: IoTexel> Tex3dVar<T> {
         ))
     }
     pub fn write(&self, uv: impl AsExpr<Value = Uint3>, v: impl AsExpr<Value = T>) {
-        let uv = uv.as_expr().node().get();
+        let uv = uv.as_expr();
+        if need_runtime_check() {
+            check_texel_coord(uv.lt(self.size()).all(), uv);
+        }
+        let uv = uv.node().get();
         let v = v.as_expr();
         let v = T::convert_to_write(v).node().get();
         let self_node = self.node.get();
@@ -2360,3 +2830,105 @@ pub struct Tex3dVar<T: IoTexel> {
     #[allow(dead_code)]
     pub(crate) level: Option<u32>,
 }
+
+/// A 1D texture, stored as a 2D texture of height 1.
+/// See [`Device::create_tex1d`].
+pub struct Tex1d<T: IoTexel> {
+    pub(crate) tex: Tex2d<T>,
+}
+impl<T: IoTexel> Tex1d<T> {
+    /// The texels of mip level `level`, a row of `max(width >> level, 1)`.
+    pub fn view(&self, level: u32) -> Tex2dView<T> {
+        self.tex.view(level)
+    }
+    pub fn width(&self) -> u32 {
+        self.tex.width()
+    }
+    pub fn format(&self) -> PixelFormat {
+        self.tex.format()
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.tex.storage()
+    }
+    pub fn var(&self) -> Tex1dVar<T> {
+        Tex1dVar::new(self.view(0))
+    }
+}
+#[derive(Clone)]
+pub struct Tex1dVar<T: IoTexel> {
+    pub(crate) tex: Tex2dVar<T>,
+}
+impl<T: IoTexel> Tex1dVar<T> {
+    pub fn new(view: Tex2dView<T>) -> Self {
+        assert_eq!(view.height, 1, "Tex1dVar requires a texture of height 1");
+        Self { tex: view.var() }
+    }
+    pub fn read(&self, x: impl AsExpr<Value = u32>) -> Expr<T> {
+        self.tex.read(Uint2::expr(x.as_expr(), 0u32))
+    }
+    pub fn write(&self, x: impl AsExpr<Value = u32>, v: impl AsExpr<Value = T>) {
+        self.tex.write(Uint2::expr(x.as_expr(), 0u32), v)
+    }
+    pub fn size(&self) -> Expr<u32> {
+        self.tex.size().x
+    }
+}
+
+/// An array of 2D textures of the same size and format, stored as the
+/// layers of a 3D texture. See [`Device::create_tex2d_array`].
+pub struct Tex2dArray<T: IoTexel> {
+    pub(crate) tex: Tex3d<T>,
+}
+impl<T: IoTexel> Tex2dArray<T> {
+    /// All layers, stored one after another.
+    pub fn view(&self) -> Tex3dView<T> {
+        self.tex.view(0)
+    }
+    pub fn width(&self) -> u32 {
+        self.tex.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.tex.height()
+    }
+    pub fn layers(&self) -> u32 {
+        self.tex.depth()
+    }
+    pub fn format(&self) -> PixelFormat {
+        self.tex.format()
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.tex.storage()
+    }
+    pub fn var(&self) -> Tex2dArrayVar<T> {
+        Tex2dArrayVar::new(self.view())
+    }
+}
+#[derive(Clone)]
+pub struct Tex2dArrayVar<T: IoTexel> {
+    pub(crate) tex: Tex3dVar<T>,
+}
+impl<T: IoTexel> Tex2dArrayVar<T> {
+    pub fn new(view: Tex3dView<T>) -> Self {
+        Self { tex: view.var() }
+    }
+    pub fn read(&self, uv: impl AsExpr<Value = Uint2>, layer: impl AsExpr<Value = u32>) -> Expr<T> {
+        let uv = uv.as_expr();
+        self.tex.read(Uint3::expr(uv.x, uv.y, layer.as_expr()))
+    }
+    pub fn write(
+        &self,
+        uv: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+        v: impl AsExpr<Value = T>,
+    ) {
+        let uv = uv.as_expr();
+        self.tex.write(Uint3::expr(uv.x, uv.y, layer.as_expr()), v)
+    }
+    /// The width and height of a layer.
+    pub fn size(&self) -> Expr<Uint2> {
+        self.tex.size().xy()
+    }
+    pub fn layers(&self) -> Expr<u32> {
+        self.tex.size().z
+    }
+}
@@ -0,0 +1,81 @@
+use super::*;
+
+use ::image::{Rgba, RgbaImage};
+
+impl<T: IoTexel<RwType = Float4>> Tex2dView<T> {
+    /// Downloads the texels into an 8-bit RGBA image.
+    ///
+    /// One-channel textures become gray and two-channel textures fill red
+    /// and green; missing channels are opaque. Half and float values are
+    /// clamped to `[0, 1]` without tonemapping, so tonemap HDR textures
+    /// before reading them back.
+    pub fn copy_to_image(&self) -> RgbaImage {
+        let (channels, channel_size) = match self.storage {
+            PixelStorage::Byte1 => (1, 1),
+            PixelStorage::Byte2 => (2, 1),
+            PixelStorage::Byte4 => (4, 1),
+            PixelStorage::Short1 | PixelStorage::Half1 => (1, 2),
+            PixelStorage::Short2 | PixelStorage::Half2 => (2, 2),
+            PixelStorage::Short4 | PixelStorage::Half4 => (4, 2),
+            PixelStorage::Float1 => (1, 4),
+            PixelStorage::Float2 => (2, 4),
+            PixelStorage::Float4 => (4, 4),
+            storage => panic!("Cannot convert {:?} texels to an image", storage),
+        };
+        let [width, height, _] = self.size();
+        let mut data = vec![0u8; (width * height) as usize * channels * channel_size];
+        let mut rt = ResourceTracker::new();
+        rt.add(self._handle());
+        let download = Command {
+            inner: api::Command::TextureDownload(api::TextureDownloadCommand {
+                texture: self.handle(),
+                storage: self.storage,
+                level: self.level,
+                size: self.size(),
+                data: data.as_mut_ptr(),
+            }),
+            resource_tracker: rt,
+            marker: PhantomData,
+            callback: None,
+        };
+        submit_default_stream_and_sync(&self.device, [download]);
+        let storage = self.storage;
+        let channel = |bytes: &[u8]| -> u8 {
+            let v = match storage {
+                PixelStorage::Byte1 | PixelStorage::Byte2 | PixelStorage::Byte4 => {
+                    return bytes[0];
+                }
+                PixelStorage::Short1 | PixelStorage::Short2 | PixelStorage::Short4 => {
+                    u16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / 65535.0
+                }
+                PixelStorage::Half1 | PixelStorage::Half2 | PixelStorage::Half4 => {
+                    half::f16::from_ne_bytes([bytes[0], bytes[1]]).to_f32()
+                }
+                _ => f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            };
+            // NaN becomes 0
+            (v.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        let mut image = RgbaImage::new(width, height);
+        for (texel, pixel) in data
+            .chunks_exact(channels * channel_size)
+            .zip(image.pixels_mut())
+        {
+            let c: Vec<u8> = texel.chunks_exact(channel_size).map(channel).collect();
+            *pixel = match channels {
+                1 => Rgba([c[0], c[0], c[0], 255]),
+                2 => Rgba([c[0], c[1], 0, 255]),
+                _ => Rgba([c[0], c[1], c[2], c[3]]),
+            };
+        }
+        image
+    }
+}
+
+impl<T: IoTexel<RwType = Float4>> Tex2d<T> {
+    /// Downloads the first mip level into an 8-bit RGBA image.
+    /// See [`Tex2dView::copy_to_image`].
+    pub fn copy_to_image(&self) -> RgbaImage {
+        self.view(0).copy_to_image()
+    }
+}
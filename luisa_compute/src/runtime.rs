@@ -3,10 +3,11 @@ use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::CString;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 
 use parking_lot::lock_api::RawMutex as RawMutexTrait;
@@ -16,6 +17,7 @@ use std::ffi::c_void;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
 use crate::internal_prelude::*;
+use crate::lang::debug::DeviceAsserts;
 use crate::lang::soa::{SoaBuffer, SoaBufferVar, SoaBufferView, SoaMetadata};
 use crate::lang::types::SoaValue;
 use ir::{
@@ -31,9 +33,23 @@ use api::AccelOption;
 pub use luisa_compute_api_types as api;
 use luisa_compute_backend::proxy::ProxyBackend;
 
+mod cache;
+mod features;
+mod frame_ring;
 mod kernel;
-
+mod persistent;
+mod profile;
+mod race_check;
+
+pub use cache::KernelLoadError;
+pub(crate) use cache::{PreloadedKernels, FNV_OFFSET_BASIS, FNV_PRIME};
+pub use features::{Features, Properties};
+pub use frame_ring::{Frame, FrameRing};
 pub use kernel::*;
+pub use persistent::{PersistentKernel, WorkQueue};
+pub use profile::ProfiledKernel;
+pub use race_check::CheckedKernel;
+pub(crate) use race_check::{check_buffer_write, RaceCheck};
 
 #[derive(Clone)]
 pub struct Device {
@@ -76,8 +92,126 @@ pub(crate) struct DeviceHandle {
     pub(crate) default_stream: Option<Arc<StreamHandle>>,
     #[allow(dead_code)]
     pub(crate) ctx: Arc<crate::backend::Context>,
+    pub(crate) compile_progress: RwLock<Option<Arc<CompileProgressCallback>>>,
+    /// See [`Device::set_cache_enabled`].
+    pub(crate) cache_enabled: AtomicBool,
+    /// See [`Device::set_cache_dir`].
+    pub(crate) cache_dir: RwLock<Option<PathBuf>>,
+    /// Streams created with [`Device::create_stream`], synchronized by
+    /// [`Device::synchronize`].
+    pub(crate) streams: Mutex<Vec<Weak<StreamHandle>>>,
+    /// See [`Device::shared_kernel`].
+    pub(crate) shared_kernels: Mutex<SharedKernels>,
+    /// See [`Device::create_shared_callable`], keyed by the type of the
+    /// recorded function and the signature.
+    pub(crate) shared_callables: Mutex<HashMap<(TypeId, TypeId), SharedCallable>>,
+}
+
+/// A recorded callable owned by its device, without the device itself.
+pub(crate) struct SharedCallable {
+    module: CallableModuleRef,
+    /// The resources captured by the callable, without keeping them alive.
+    captures: ResourceTracker,
+    captured_args: Vec<NodeRef>,
+}
+
+/// A compiled kernel owned by its device, see [`Device::shared_kernel`].
+pub(crate) struct SharedKernel {
+    /// Also held by the kernels returned for it, see [`SharedKernels::retire`].
+    shader: Arc<api::Shader>,
+    seen_before: bool,
+    module: CArc<KernelModule>,
+    /// The resources captured by the kernel, without keeping them alive.
+    captures: ResourceTracker,
+    options: KernelBuildOptions,
+    cache_key: u64,
+    cache_dir: Option<PathBuf>,
+    /// The value of [`SharedKernels::clock`] when the kernel was last used.
+    last_used: u64,
+}
+
+/// The number of kernels [`Device::run`] keeps for each closure.
+pub const RUN_KERNELS_PER_CLOSURE: usize = 16;
+
+#[derive(Default)]
+pub(crate) struct SharedKernels {
+    kernels: HashMap<String, SharedKernel>,
+    /// Shaders of kernels removed from the table while a kernel returned
+    /// earlier may still dispatch them.
+    retired: Vec<Arc<api::Shader>>,
+    /// Counts the uses of kernels, to find the least recently used ones.
+    clock: u64,
+}
+
+impl SharedKernels {
+    /// Returns the kernel stored under `key`, marking it as used.
+    fn get(&mut self, key: &str) -> Option<&SharedKernel> {
+        self.clock += 1;
+        let kernel = self.kernels.get_mut(key)?;
+        kernel.last_used = self.clock;
+        Some(kernel)
+    }
+    /// Stores `kernel` under `key`, dropping kernels whose captures were
+    /// dropped, as they cannot be dispatched anymore.
+    fn insert(&mut self, device: &Device, key: String, mut kernel: SharedKernel) {
+        let dead = self
+            .kernels
+            .iter()
+            .filter(|(_, k)| k.captures.try_upgrade().is_none())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in dead {
+            let dead = self.kernels.remove(&key).unwrap();
+            self.retire(device, dead.shader);
+        }
+        self.clock += 1;
+        kernel.last_used = self.clock;
+        if let Some(old) = self.kernels.insert(key, kernel) {
+            self.retire(device, old.shader);
+        }
+    }
+    /// Drops all but the `keep` most recently used kernels whose key starts
+    /// with `prefix`.
+    fn evict(&mut self, device: &Device, prefix: &str, keep: usize) {
+        let mut keys = self
+            .kernels
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, k)| (k.last_used, key.clone()))
+            .collect::<Vec<_>>();
+        if keys.len() <= keep {
+            return;
+        }
+        keys.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        for (_, key) in keys.drain(keep..) {
+            let evicted = self.kernels.remove(&key).unwrap();
+            self.retire(device, evicted.shader);
+        }
+    }
+    /// Destroys `shader` once no kernel returned for it is alive anymore,
+    /// along with earlier retired shaders that are not used anymore.
+    fn retire(&mut self, device: &Device, shader: Arc<api::Shader>) {
+        self.retired.push(shader);
+        self.retired.retain(|shader| {
+            let used = Arc::strong_count(shader) > 1;
+            if !used {
+                device.inner.destroy_shader(**shader);
+            }
+            used
+        });
+    }
+}
+
+/// Progress of an asynchronously compiled kernel, reported to the callback
+/// set with [`Device::set_compile_progress_callback`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompileState {
+    Started,
+    Finished,
 }
 
+pub(crate) type CompileProgressCallback = dyn Fn(&str, CompileState) + Send + Sync;
+
 unsafe impl Send for DeviceHandle {}
 
 unsafe impl Sync for DeviceHandle {}
@@ -95,6 +229,16 @@ unsafe impl Sync for Device {}
 
 impl Drop for DeviceHandle {
     fn drop(&mut self) {
+        let shared = std::mem::take(self.shared_kernels.get_mut());
+        // no kernel is alive anymore, as kernels keep their device alive
+        for shader in shared
+            .kernels
+            .values()
+            .map(|k| &k.shader)
+            .chain(&shared.retired)
+        {
+            self.backend.destroy_shader(**shader);
+        }
         if let Some(s) = &self.default_stream {
             let handle = s.handle();
             self.backend.destroy_stream(handle);
@@ -445,7 +589,36 @@ impl Device {
     pub fn create_buffer<T: Value>(&self, count: usize) -> Buffer<T> {
         self._create_buffer(std::ptr::null_mut(), count)
     }
+    /// Like [`Device::create_buffer`], but returns an error instead of
+    /// panicking if the backend fails to allocate the buffer.
+    pub fn try_create_buffer<T: Value>(&self, count: usize) -> Result<Buffer<T>, ResourceError> {
+        self._try_create_buffer(std::ptr::null_mut(), count)
+    }
+    /// Creates an **unintialized** buffer of `count` elements of type `T`
+    /// placed in the memory `heap`.
+    ///
+    /// The heap is a placement hint. The backend interface does not yet
+    /// distinguish heaps, so every buffer is currently placed in the default
+    /// heap and behaves exactly like one from [`Device::create_buffer`].
+    pub fn create_buffer_in_heap<T: Value>(&self, count: usize, heap: BufferHeap) -> Buffer<T> {
+        let _ = heap;
+        self.create_buffer(count)
+    }
+    /// Creates an **unintialized** buffer of `count` elements of type `T`
+    /// that is mostly read back by the host. See
+    /// [`Device::create_buffer_in_heap`].
+    pub fn create_buffer_download<T: Value>(&self, count: usize) -> Buffer<T> {
+        self.create_buffer_in_heap(count, BufferHeap::Download)
+    }
     fn _create_buffer<T: Value>(&self, ext_mem: *mut c_void, count: usize) -> Buffer<T> {
+        self._try_create_buffer(ext_mem, count)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn _try_create_buffer<T: Value>(
+        &self,
+        ext_mem: *mut c_void,
+        count: usize,
+    ) -> Result<Buffer<T>, ResourceError> {
         let name = self.name();
         assert!(
             std::mem::size_of::<T>() > 0,
@@ -467,33 +640,131 @@ impl Device {
             <T as TypeOf>::type_()
         };
         let buffer = self.inner.create_buffer(&ty, count, ext_mem);
-        let handle = Arc::new(BufferHandle {
-            device: self.clone(),
-            handle: api::Buffer(buffer.resource.handle),
-            native_handle: buffer.resource.native_handle,
-        });
-        let buffer = Buffer {
+        if buffer.resource.handle == api::INVALID_RESOURCE_HANDLE {
+            return Err(ResourceError::AllocationFailed {
+                size_bytes: count * std::mem::size_of::<T>(),
+            });
+        }
+        Ok(self._wrap_buffer(
+            BufferHandle {
+                device: self.clone(),
+                handle: api::Buffer(buffer.resource.handle),
+                native_handle: buffer.resource.native_handle,
+            },
+            count,
+            buffer.total_size_bytes,
+        ))
+    }
+    fn _wrap_buffer<T: Value>(
+        &self,
+        handle: BufferHandle,
+        count: usize,
+        total_size_bytes: usize,
+    ) -> Buffer<T> {
+        let handle = Arc::new(handle);
+        Buffer {
             handle: handle.clone(),
             full_view: BufferView {
                 device: self.clone(),
                 handle: Arc::downgrade(&handle),
                 offset: 0,
                 len: count,
-                total_size_bytes: buffer.total_size_bytes,
+                total_size_bytes,
                 _marker: PhantomData,
             },
-        };
-        buffer
+        }
     }
 
-    /// Imports an external buffer of `count` elements of type `T`.
-    pub unsafe fn import_external_buffer<T: Value>(&self, data: *mut T, count: usize) -> Buffer<T> {
-        self._create_buffer(data as *mut c_void, count)
+    /// Wraps a buffer of `count` elements allocated outside this crate, e.g.
+    /// a CUDA allocation owned by the engine embedding it. `native_handle` is
+    /// the backend's native buffer, as returned by
+    /// [`Buffer::native_handle`] on the same backend.
+    ///
+    /// The device creates its own resource over the foreign memory. Dropping
+    /// the returned [`Buffer`] only releases that resource, never the memory
+    /// behind `native_handle`.
+    ///
+    /// # Safety
+    /// `native_handle` must refer to a live native buffer of this device's
+    /// backend holding at least `count` elements of `T`. The owner must keep
+    /// it alive until the returned buffer, and every command using it, has
+    /// finished.
+    pub unsafe fn import_buffer<T: Value>(
+        &self,
+        native_handle: *mut c_void,
+        count: usize,
+    ) -> Result<Buffer<T>, ResourceError> {
+        self._try_create_buffer(native_handle, count)
+    }
+    /// Would wrap a 2D texture allocated outside this crate, like
+    /// [`Device::import_buffer`] does for buffers.
+    ///
+    /// The backend interface cannot create a texture over a native handle
+    /// yet, so this always returns
+    /// [`ResourceError::TextureImportUnsupported`]. Callers can copy the
+    /// texture through an imported buffer instead.
+    ///
+    /// # Safety
+    /// `native_handle` must refer to a live native texture of this device's
+    /// backend with the given storage, size and mip levels, kept alive by
+    /// its owner until the returned texture has been dropped.
+    pub unsafe fn import_tex2d<T: IoTexel>(
+        &self,
+        native_handle: *mut c_void,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        mips: u32,
+    ) -> Result<Tex2d<T>, ResourceError> {
+        let _ = (native_handle, storage, width, height, mips);
+        Err(ResourceError::TextureImportUnsupported)
+    }
+    /// Would wrap a 3D texture allocated outside this crate. Always returns
+    /// [`ResourceError::TextureImportUnsupported`], see
+    /// [`Device::import_tex2d`].
+    ///
+    /// # Safety
+    /// See [`Device::import_tex2d`].
+    pub unsafe fn import_tex3d<T: IoTexel>(
+        &self,
+        native_handle: *mut c_void,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mips: u32,
+    ) -> Result<Tex3d<T>, ResourceError> {
+        let _ = (native_handle, storage, width, height, depth, mips);
+        Err(ResourceError::TextureImportUnsupported)
+    }
+    /// Creates a buffer that lives in memory shared by the host and the device.
+    /// Only backends whose buffers are host memory (currently `cpu`) support
+    /// this; others return [`ResourceError::UnifiedMemoryUnsupported`], so
+    /// callers can fall back to a [`Buffer`] and explicit copies.
+    pub fn create_unified_buffer<T: Value>(
+        &self,
+        count: usize,
+    ) -> Result<UnifiedBuffer<T>, ResourceError> {
+        if self.name() != "cpu" {
+            return Err(ResourceError::UnifiedMemoryUnsupported);
+        }
+        Ok(UnifiedBuffer {
+            buffer: self.try_create_buffer(count)?,
+        })
     }
     pub fn create_buffer_from_slice<T: Value>(&self, data: &[T]) -> Buffer<T> {
-        let buffer = self.create_buffer(data.len());
+        self.try_create_buffer_from_slice(data)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Creates a buffer holding a copy of `data`, or returns an error if the
+    /// backend fails to allocate it.
+    pub fn try_create_buffer_from_slice<T: Value>(
+        &self,
+        data: &[T],
+    ) -> Result<Buffer<T>, ResourceError> {
+        let buffer = self.try_create_buffer(data.len())?;
         buffer.view(..).copy_from(data);
-        buffer
+        Ok(buffer)
     }
     pub fn create_buffer_from_fn<T: Value>(
         &self,
@@ -537,17 +808,25 @@ impl Device {
         let texture = self
             .inner
             .create_texture(format, 2, width, height, 1, mips, true, false);
-        let handle = Arc::new(TextureHandle {
-            device: self.clone(),
-            handle: api::Texture(texture.handle),
-            native_handle: texture.native_handle,
-            format,
-            levels: mips,
-            width,
-            height,
-            depth: 1,
-            storage: format.storage(),
-        });
+        self._wrap_tex2d(
+            TextureHandle {
+                device: self.clone(),
+                handle: api::Texture(texture.handle),
+                native_handle: texture.native_handle,
+                format,
+                levels: mips,
+                width,
+                height,
+                depth: 1,
+                storage: format.storage(),
+            },
+            storage,
+        )
+    }
+    fn _wrap_tex2d<T: IoTexel>(&self, handle: TextureHandle, storage: PixelStorage) -> Tex2d<T> {
+        let (width, height, mips, format) =
+            (handle.width, handle.height, handle.levels, handle.format);
+        let handle = Arc::new(handle);
         let weak = Arc::downgrade(&handle);
         let tex = Tex2d {
             width,
@@ -568,6 +847,70 @@ impl Device {
         };
         tex
     }
+    /// Creates a buffer holding a copy of the texels of `tex`, in the
+    /// texture's storage layout `U`.
+    pub fn create_buffer_from_tex<T: IoTexel, U: StorageTexel<T> + Value>(
+        &self,
+        tex: &Tex2dView<T>,
+    ) -> Buffer<U> {
+        assert_eq!(
+            tex.storage,
+            U::pixel_storage(),
+            "texture storage {:?} does not match buffer element type",
+            tex.storage
+        );
+        let buffer = self.create_buffer::<U>(tex.texel_count() as usize);
+        tex.copy_to_buffer(&buffer.view(..));
+        buffer
+    }
+    /// Creates a single-level `width` x `height` texture with the given
+    /// `storage` and copies `buffer` into it.
+    pub fn create_tex_from_buffer<T: IoTexel, U: StorageTexel<T> + Value>(
+        &self,
+        buffer: &BufferView<U>,
+        width: u32,
+        height: u32,
+        storage: PixelStorage,
+    ) -> Tex2d<T> {
+        assert_eq!(
+            storage,
+            U::pixel_storage(),
+            "texture storage {:?} does not match buffer element type",
+            storage
+        );
+        assert_eq!(
+            buffer.len,
+            (width * height) as usize,
+            "buffer length does not match texture size"
+        );
+        let tex = self.create_tex2d::<T>(storage, width, height, 1);
+        tex.view(0).copy_from_buffer(buffer);
+        tex
+    }
+    /// Creates a 1D texture of `width` texels and `mips` levels.
+    pub fn create_tex1d<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        mips: u32,
+    ) -> Tex1d<T> {
+        Tex1d {
+            tex: self.create_tex2d(storage, width, 1, mips),
+        }
+    }
+    /// Creates `layers` 2D textures of `width` x `height` texels, addressed by
+    /// layer index in kernels. Layers have no mip levels.
+    pub fn create_tex2d_array<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        layers: u32,
+    ) -> Tex2dArray<T> {
+        Tex2dArray {
+            tex: self.create_tex3d(storage, width, height, layers, 1),
+        }
+    }
     pub fn create_tex3d<T: IoTexel>(
         &self,
         storage: PixelStorage,
@@ -580,17 +923,30 @@ impl Device {
         let texture = self
             .inner
             .create_texture(format, 3, width, height, depth, mips, true, false);
-        let handle = Arc::new(TextureHandle {
-            device: self.clone(),
-            handle: api::Texture(texture.handle),
-            native_handle: texture.native_handle,
-            format,
-            levels: mips,
-            width,
-            height,
-            depth,
-            storage: format.storage(),
-        });
+        self._wrap_tex3d(
+            TextureHandle {
+                device: self.clone(),
+                handle: api::Texture(texture.handle),
+                native_handle: texture.native_handle,
+                format,
+                levels: mips,
+                width,
+                height,
+                depth,
+                storage: format.storage(),
+            },
+            storage,
+        )
+    }
+    fn _wrap_tex3d<T: IoTexel>(&self, handle: TextureHandle, storage: PixelStorage) -> Tex3d<T> {
+        let (width, height, depth, mips, format) = (
+            handle.width,
+            handle.height,
+            handle.depth,
+            handle.levels,
+            handle.format,
+        );
+        let handle = Arc::new(handle);
         let weak = Arc::downgrade(&handle);
         let tex = Tex3d {
             width,
@@ -620,15 +976,122 @@ impl Device {
             handle: self.inner.default_stream.clone().unwrap(),
         }
     }
+    /// Runs `f` with a [`Scope`] on the default stream.
+    /// Commands submitted in `f` return immediately. The scope is dropped
+    /// when `f` returns, and dropping a [`Scope`] synchronizes its stream,
+    /// so the commands have completed and their results are visible after
+    /// this function returns.
+    #[inline]
+    pub fn with_scope<'a, R>(&self, f: impl FnOnce(&Scope<'a>) -> R) -> R {
+        self.default_stream().with_scope(f)
+    }
+    /// Records `f` as a kernel, dispatches it once over `dispatch_size` and
+    /// waits for it to finish.
+    ///
+    /// The resources and values captured by `f` are baked into the kernel,
+    /// so `f` is recorded on every call. The compiled kernel is kept on the
+    /// device, keyed by the type of `f`, the recorded kernel and the captured
+    /// resources, and reused by later calls while those resources are alive.
+    /// Only the [`RUN_KERNELS_PER_CLOSURE`] most recently used kernels of
+    /// each closure are kept, so that captured values changing on every
+    /// call, e.g. a frame counter, do not accumulate kernels; pass such
+    /// values through a buffer to avoid compiling on every call.
+    pub fn run<F: Fn()>(&self, dispatch_size: [u32; 3], f: F) {
+        let def = KernelDef::<fn()>::new(self, &f);
+        let module = &def.inner.module;
+        let mut captures = std::collections::hash_map::DefaultHasher::new();
+        for c in module.captures.iter() {
+            c.binding.hash(&mut captures);
+        }
+        let prefix = format!("run/{}/", std::any::type_name::<F>());
+        let key = format!(
+            "{}{:016x}/{:016x}",
+            prefix,
+            cache::kernel_cache_key(self, module, &KernelBuildOptions::default()),
+            captures.finish()
+        );
+        let kernel = self.shared_kernel(key, || self.compile_kernel_def(&def));
+        self.inner
+            .shared_kernels
+            .lock()
+            .evict(self, &prefix, RUN_KERNELS_PER_CLOSURE);
+        kernel.dispatch(dispatch_size);
+    }
+    /// The number of kernels kept by the device, see [`Device::run`].
+    #[doc(hidden)]
+    pub fn shared_kernel_count(&self) -> usize {
+        self.inner.shared_kernels.lock().kernels.len()
+    }
+    /// Creates a kernel `(src, index, dst)` computing
+    /// `dst[i] = src[index[i]]`. Dispatch it over `index.len()` threads.
+    pub fn create_gather<T: Value>(&self) -> Kernel<fn(Buffer<T>, Buffer<u32>, Buffer<T>)> {
+        Kernel::<fn(Buffer<T>, Buffer<u32>, Buffer<T>)>::new(
+            self,
+            &track!(|src, index, dst| {
+                let i = dispatch_id().x;
+                dst.write(i, src.read(index.read(i)));
+            }),
+        )
+    }
+    /// Creates a kernel `(src, index, dst)` computing
+    /// `dst[index[i]] = src[i]`. Dispatch it over `index.len()` threads.
+    ///
+    /// Which write wins for duplicate indices is unspecified, see
+    /// [`Device::create_scatter_add`] to accumulate them instead.
+    pub fn create_scatter<T: Value>(&self) -> Kernel<fn(Buffer<T>, Buffer<u32>, Buffer<T>)> {
+        Kernel::<fn(Buffer<T>, Buffer<u32>, Buffer<T>)>::new(
+            self,
+            &track!(|src, index, dst| {
+                let i = dispatch_id().x;
+                dst.write(index.read(i), src.read(i));
+            }),
+        )
+    }
+    /// Creates a kernel `(src, index, dst)` computing
+    /// `dst[index[i]] += src[i]` with atomics, so duplicate indices
+    /// accumulate. Dispatch it over `index.len()` threads.
+    pub fn create_scatter_add<T: AtomicAdd>(
+        &self,
+    ) -> Kernel<fn(Buffer<T>, Buffer<u32>, Buffer<T>)> {
+        Kernel::<fn(Buffer<T>, Buffer<u32>, Buffer<T>)>::new(
+            self,
+            &track!(|src, index, dst| {
+                let i = dispatch_id().x;
+                T::buffer_atomic_add(&dst, index.read(i), src.read(i));
+            }),
+        )
+    }
     pub fn create_stream(&self, tag: api::StreamTag) -> Stream {
+        self.create_stream_with_priority(tag, Priority::Normal)
+    }
+    /// Creates a stream with a scheduling priority hint.
+    ///
+    /// The backend interface has no stream priorities yet, so the hint is
+    /// only recorded (see [`Stream::priority`]) and does not change how
+    /// commands are scheduled.
+    pub fn create_stream_with_priority(&self, tag: api::StreamTag, priority: Priority) -> Stream {
         let stream = self.inner.create_stream(tag);
+        let handle = Arc::new(StreamHandle::NonDefault {
+            device: self.inner.clone(),
+            handle: api::Stream(stream.handle),
+            native_handle: stream.native_handle,
+            priority,
+        });
+        let mut streams = self.inner.streams.lock();
+        streams.retain(|s| s.strong_count() > 0);
+        streams.push(Arc::downgrade(&handle));
         Stream {
             device: self.clone(),
-            handle: Arc::new(StreamHandle::NonDefault {
-                device: self.inner.clone(),
-                handle: api::Stream(stream.handle),
-                native_handle: stream.native_handle,
-            }),
+            handle,
+        }
+    }
+    /// Waits for the commands submitted to every stream of the device.
+    pub fn synchronize(&self) {
+        let default_stream = self.inner.default_stream.as_ref().unwrap();
+        self.inner.synchronize_stream(default_stream.handle());
+        let streams = self.inner.streams.lock().clone();
+        for stream in streams.iter().filter_map(Weak::upgrade) {
+            self.inner.synchronize_stream(stream.handle());
         }
     }
     pub fn create_event(&self) -> Event {
@@ -638,6 +1101,7 @@ impl Device {
                 device: self.clone(),
                 handle: api::Event(event.handle),
                 native_handle: event.native_handle,
+                last_signaled: AtomicU64::new(0),
             }),
         }
     }
@@ -700,17 +1164,54 @@ impl Device {
         }
     }
 
-    // pub fn create_callable<S: CallableSignature>(
-    //     &self,
-    //     f: impl CallableBuildFn<S>,
-    // ) -> Callable<S> {
-    //     let mut builder = KernelBuilder::new(Some(self.clone()), false);
-    //     let raw_callable = CallableBuildFn::build_callable(&f, None, &mut builder);
-    //     Callable {
-    //         inner: raw_callable,
-    //         _marker: PhantomData,
-    //     }
-    // }
+    /// Records `f` as a callable, like [`Callable::new`], or returns the
+    /// callable already recorded from `f` on this device.
+    ///
+    /// `f` is identified by its type, so it must not capture anything, e.g.
+    /// a `fn` item or a closure without captures. Every callable returned for
+    /// the same `f` refers to the same function, which a kernel calling them
+    /// defines only once.
+    pub fn create_shared_callable<S, F>(&self, f: F) -> Callable<S>
+    where
+        S: CallableSignature + 'static,
+        F: CallableBuildFn<S> + 'static,
+    {
+        assert_eq!(
+            std::mem::size_of::<F>(),
+            0,
+            "create_shared_callable: `f` must not capture anything"
+        );
+        let key = (TypeId::of::<F>(), TypeId::of::<S>());
+        if let Some(shared) = self.inner.shared_callables.lock().get(&key) {
+            if let Some(resource_tracker) = shared.captures.try_upgrade() {
+                return Callable {
+                    inner: RawCallable {
+                        device: Some(self.clone()),
+                        module: shared.module.clone(),
+                        resource_tracker,
+                        captured_args: shared.captured_args.clone(),
+                    },
+                    _marker: PhantomData,
+                };
+            }
+        }
+        // recorded without holding the lock, `f` may create other shared
+        // callables
+        let mut builder = KernelBuilder::new(Some(self.clone()), false);
+        let raw_callable = CallableBuildFn::build_callable(&f, None, &mut builder);
+        self.inner.shared_callables.lock().insert(
+            key,
+            SharedCallable {
+                module: raw_callable.module.clone(),
+                captures: raw_callable.resource_tracker.downgrade(),
+                captured_args: raw_callable.captured_args.clone(),
+            },
+        );
+        Callable {
+            inner: raw_callable,
+            _marker: PhantomData,
+        }
+    }
 
     pub fn create_kernel<'a, S: KernelSignature2<'a>>(&self, f: S::Fn) -> Kernel<S> {
         let mut builder = KernelBuilder::new(Some(self.clone()), true);
@@ -718,6 +1219,88 @@ impl Device {
         self.compile_kernel_def(&k)
     }
 
+    /// Sets a callback invoked when each asynchronously compiled kernel of
+    /// this device starts and finishes compiling, with the kernel's
+    /// [`KernelBuildOptions::name`] (empty if unset).
+    ///
+    /// The callback runs on the compilation thread. Kernels whose compilation
+    /// started before the call are reported to the previous callback, if any.
+    pub fn set_compile_progress_callback(
+        &self,
+        f: impl Fn(&str, CompileState) + Send + Sync + 'static,
+    ) {
+        *self.inner.compile_progress.write() = Some(Arc::new(f));
+    }
+    /// Enables or disables the persistent shader cache for kernels compiled
+    /// afterwards. While it is disabled, every kernel is compiled as if
+    /// [`KernelBuildOptions::enable_cache`] were false, e.g. to benchmark
+    /// compilation reproducibly.
+    pub fn set_cache_enabled(&self, enabled: bool) {
+        self.inner.cache_enabled.store(enabled, Ordering::Relaxed);
+    }
+    /// Moves the persistent shader cache of kernels compiled afterwards to
+    /// `dir`, e.g. a writable location in a sandbox.
+    ///
+    /// The backend stores a cached shader under its name, so the kernels
+    /// are named by a path inside `dir`: their
+    /// [`KernelBuildOptions::name`], or `kernel_{cache key}` if unset.
+    /// [`Kernel::cache_dir`] and [`Kernel::seen_before`] of these kernels
+    /// refer to `dir`; kernels compiled before keep their directory.
+    pub fn set_cache_dir(&self, dir: impl Into<PathBuf>) {
+        *self.inner.cache_dir.write() = Some(dir.into());
+    }
+    /// Returns the kernel stored under `key`, compiling it with `compile`
+    /// the first time.
+    ///
+    /// The compiled shader belongs to the device and is reused for as long
+    /// as the resources the kernel captured are alive, so that e.g.
+    /// built-in commands do not compile their kernel on every use. Kernels
+    /// using [`device_assert!`](crate::device_assert) are not shared.
+    pub(crate) fn shared_kernel<S: KernelSignature>(
+        &self,
+        key: String,
+        compile: impl FnOnce() -> Kernel<S>,
+    ) -> Kernel<S> {
+        if let Some(shared) = self.inner.shared_kernels.lock().get(&key) {
+            if let Some(resource_tracker) = shared.captures.try_upgrade() {
+                return Kernel {
+                    inner: Arc::new(RawKernel {
+                        device: self.clone(),
+                        artifact: ShaderArtifact::Shared(shared.shader.clone(), shared.seen_before),
+                        module: shared.module.clone(),
+                        resource_tracker,
+                        options: shared.options.clone(),
+                        cache_key: shared.cache_key,
+                        cache_dir: shared.cache_dir.clone(),
+                        asserts: None,
+                    }),
+                    _marker: PhantomData,
+                };
+            }
+        }
+        // compiled without holding the lock, `compile` may need another
+        // shared kernel
+        let mut kernel = compile();
+        let raw = match Arc::get_mut(&mut kernel.inner) {
+            Some(raw) if raw.asserts.is_none() => raw,
+            _ => return kernel,
+        };
+        let shader = Arc::new(raw.unwrap());
+        let seen_before = raw.artifact.seen_before();
+        raw.artifact = ShaderArtifact::Shared(shader.clone(), seen_before);
+        let shared = SharedKernel {
+            shader,
+            seen_before,
+            module: raw.module.clone(),
+            captures: raw.resource_tracker.downgrade(),
+            options: raw.options.clone(),
+            cache_key: raw.cache_key,
+            cache_dir: raw.cache_dir.clone(),
+            last_used: 0,
+        };
+        self.inner.shared_kernels.lock().insert(self, key, shared);
+        kernel
+    }
     pub fn create_kernel_async<'a, S: KernelSignature2<'a>>(&self, f: S::Fn) -> Kernel<S> {
         let mut builder = KernelBuilder::new(Some(self.clone()), true);
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
@@ -733,6 +1316,15 @@ impl Device {
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
         self.compile_kernel_def_with_options(&k, options)
     }
+    /// Creates a kernel compiled at optimization level `level`, see
+    /// [`OptLevel`] for what the levels change.
+    pub fn create_kernel_with_opt<'a, S: KernelSignature2<'a>>(
+        &self,
+        level: OptLevel,
+        f: S::Fn,
+    ) -> Kernel<S> {
+        self.create_kernel_with_options(KernelBuildOptions::default().with_opt_level(level), f)
+    }
     /// Compile a [`KernelDef`] into a [`Kernel`]. See [`Kernel`] for more
     /// details on kernel creation
     pub fn compile_kernel_def<S: KernelSignature>(&self, k: &KernelDef<S>) -> Kernel<S> {
@@ -756,23 +1348,27 @@ impl Device {
     pub fn compile_kernel_def_with_options<S: KernelSignature>(
         &self,
         k: &KernelDef<S>,
-        options: KernelBuildOptions,
+        mut options: KernelBuildOptions,
     ) -> Kernel<S> {
-        let name = options.name.unwrap_or("".to_string());
-        let name = Arc::new(CString::new(name).unwrap());
-        let native_include = options.native_include.unwrap_or("".to_string());
-        let native_include = Arc::new(CString::new(native_include).unwrap());
-        let shader_options = api::ShaderOption {
-            enable_cache: options.enable_cache,
-            enable_fast_math: options.enable_fast_math,
-            enable_debug_info: options.enable_debug_info,
-            time_trace: options.time_trace,
-            max_registers: options.max_registers,
-            compile_only: false,
-            name: name.as_ptr(),
-            native_include: native_include.as_ptr(),
-        };
+        options.enable_cache &= self.inner.cache_enabled.load(Ordering::Relaxed);
         let module = k.inner.module.clone();
+        let cache_key = cache::kernel_cache_key(self, &module, &options);
+        if options.enable_cache {
+            if let Some(kernel) = self.preloaded_kernel(k, cache_key) {
+                return kernel;
+            }
+        }
+        if options.name.is_none() && options.enable_cache && options.name_by_cache_key {
+            options.name = Some(format!("kernel_{:016x}", cache_key));
+        }
+        let cache_dir = if options.enable_cache {
+            self.inner.cache_dir.read().clone()
+        } else {
+            None
+        };
+        let (shader_options, name, native_include) =
+            self.shader_option(&options, cache_key, cache_dir.as_deref());
+        let enable_cache = options.enable_cache;
         let artifact = if options.async_compile {
             ShaderArtifact::Async(AsyncShaderArtifact::new(
                 self.clone(),
@@ -780,9 +1376,20 @@ impl Device {
                 shader_options,
                 name,
                 native_include,
+                options.name.clone().unwrap_or_default(),
+                cache_key,
+                cache_dir.clone(),
             ))
         } else {
-            ShaderArtifact::Sync(self.inner.create_shader(&module, &shader_options))
+            let shader = self.inner.create_shader(&module, &shader_options);
+            let seen_before = enable_cache
+                && cache::record_cache_entry(
+                    self,
+                    cache_dir.as_deref(),
+                    api::Shader(shader.resource.handle),
+                    cache_key,
+                );
+            ShaderArtifact::Sync(shader, seen_before)
         };
         Kernel {
             inner: Arc::new(RawKernel {
@@ -790,10 +1397,136 @@ impl Device {
                 artifact,
                 module,
                 resource_tracker: k.inner.resource_tracker.clone(),
+                options,
+                cache_key,
+                cache_dir,
+                asserts: k.inner.asserts.clone(),
             }),
             _marker: PhantomData {},
         }
     }
+    /// The backend options for compiling a kernel with `options`, along
+    /// with the strings they point to.
+    fn shader_option(
+        &self,
+        options: &KernelBuildOptions,
+        cache_key: u64,
+        cache_dir: Option<&Path>,
+    ) -> (api::ShaderOption, Arc<CString>, Arc<CString>) {
+        // the backend caches a named shader under its name
+        let name = match cache_dir {
+            Some(dir) => {
+                let name = options.name.clone();
+                let name = name.unwrap_or_else(|| format!("kernel_{:016x}", cache_key));
+                dir.join(name).to_string_lossy().into_owned()
+            }
+            None => options.name.clone().unwrap_or_default(),
+        };
+        let name = Arc::new(CString::new(name).unwrap());
+        let native_include = options.native_include.clone().unwrap_or("".to_string());
+        let native_include = Arc::new(CString::new(native_include).unwrap());
+        let shader_options = api::ShaderOption {
+            enable_cache: options.enable_cache,
+            enable_fast_math: options.enable_fast_math,
+            enable_debug_info: options.enable_debug_info,
+            time_trace: options.time_trace,
+            max_registers: options.max_registers,
+            compile_only: false,
+            name: name.as_ptr(),
+            native_include: native_include.as_ptr(),
+        };
+        (shader_options, name, native_include)
+    }
+}
+
+/// Error creating a device resource or a view of one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceError {
+    /// The backend failed to allocate `size_bytes` bytes.
+    AllocationFailed { size_bytes: usize },
+    /// A view of `len` elements at `offset` does not fit in a view of
+    /// `view_len` elements.
+    ViewOutOfBounds {
+        offset: usize,
+        len: usize,
+        view_len: usize,
+    },
+    /// The backend cannot share buffer memory with the host.
+    /// See [`Device::create_unified_buffer`].
+    UnifiedMemoryUnsupported,
+    /// The backend cannot create a texture over a native handle.
+    /// See [`Device::import_tex2d`].
+    TextureImportUnsupported,
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceError::AllocationFailed { size_bytes } => {
+                write!(f, "failed to allocate a buffer of {} bytes", size_bytes)
+            }
+            ResourceError::ViewOutOfBounds {
+                offset,
+                len,
+                view_len,
+            } => write!(
+                f,
+                "view of {} elements at offset {} is out of bounds of a view of {} elements",
+                len, offset, view_len
+            ),
+            ResourceError::UnifiedMemoryUnsupported => {
+                write!(f, "the backend does not support unified buffers")
+            }
+            ResourceError::TextureImportUnsupported => {
+                write!(f, "the backend does not support importing textures")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+/// A failed [`device_assert!`](crate::device_assert), reported by
+/// [`Kernel::check_asserts`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceAssertion {
+    /// The message of the assertion and where it was recorded.
+    pub message: String,
+    /// Dispatch id of the first thread that failed it.
+    pub dispatch_id: [u32; 3],
+}
+
+impl std::fmt::Display for DeviceAssertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "device assertion failed: {}, dispatch_id = {:?}",
+            self.message, self.dispatch_id
+        )
+    }
+}
+
+impl std::error::Error for DeviceAssertion {}
+
+/// Memory heap a buffer is placed in. See [`Device::create_buffer_in_heap`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum BufferHeap {
+    /// Device-local memory, fastest for kernel access.
+    #[default]
+    Default,
+    /// Host-visible memory optimized for host to device transfer.
+    Upload,
+    /// Host-visible memory optimized for device to host transfer.
+    Download,
+}
+
+/// Scheduling priority hint of a [`Stream`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
 }
 
 pub(crate) enum StreamHandle {
@@ -806,6 +1539,7 @@ pub(crate) enum StreamHandle {
         device: Arc<DeviceHandle>,
         handle: api::Stream,
         native_handle: *mut std::ffi::c_void,
+        priority: Priority,
     },
 }
 
@@ -855,6 +1589,8 @@ impl Swapchain {
 /// Synchronization primitives between streams, resemble timeline semaphores.
 /// `scope.signal(event, ticket)` signals the event with a ticket.
 /// `scope.wait(event, ticket)` waits until the event is signaled with a ticket
+///
+/// [`Stream::signal`] and [`Stream::wait`] pick the tickets automatically.
 #[derive(Clone)]
 pub struct Event {
     pub(crate) handle: Arc<EventHandle>,
@@ -883,12 +1619,37 @@ impl Event {
             .inner
             .is_event_completed(self.handle.handle, ticket)
     }
+    /// The highest ticket this event has been signaled with so far, or 0.
+    #[inline]
+    pub fn last_signaled(&self) -> u64 {
+        self.handle.last_signaled.load(Ordering::SeqCst)
+    }
+}
+
+/// Completion of the commands submitted by [`Stream::submit_async`].
+pub struct Completion {
+    event: Event,
+    ticket: u64,
+}
+
+impl Completion {
+    /// Whether the commands have completed.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.event.is_completed(self.ticket)
+    }
+    /// Blocks until the commands have completed.
+    #[inline]
+    pub fn wait(&self) {
+        self.event.synchronize(self.ticket);
+    }
 }
 
 pub(crate) struct EventHandle {
     pub(crate) device: Device,
     handle: api::Event,
     native_handle: *mut std::ffi::c_void,
+    last_signaled: AtomicU64,
 }
 
 unsafe impl Send for EventHandle {}
@@ -942,6 +1703,13 @@ impl StreamHandle {
             StreamHandle::NonDefault { native_handle, .. } => *native_handle,
         }
     }
+    #[inline]
+    pub(crate) fn priority(&self) -> Priority {
+        match self {
+            StreamHandle::Default { .. } => Priority::Normal,
+            StreamHandle::NonDefault { priority, .. } => *priority,
+        }
+    }
 }
 
 impl Drop for StreamHandle {
@@ -977,6 +1745,7 @@ pub struct Scope<'a> {
     marker: PhantomData<&'a ()>,
     synchronized: Cell<bool>,
     resource_tracker: RefCell<ResourceTracker>,
+    debug_markers: RefCell<Vec<String>>,
 }
 
 impl<'a> Scope<'a> {
@@ -1082,11 +1851,42 @@ impl<'a> Scope<'a> {
     }
     #[inline]
     pub fn signal(&self, event: &Event, ticket: u64) -> &Self {
+        event
+            .handle
+            .last_signaled
+            .fetch_max(ticket, Ordering::SeqCst);
         self.handle
             .device()
             .signal_event(event.handle(), self.handle(), ticket);
         self
     }
+    /// Pushes `name` onto the scope's stack of debug markers, which nest and
+    /// must be popped with [`Scope::pop_debug_marker`]. A [`CommandBuffer`]
+    /// records markers in order with its commands.
+    ///
+    /// Markers are host-side only: the backend interface has no debug-group
+    /// command, so they do not show up in GPU debuggers or profilers such as
+    /// Nsight. They are logged through the `log` crate at debug level and
+    /// returned by [`Scope::debug_markers`], e.g. to label host-side timings
+    /// or error reports.
+    pub fn push_debug_marker(&self, name: impl Into<String>) -> &Self {
+        let mut markers = self.debug_markers.borrow_mut();
+        markers.push(name.into());
+        log::debug!("push debug marker `{}`", markers.join("/"));
+        self
+    }
+    /// Pops the innermost debug marker, see [`Scope::push_debug_marker`].
+    pub fn pop_debug_marker(&self) -> &Self {
+        let mut markers = self.debug_markers.borrow_mut();
+        assert!(!markers.is_empty(), "No debug marker to pop");
+        log::debug!("pop debug marker `{}`", markers.join("/"));
+        markers.pop();
+        self
+    }
+    /// Currently pushed debug markers, outermost first.
+    pub fn debug_markers(&self) -> Vec<String> {
+        self.debug_markers.borrow().clone()
+    }
     #[inline]
     pub fn present<T: IoTexel>(&self, swapchain: &Swapchain, image: &Tex2d<T>) -> &Self {
         assert_eq!(image.handle.storage, swapchain.handle.pixel_storage);
@@ -1103,6 +1903,127 @@ impl<'a> Scope<'a> {
     }
 }
 
+/// An ordered mix of device commands and host closures, committed to a
+/// [`Scope`] as one batch.
+///
+/// Host closures run on the committing thread once every command before them
+/// has completed, so they observe the effects of preceding dispatches and
+/// commands after them observe their effects on host data. Each host closure
+/// synchronizes the stream.
+pub struct CommandBuffer<'cmd, 'scope> {
+    items: Vec<CommandBufferItem<'cmd, 'scope>>,
+}
+
+enum CommandBufferItem<'cmd, 'scope> {
+    Device(Command<'cmd, 'scope>),
+    Host(Box<dyn FnOnce() + 'cmd>),
+    PushDebugMarker(String),
+    PopDebugMarker,
+}
+
+impl<'cmd, 'scope> CommandBuffer<'cmd, 'scope> {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+    pub fn push(&mut self, command: Command<'cmd, 'scope>) -> &mut Self {
+        self.items.push(CommandBufferItem::Device(command));
+        self
+    }
+    pub fn push_host(&mut self, f: impl FnOnce() + 'cmd) -> &mut Self {
+        self.items.push(CommandBufferItem::Host(Box::new(f)));
+        self
+    }
+    /// Pushes a debug marker around the commands pushed after it, see
+    /// [`Scope::push_debug_marker`] for what markers do and do not do.
+    pub fn push_debug_marker(&mut self, name: impl Into<String>) -> &mut Self {
+        self.items
+            .push(CommandBufferItem::PushDebugMarker(name.into()));
+        self
+    }
+    /// Pops the innermost debug marker, see [`Scope::pop_debug_marker`].
+    pub fn pop_debug_marker(&mut self) -> &mut Self {
+        self.items.push(CommandBufferItem::PopDebugMarker);
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    /// Submits the commands to `scope` in order, running each host closure
+    /// after the commands pushed before it have completed. Debug markers are
+    /// pushed to and popped from `scope` between the commands around them.
+    pub fn commit(self, scope: &Scope<'scope>) {
+        let mut commands = vec![];
+        for item in self.items {
+            match item {
+                CommandBufferItem::Device(command) => commands.push(command),
+                CommandBufferItem::Host(f) => {
+                    scope.submit(std::mem::take(&mut commands));
+                    scope.synchronize();
+                    f();
+                }
+                CommandBufferItem::PushDebugMarker(name) => {
+                    scope.submit(std::mem::take(&mut commands));
+                    scope.push_debug_marker(name);
+                }
+                CommandBufferItem::PopDebugMarker => {
+                    scope.submit(std::mem::take(&mut commands));
+                    scope.pop_debug_marker();
+                }
+            }
+        }
+        scope.submit(commands);
+    }
+}
+
+impl<'cmd, 'scope> Default for CommandBuffer<'cmd, 'scope> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Device commands recorded once and submitted any number of times, for
+/// command sequences that do not change between frames.
+///
+/// Only commands that borrow nothing, such as kernel dispatches, can be
+/// recorded. The resources they use are kept alive as long as the recording.
+#[derive(Default)]
+pub struct RecordedCommands {
+    commands: Vec<(api::Command, ResourceTracker)>,
+}
+
+impl RecordedCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, command: Command<'static, 'static>) -> &mut Self {
+        assert!(
+            command.callback.is_none(),
+            "commands with callbacks cannot be replayed"
+        );
+        self.commands
+            .push((command.inner, command.resource_tracker));
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+    /// Submits the recorded commands to `scope` in order.
+    pub fn replay<'a>(&self, scope: &Scope<'a>) {
+        scope.submit(self.commands.iter().map(|(inner, rt)| Command {
+            inner: *inner,
+            marker: PhantomData,
+            callback: None,
+            resource_tracker: rt.clone(),
+        }));
+    }
+}
+
 impl Scope<'static> {
     pub fn detach(self) {
         self.synchronized.set(true);
@@ -1133,6 +2054,7 @@ impl Stream {
             marker: PhantomData {},
             synchronized: Cell::new(false),
             resource_tracker: RefCell::new(ResourceTracker::new()),
+            debug_markers: RefCell::new(Vec::new()),
         }
     }
     #[inline]
@@ -1143,6 +2065,47 @@ impl Stream {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle()
     }
+    /// The priority hint this stream was created with. The default stream is
+    /// [`Priority::Normal`].
+    #[inline]
+    pub fn priority(&self) -> Priority {
+        self.handle.priority()
+    }
+    /// Signals `event` with the ticket after [`Event::last_signaled`] once
+    /// the commands submitted to this stream so far complete, and returns
+    /// the ticket. The host does not wait.
+    pub fn signal(&self, event: &Event) -> u64 {
+        let ticket = event.handle.last_signaled.fetch_add(1, Ordering::SeqCst) + 1;
+        self.handle
+            .device()
+            .signal_event(event.handle(), self.handle(), ticket);
+        ticket
+    }
+    /// Submits `commands` and returns without waiting for them.
+    ///
+    /// Unlike a [`Scope`], nothing synchronizes the stream afterwards, so
+    /// work on several streams can run concurrently and be joined with
+    /// [`Completion::wait`].
+    pub fn submit_async(
+        &self,
+        commands: impl IntoIterator<Item = Command<'static, 'static>>,
+    ) -> Completion {
+        let event = self.device.create_event();
+        let scope: Scope<'static> = self.scope();
+        scope.submit(commands);
+        scope.detach();
+        let ticket = self.signal(&event);
+        Completion { event, ticket }
+    }
+    /// Makes the commands submitted to this stream from now on wait until
+    /// `event` is signaled with [`Event::last_signaled`]. The wait happens on
+    /// the device; the host does not wait.
+    pub fn wait(&self, event: &Event) -> &Self {
+        self.handle
+            .device()
+            .wait_event(event.handle(), self.handle(), event.last_signaled());
+        self
+    }
 }
 
 struct CommandCallbackCtx<'cmd, 'scope, F: FnOnce() + Send + 'static> {
@@ -1191,9 +2154,38 @@ impl<'cmd, 'scope> Command<'cmd, 'scope> {
     }
 }
 
+impl Command<'static, 'static> {
+    /// Converts `src` into the format of `dst` on device.
+    /// Both textures must be read as floats (float, unorm and snorm formats)
+    /// and have the same size.
+    pub fn convert_tex<S, D>(src: &Tex2dView<S>, dst: &Tex2dView<D>) -> Self
+    where
+        S: IoTexel<RwType = Float4>,
+        D: IoTexel<RwType = Float4>,
+    {
+        assert!(
+            src.device == dst.device,
+            "Cannot convert between textures on different devices"
+        );
+        assert_eq!(src.size(), dst.size());
+        let device = &src.device;
+        let key = format!("convert_tex/{:?}/{:?}", src.storage, dst.storage);
+        let kernel = device.shared_kernel(key, || {
+            Kernel::<fn(Tex2d<Float4>, Tex2d<Float4>)>::new(device, &|src, dst| {
+                let p = dispatch_id().xy();
+                dst.write(p, src.read(p));
+            })
+        });
+        let [w, h, _] = dst.size();
+        kernel.dispatch_async([w, h, 1], &src.as_float4(), &dst.as_float4())
+    }
+}
+
 pub(crate) struct AsyncShaderArtifact {
     shader: Option<api::CreatedShaderInfo>,
+    seen_before: bool,
     // strange naming, huh?
+    // both keep the strings the shader options point to alive
     #[allow(dead_code)]
     name: Arc<CString>,
     #[allow(dead_code)]
@@ -1202,7 +2194,20 @@ pub(crate) struct AsyncShaderArtifact {
 
 pub(crate) enum ShaderArtifact {
     Async(Arc<(Mutex<AsyncShaderArtifact>, Condvar)>),
-    Sync(api::CreatedShaderInfo),
+    Sync(api::CreatedShaderInfo, bool),
+    /// A shader owned by the device, see [`Device::shared_kernel`].
+    Shared(Arc<api::Shader>, bool),
+}
+
+impl ShaderArtifact {
+    /// See [`Kernel::seen_before`]. Must only be called once an asynchronous
+    /// compilation has finished.
+    fn seen_before(&self) -> bool {
+        match self {
+            ShaderArtifact::Sync(_, seen) | ShaderArtifact::Shared(_, seen) => *seen,
+            ShaderArtifact::Async(artifact) => artifact.0.lock().seen_before,
+        }
+    }
 }
 
 impl AsyncShaderArtifact {
@@ -1212,10 +2217,15 @@ impl AsyncShaderArtifact {
         options: api::ShaderOption,
         name: Arc<CString>,
         native_include: Arc<CString>,
+        // the name reported to the progress callback, `name` may be a path
+        progress_name: String,
+        cache_key: u64,
+        cache_dir: Option<PathBuf>,
     ) -> Arc<(Mutex<AsyncShaderArtifact>, Condvar)> {
         let artifact = Arc::new((
             Mutex::new(AsyncShaderArtifact {
                 shader: None,
+                seen_before: false,
                 name,
                 native_include,
             }),
@@ -1224,10 +2234,28 @@ impl AsyncShaderArtifact {
         {
             let artifact = artifact.clone();
             rayon::spawn(move || {
+                let progress = device.inner.compile_progress.read().clone();
+                let name = progress_name.as_str();
+                if let Some(progress) = &progress {
+                    progress(name, CompileState::Started);
+                }
                 let shader = device.inner.create_shader(&kernel, &options);
+                let seen_before = options.enable_cache
+                    && cache::record_cache_entry(
+                        &device,
+                        cache_dir.as_deref(),
+                        api::Shader(shader.resource.handle),
+                        cache_key,
+                    );
+                // report before publishing the shader, so that the event has
+                // fired once `ensure_ready` returns
+                if let Some(progress) = &progress {
+                    progress(name, CompileState::Finished);
+                }
                 {
                     let mut artifact = artifact.0.lock();
                     artifact.shader = Some(shader);
+                    artifact.seen_before = seen_before;
                 }
                 artifact.1.notify_all();
             });
@@ -1242,12 +2270,22 @@ pub struct RawKernel {
     #[allow(dead_code)]
     pub(crate) resource_tracker: ResourceTracker,
     pub(crate) module: CArc<KernelModule>,
+    pub(crate) options: KernelBuildOptions,
+    pub(crate) cache_key: u64,
+    /// The directory set with [`Device::set_cache_dir`] when the kernel was
+    /// compiled, if any.
+    pub(crate) cache_dir: Option<PathBuf>,
+    pub(crate) asserts: Option<Arc<DeviceAsserts>>,
 }
 
 impl Drop for RawKernel {
     fn drop(&mut self) {
-        let shader = self.unwrap();
-        self.device.inner.destroy_shader(shader);
+        // shared shaders are destroyed by the device, see
+        // `SharedKernels::retire`
+        if !matches!(self.artifact, ShaderArtifact::Shared(..)) {
+            let shader = self.unwrap();
+            self.device.inner.destroy_shader(shader);
+        }
     }
 }
 
@@ -1491,7 +2529,8 @@ impl_kernel_arg_for_tuple!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 T14 T15
 impl RawKernel {
     fn unwrap(&self) -> api::Shader {
         match &self.artifact {
-            ShaderArtifact::Sync(shader) => api::Shader(shader.resource.handle),
+            ShaderArtifact::Sync(shader, _) => api::Shader(shader.resource.handle),
+            ShaderArtifact::Shared(shader, _) => **shader,
             ShaderArtifact::Async(artifact) => {
                 let condvar = &artifact.1;
                 let mut artifact = artifact.0.lock();
@@ -1509,6 +2548,16 @@ impl RawKernel {
         args: KernelArgEncoder,
         dispatch_size: [u32; 3],
     ) -> Command<'static, 'static> {
+        if let Some(max_threads) = self.options.max_dispatch_threads {
+            let threads = dispatch_size.iter().map(|&x| x as usize).product::<usize>();
+            assert!(
+                threads <= max_threads,
+                "dispatch of {:?} ({} threads) exceeds the {} elements of the buffer the kernel is sized over",
+                dispatch_size,
+                threads,
+                max_threads
+            );
+        }
         let mut rt = ResourceTracker::new();
         rt.add(Arc::new(args.uniform_data));
         rt.add(self.clone());
@@ -1673,6 +2722,9 @@ pub struct RawKernelDef {
     pub(crate) module: CArc<KernelModule>,
     #[allow(dead_code)]
     pub(crate) resource_tracker: ResourceTracker,
+    pub(crate) asserts: Option<Arc<DeviceAsserts>>,
+    /// Number of distinct callables the kernel calls directly.
+    pub(crate) num_callables: usize,
 }
 
 impl RawKernelDef {
@@ -1680,6 +2732,10 @@ impl RawKernelDef {
     pub fn kernel_module(&self) -> &KernelModule {
         self.module.as_ref()
     }
+    #[doc(hidden)]
+    pub fn num_callables(&self) -> usize {
+        self.num_callables
+    }
 }
 
 /// A kernel definition
@@ -1733,10 +2789,54 @@ unsafe impl<T: KernelSignature> Send for Kernel<T> {}
 unsafe impl<T: KernelSignature> Sync for Kernel<T> {}
 
 impl<T: KernelSignature> Kernel<T> {
+    /// Whether the kernel has finished compiling. Always true unless it was
+    /// compiled asynchronously.
+    pub fn is_ready(&self) -> bool {
+        match &self.inner.artifact {
+            ShaderArtifact::Sync(..) | ShaderArtifact::Shared(..) => true,
+            ShaderArtifact::Async(artifact) => artifact.0.lock().shader.is_some(),
+        }
+    }
+    /// A hash of the kernel's IR, the device and the build options affecting
+    /// code generation, identifying the kernel across runs.
+    pub fn cache_key(&self) -> u64 {
+        self.inner.cache_key
+    }
+    /// Returns the first [`device_assert!`](crate::device_assert) that
+    /// failed in the dispatches since the last check, and clears it. Waits
+    /// for the default stream.
+    pub fn check_asserts(&self) -> Result<(), DeviceAssertion> {
+        let asserts = match &self.inner.asserts {
+            Some(asserts) => asserts,
+            None => return Ok(()),
+        };
+        let state = asserts.state.copy_to_vec();
+        if state[0] == 0 {
+            return Ok(());
+        }
+        asserts.state.fill(0);
+        Err(DeviceAssertion {
+            message: asserts.messages[state[0] as usize - 1].clone(),
+            dispatch_id: [state[1], state[2], state[3]],
+        })
+    }
+    /// Whether a kernel with the same [`Kernel::cache_key`] was compiled into
+    /// [`Kernel::cache_dir`] before, e.g. by an earlier run, according to
+    /// records the crate keeps there. Waits for an asynchronously compiled
+    /// kernel.
+    ///
+    /// This does not tell whether the backend loaded a binary rather than
+    /// compiling: the backend keeps its own cache, keyed on the code it
+    /// generates, and does not report hits.
+    pub fn seen_before(&self) -> bool {
+        self.inner.unwrap();
+        self.inner.artifact.seen_before()
+    }
+    /// The directory of the persistent shader cache the kernel was compiled
+    /// with, if the backend has one.
     pub fn cache_dir(&self) -> Option<PathBuf> {
-        let handle = self.inner.unwrap();
-        let device = &self.inner.device;
-        device.inner.shader_cache_dir(handle)
+        let dir = self.inner.cache_dir.as_deref();
+        cache::shader_cache_dir(&self.inner.device, dir, self.inner.unwrap())
     }
     pub fn dump(&self) -> String {
         ir::debug::dump_ir_human_readable(&self.inner.module.module)
@@ -1758,6 +2858,119 @@ impl<T: KernelSignature> Kernel<T> {
     pub fn wait_for_compile(&self) {
         self.inner.unwrap();
     }
+    /// Compiles this kernel for `device` with the same build options,
+    /// reusing the recorded IR instead of tracing the kernel again.
+    ///
+    /// On the device of this kernel, the compiled shader itself is reused.
+    /// On another device of the same backend, the kernel has the same
+    /// [`Kernel::cache_key`], so with the cache enabled the backend loads the
+    /// shader from its persistent cache rather than compiling it again.
+    ///
+    /// Captured resources belong to the device they were created on, so the
+    /// kernel must take all its resources as arguments.
+    pub fn clone_for(&self, device: &Device) -> Kernel<T> {
+        if *device == self.inner.device {
+            return Kernel {
+                inner: self.inner.clone(),
+                _marker: PhantomData,
+            };
+        }
+        assert!(
+            self.inner.module.captures.is_empty(),
+            "Kernel::clone_for: kernel captures resources of its device; pass them as arguments instead"
+        );
+        let def = KernelDef {
+            inner: RawKernelDef {
+                device: Some(device.clone()),
+                module: self.inner.module.clone(),
+                resource_tracker: ResourceTracker::new(),
+                asserts: None,
+                num_callables: 0,
+            },
+            _marker: PhantomData,
+        };
+        device.compile_kernel_def_with_options(&def, self.inner.options.clone())
+    }
+    /// Reads back the contents of every buffer captured by the kernel.
+    fn captured_buffer_contents(&self) -> Vec<Vec<u8>> {
+        let mut seen = HashSet::new();
+        self.inner
+            .module
+            .captures
+            .iter()
+            .filter_map(|c| match &c.binding {
+                ir::Binding::Buffer(b) if seen.insert(b.handle) => Some(b),
+                _ => None,
+            })
+            .map(|b| {
+                let mut data = vec![0u8; b.size];
+                let command = Command {
+                    inner: api::Command::BufferDownload(api::BufferDownloadCommand {
+                        buffer: api::Buffer(b.handle),
+                        offset: b.offset as usize,
+                        size: b.size,
+                        data: data.as_mut_ptr(),
+                    }),
+                    marker: PhantomData,
+                    resource_tracker: ResourceTracker::new(),
+                    callback: None,
+                };
+                submit_default_stream_and_sync(&self.inner.device, [command]);
+                data
+            })
+            .collect()
+    }
+    /// Saves the current contents of the buffers captured by the kernel to
+    /// `path`, as golden data for [`Kernel::verify_io`]. Call it after a
+    /// dispatch to record the inputs and outputs of that run.
+    ///
+    /// Buffers passed as kernel arguments are not recorded.
+    pub fn record_io(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = vec![];
+        for data in self.captured_buffer_contents() {
+            file.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            file.extend_from_slice(&data);
+        }
+        std::fs::write(path, file)
+    }
+    /// Checks that the buffers captured by the kernel hold exactly the data
+    /// saved by [`Kernel::record_io`] at `path`. Repeating the recorded run
+    /// and verifying catches nondeterminism such as races and reads of
+    /// uninitialized memory.
+    ///
+    /// Data that differs from the recording is reported as an error of kind
+    /// [`std::io::ErrorKind::InvalidData`], naming the first differing
+    /// buffer and byte.
+    pub fn verify_io(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let golden = std::fs::read(path.as_ref())?;
+        let path = path.as_ref().display();
+        let mismatch = |msg: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", path, msg),
+            )
+        };
+        let mut golden = golden.as_slice();
+        for (i, data) in self.captured_buffer_contents().iter().enumerate() {
+            if golden.len() < 8 {
+                return Err(mismatch("fewer buffers recorded".to_string()));
+            }
+            let (len, rest) = golden.split_at(8);
+            let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+            if len != data.len() || rest.len() < len {
+                return Err(mismatch(format!("buffer {} size differs", i)));
+            }
+            let (expected, rest) = rest.split_at(len);
+            if let Some(offset) = expected.iter().zip(data).position(|(a, b)| a != b) {
+                return Err(mismatch(format!("buffer {} differs at byte {}", i, offset)));
+            }
+            golden = rest;
+        }
+        if !golden.is_empty() {
+            return Err(mismatch("more buffers recorded".to_string()));
+        }
+        Ok(())
+    }
 }
 
 // A trait signifying that this argument can be used in place of an argument of type `Self::T`.
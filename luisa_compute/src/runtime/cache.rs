@@ -0,0 +1,365 @@
+use super::*;
+
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+pub(crate) const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a. Unlike [`std::hash::DefaultHasher`], the result is
+/// stable across processes and compiler versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+        // separate consecutive fields
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(FNV_PRIME);
+    }
+}
+
+/// Key of a kernel across runs, see [`Kernel::cache_key`]: a hash of the
+/// generated IR, the device it is compiled for and the options affecting
+/// code generation.
+pub(crate) fn kernel_cache_key(
+    device: &Device,
+    module: &KernelModule,
+    options: &KernelBuildOptions,
+) -> u64 {
+    let mut h = Fnv1a::new();
+    h.write(ir::debug::dump_ir_human_readable(&module.module).as_bytes());
+    h.write(device.name().as_bytes());
+    h.write(&[
+        options.enable_debug_info as u8,
+        options.enable_optimization as u8,
+        options.enable_fast_math as u8,
+    ]);
+    h.write(&options.max_registers.to_le_bytes());
+    h.write(options.native_include.as_deref().unwrap_or("").as_bytes());
+    h.0
+}
+
+/// The persistent cache directory of `shader`: `cache_dir`, the directory
+/// set with [`Device::set_cache_dir`] when it was compiled, or the backend's.
+pub(crate) fn shader_cache_dir(
+    device: &Device,
+    cache_dir: Option<&Path>,
+    shader: api::Shader,
+) -> Option<PathBuf> {
+    match cache_dir {
+        Some(dir) => Some(dir.to_path_buf()),
+        None => device.inner.shader_cache_dir(shader),
+    }
+}
+
+/// Subdirectory of the shader cache directory holding the records of
+/// [`record_cache_entry`], apart from the backend's own files.
+const RECORDS_DIR: &str = "luisa_compute_rs";
+
+/// Records that the kernel with `key` has been compiled into the cache
+/// directory of `shader`, see [`shader_cache_dir`]. Returns whether it had
+/// been recorded before, see [`Kernel::seen_before`].
+pub(crate) fn record_cache_entry(
+    device: &Device,
+    cache_dir: Option<&Path>,
+    shader: api::Shader,
+    key: u64,
+) -> bool {
+    let dir = match shader_cache_dir(device, cache_dir, shader) {
+        Some(dir) => dir.join(RECORDS_DIR),
+        None => return false,
+    };
+    let entry = dir.join(format!("{:016x}.key", key));
+    if entry.exists() {
+        return true;
+    }
+    // a failed write only costs a reported miss on the next run
+    let _ = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&entry, []));
+    false
+}
+
+/// The arguments of `module`, e.g. `buffer<...>, value<...>`, for checking
+/// that a saved kernel is loaded with the same signature. Unlike
+/// [`std::any::type_name`], it comes from the IR, so it does not change with
+/// the compiler version.
+fn kernel_signature(module: &KernelModule) -> String {
+    let args = module.args.iter().map(|arg| {
+        let kind = match arg.get().instruction.as_ref() {
+            ir::Instruction::Buffer => "buffer",
+            ir::Instruction::Texture2D => "tex2d",
+            ir::Instruction::Texture3D => "tex3d",
+            ir::Instruction::Bindless => "bindless",
+            ir::Instruction::Accel => "accel",
+            _ => "value",
+        };
+        format!("{}<{:?}>", kind, **arg.type_())
+    });
+    args.collect::<Vec<_>>().join(", ")
+}
+
+/// Error loading a kernel saved by [`Kernel::save`].
+#[derive(Debug)]
+pub enum KernelLoadError {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file is not a saved kernel.
+    Malformed,
+    /// The kernel was saved for a device with another backend.
+    DeviceMismatch { saved: String, device: String },
+    /// The kernel was saved with another argument signature.
+    SignatureMismatch { saved: String, expected: String },
+    /// The recorded kernel differs from the saved one, e.g. because its code
+    /// changed since it was saved.
+    Stale,
+}
+
+impl std::fmt::Display for KernelLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KernelLoadError::Io(err) => write!(f, "failed to read saved kernel: {}", err),
+            KernelLoadError::Malformed => write!(f, "not a saved kernel"),
+            KernelLoadError::DeviceMismatch { saved, device } => write!(
+                f,
+                "kernel saved for device `{}` cannot be loaded on device `{}`",
+                saved, device
+            ),
+            KernelLoadError::SignatureMismatch { saved, expected } => write!(
+                f,
+                "kernel saved with signature `{}` cannot be loaded as `{}`",
+                saved, expected
+            ),
+            KernelLoadError::Stale => write!(f, "saved kernel differs from the recorded one"),
+        }
+    }
+}
+
+impl std::error::Error for KernelLoadError {}
+
+impl<T: KernelSignature> Kernel<T> {
+    /// Saves what [`Device::load_kernel`] needs to find the compiled kernel
+    /// in the persistent shader cache again: its signature, cache key, name
+    /// and the build options affecting code generation. The IR is saved as
+    /// well, for [`Context::preload_cache`](crate::Context::preload_cache)
+    /// to compile the kernel before it is recorded.
+    ///
+    /// The backend can only create a kernel from its IR, so the compiled
+    /// binary itself stays in [`Kernel::cache_dir`]; ship that directory
+    /// along with the saved file.
+    ///
+    /// Fails with [`std::io::ErrorKind::InvalidInput`] if the kernel was
+    /// compiled without the persistent cache, as there is no binary to find.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let options = &self.inner.options;
+        if !options.enable_cache {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the kernel was compiled without the persistent cache",
+            ));
+        }
+        let file = serde_json::json!({
+            "device": self.inner.device.name(),
+            "signature": kernel_signature(&self.inner.module),
+            "cache_key": format!("{:016x}", self.inner.cache_key),
+            "name": options.name,
+            "enable_debug_info": options.enable_debug_info,
+            "enable_optimization": options.enable_optimization,
+            "enable_fast_math": options.enable_fast_math,
+            "max_registers": options.max_registers,
+            "native_include": options.native_include,
+            "module": ir::serialize::serialize_kernel_module(&self.inner.module),
+        });
+        std::fs::write(path, serde_json::to_string_pretty(&file).unwrap())
+    }
+}
+
+/// A kernel file written by [`Kernel::save`].
+#[derive(Clone)]
+pub(crate) struct SavedKernel {
+    device: String,
+    signature: String,
+    cache_key: u64,
+    options: KernelBuildOptions,
+    module: CArc<KernelModule>,
+}
+
+impl SavedKernel {
+    fn read(path: &Path) -> Result<Self, KernelLoadError> {
+        let file = std::fs::read(path).map_err(KernelLoadError::Io)?;
+        let file: serde_json::Value =
+            serde_json::from_slice(&file).map_err(|_| KernelLoadError::Malformed)?;
+        let string = |key: &str| file[key].as_str().ok_or(KernelLoadError::Malformed);
+        let flag = |key: &str| file[key].as_bool().ok_or(KernelLoadError::Malformed);
+        let cache_key = u64::from_str_radix(string("cache_key")?, 16)
+            .map_err(|_| KernelLoadError::Malformed)?;
+        let options = KernelBuildOptions {
+            enable_debug_info: flag("enable_debug_info")?,
+            enable_optimization: flag("enable_optimization")?,
+            enable_fast_math: flag("enable_fast_math")?,
+            max_registers: file["max_registers"]
+                .as_u64()
+                .ok_or(KernelLoadError::Malformed)? as u32,
+            name: file["name"].as_str().map(str::to_string),
+            native_include: file["native_include"].as_str().map(str::to_string),
+            enable_cache: true,
+            ..Default::default()
+        };
+        let module = serde_json::from_value(file["module"].clone())
+            .map_err(|_| KernelLoadError::Malformed)?;
+        Ok(Self {
+            device: string("device")?.to_string(),
+            signature: string("signature")?.to_string(),
+            cache_key,
+            options,
+            module: CArc::new(ir::serialize::deserialize_kernel_module(&module)),
+        })
+    }
+}
+
+/// Kernels registered by [`Context::preload_cache`], and the devices of
+/// the context that compile them.
+///
+/// [`Context::preload_cache`]: crate::Context::preload_cache
+#[derive(Default)]
+pub(crate) struct PreloadedKernels {
+    kernels: Mutex<Vec<SavedKernel>>,
+    devices: Mutex<Vec<Weak<DeviceHandle>>>,
+}
+
+impl PreloadedKernels {
+    /// Compiles the kernels preloaded so far for `device`, and those
+    /// preloaded later on.
+    pub(crate) fn add_device(&self, device: &Device) {
+        self.devices.lock().push(Arc::downgrade(&device.inner));
+        let kernels = self.kernels.lock().clone();
+        for kernel in &kernels {
+            device.preload_kernel(kernel);
+        }
+    }
+}
+
+/// Key of a preloaded kernel in [`Device::shared_kernel`]'s table.
+fn preloaded_key(cache_key: u64) -> String {
+    format!("preload/{:016x}", cache_key)
+}
+
+impl crate::Context {
+    /// Compiles every kernel saved with [`Kernel::save`] in `dir` for the
+    /// devices of this context, and returns how many were found.
+    ///
+    /// All files ending in `.json` must be saved kernels; the first one
+    /// that is not fails the whole preload, before anything is compiled.
+    /// Each kernel is compiled for every device of the backend it was saved
+    /// for, including devices created afterwards, which compile it on
+    /// creation. Shipping the shader cache next to the files (see
+    /// [`Kernel::cache_dir`]) lets the backend load the binaries instead.
+    ///
+    /// Recording one of these kernels later, with the options it was saved
+    /// with, reuses the compiled shader: nothing is compiled mid-session.
+    pub fn preload_cache(&self, dir: impl AsRef<Path>) -> Result<usize, KernelLoadError> {
+        let mut saved = Vec::new();
+        for entry in std::fs::read_dir(dir).map_err(KernelLoadError::Io)? {
+            let path = entry.map_err(KernelLoadError::Io)?.path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                saved.push(SavedKernel::read(&path)?);
+            }
+        }
+        self.preloaded.kernels.lock().extend(saved.iter().cloned());
+        let devices = self.preloaded.devices.lock().clone();
+        for device in devices.iter().filter_map(Weak::upgrade) {
+            let device = Device { inner: device };
+            for kernel in &saved {
+                device.preload_kernel(kernel);
+            }
+        }
+        Ok(saved.len())
+    }
+}
+
+impl Device {
+    /// Compiles `saved` into the shared kernels of the device, unless it
+    /// was saved for another backend or is already there.
+    fn preload_kernel(&self, saved: &SavedKernel) {
+        let key = preloaded_key(saved.cache_key);
+        let compiled = self.inner.shared_kernels.lock().kernels.contains_key(&key);
+        if saved.device != self.name() || compiled {
+            return;
+        }
+        let cache_dir = self.inner.cache_dir.read().clone();
+        let (option, _name, _native_include) =
+            self.shader_option(&saved.options, saved.cache_key, cache_dir.as_deref());
+        let shader = self.inner.create_shader(&saved.module, &option);
+        let shader = api::Shader(shader.resource.handle);
+        let seen_before = record_cache_entry(self, cache_dir.as_deref(), shader, saved.cache_key);
+        let kernel = SharedKernel {
+            shader: Arc::new(shader),
+            seen_before,
+            module: saved.module.clone(),
+            // nothing is captured, the kernel lives as long as the device
+            captures: ResourceTracker::new(),
+            options: saved.options.clone(),
+            cache_key: saved.cache_key,
+            cache_dir,
+            last_used: 0,
+        };
+        self.inner.shared_kernels.lock().insert(self, key, kernel);
+    }
+    /// The kernel recorded as `k` with `cache_key`, if it was preloaded.
+    pub(crate) fn preloaded_kernel<S: KernelSignature>(
+        &self,
+        k: &KernelDef<S>,
+        cache_key: u64,
+    ) -> Option<Kernel<S>> {
+        let mut shared_kernels = self.inner.shared_kernels.lock();
+        let shared = shared_kernels.get(&preloaded_key(cache_key))?;
+        Some(Kernel {
+            inner: Arc::new(RawKernel {
+                device: self.clone(),
+                artifact: ShaderArtifact::Shared(shared.shader.clone(), shared.seen_before),
+                module: k.inner.module.clone(),
+                resource_tracker: k.inner.resource_tracker.clone(),
+                options: shared.options.clone(),
+                cache_key,
+                cache_dir: shared.cache_dir.clone(),
+                asserts: k.inner.asserts.clone(),
+            }),
+            _marker: PhantomData,
+        })
+    }
+    /// Loads a kernel saved by [`Kernel::save`], recording it again with `f`.
+    ///
+    /// Recording is cheap compared to compilation, which is skipped: the
+    /// kernel is compiled with the saved options and name, so the backend
+    /// loads the binary from its persistent cache. Fails if the saved
+    /// kernel was created for another backend, with other argument types
+    /// than `S`, or from different code than `f`.
+    pub fn load_kernel<'a, S: KernelSignature2<'a>>(
+        &self,
+        path: impl AsRef<Path>,
+        f: S::Fn,
+    ) -> Result<Kernel<S>, KernelLoadError> {
+        let saved = SavedKernel::read(path.as_ref())?;
+        if saved.device != self.name() {
+            return Err(KernelLoadError::DeviceMismatch {
+                saved: saved.device,
+                device: self.name(),
+            });
+        }
+        let mut builder = KernelBuilder::new(Some(self.clone()), true);
+        let def = KernelBuildFn::build_kernel(&f, &mut builder);
+        let signature = kernel_signature(&def.inner.module);
+        if saved.signature != signature {
+            return Err(KernelLoadError::SignatureMismatch {
+                saved: saved.signature,
+                expected: signature,
+            });
+        }
+        if kernel_cache_key(self, &def.inner.module, &saved.options) != saved.cache_key {
+            return Err(KernelLoadError::Stale);
+        }
+        Ok(self.compile_kernel_def_with_options(&def, saved.options))
+    }
+}
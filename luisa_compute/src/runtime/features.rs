@@ -0,0 +1,120 @@
+use super::*;
+
+/// Shader features supported by a device. See [`Device::features`].
+///
+/// `None` means it is not known whether the backend supports the feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Features {
+    /// Acceleration structures and ray queries.
+    pub ray_tracing: Option<bool>,
+    /// `f16` arithmetic in kernels.
+    pub f16: Option<bool>,
+    /// `f64` arithmetic in kernels.
+    pub f64: Option<bool>,
+    /// Atomic operations on 64-bit integers.
+    pub int64_atomics: Option<bool>,
+    /// Warp-level operations such as [`warp_active_sum`](crate::lang::functions::warp_active_sum).
+    pub subgroup_ops: Option<bool>,
+    /// Block-shared memory and [`sync_block`](crate::lang::functions::sync_block).
+    pub shared_memory: Option<bool>,
+    /// Bindless arrays.
+    pub bindless: Option<bool>,
+    /// Device-side printing with [`device_log!`](crate::device_log).
+    pub printf: Option<bool>,
+}
+
+impl Features {
+    fn query(device: &Device) -> Self {
+        // the backends do not report their features, so they are listed per
+        // backend here
+        let all = |v| Self {
+            ray_tracing: v,
+            f16: v,
+            f64: v,
+            int64_atomics: v,
+            subgroup_ops: v,
+            shared_memory: v,
+            bindless: v,
+            printf: v,
+        };
+        match device.name().as_str() {
+            // the cpu backend runs blocks of a single thread, which still
+            // share memory with themselves; warp functions emulate a single
+            // lane there instead of using subgroup operations
+            "cpu" => Self {
+                f16: Some(false),
+                subgroup_ops: Some(false),
+                ..all(Some(true))
+            },
+            "cuda" => all(Some(true)),
+            "dx" => Self {
+                printf: None,
+                ..all(Some(true))
+            },
+            "metal" => Self {
+                f64: Some(false),
+                int64_atomics: Some(false),
+                printf: None,
+                ..all(Some(true))
+            },
+            _ => all(None),
+        }
+    }
+}
+
+/// Limits of a device. See [`Device::properties`].
+///
+/// `None` means the limit is not known for the backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Properties {
+    /// Largest buffer, in bytes.
+    pub max_buffer_size: Option<usize>,
+    /// Largest width and height of a 2D texture.
+    pub max_texture_2d_size: Option<u32>,
+    /// Largest width, height and depth of a 3D texture.
+    pub max_texture_3d_size: Option<u32>,
+    /// Largest number of threads in a block.
+    pub max_threads_per_block: Option<u32>,
+    /// Whether acceleration structures and ray queries are supported.
+    pub ray_tracing: Option<bool>,
+}
+
+impl Properties {
+    fn query(device: &Device) -> Self {
+        let (max_buffer_size, texture_2d, texture_3d, threads) = match device.name().as_str() {
+            // buffers and textures are host allocations, so the limits are
+            // those of the host allocator
+            "cpu" => (Some(isize::MAX as usize), Some(16384), Some(2048), Some(1)),
+            // limits fixed by the graphics API; buffer sizes depend on the
+            // adapter
+            "dx" | "metal" => (None, Some(16384), Some(2048), Some(1024)),
+            "cuda" => (None, None, None, Some(1024)),
+            _ => (None, None, None, None),
+        };
+        Self {
+            max_buffer_size,
+            max_texture_2d_size: texture_2d,
+            max_texture_3d_size: texture_3d,
+            max_threads_per_block: threads,
+            ray_tracing: device.features().ray_tracing,
+        }
+    }
+}
+
+impl Device {
+    /// Limits of this device.
+    ///
+    /// The limits are fixed per backend. On `cpu` they are those of the host,
+    /// and a block holds a single thread. Limits that depend on the GPU, such
+    /// as the largest buffer, are `None`.
+    pub fn properties(&self) -> Properties {
+        Properties::query(self)
+    }
+    /// Shader features supported by this device.
+    ///
+    /// The features are fixed per backend, as the backends do not report
+    /// them. Every feature is `None` on backends not listed, e.g. `remote`.
+    pub fn features(&self) -> Features {
+        Features::query(self)
+    }
+}
@@ -0,0 +1,81 @@
+use super::*;
+
+/// `N` sets of per-frame resources used in turn, with a fence per frame so
+/// that the host never gets more than `N` frames ahead of the device.
+/// See [`Device::create_frame_ring`].
+pub struct FrameRing<R, const N: usize> {
+    resources: [R; N],
+    /// Signaled with `k + 1` once frame `k` has finished.
+    event: Event,
+    /// Number of frames begun so far.
+    frames: u64,
+}
+
+/// A frame begun with [`FrameRing::begin_frame`]. Dropping it signals the
+/// frame's fence on the scope the frame was begun on, after the commands
+/// submitted to the scope so far.
+pub struct Frame<'a, 'b, R> {
+    resources: &'a R,
+    event: &'a Event,
+    scope: &'a Scope<'b>,
+    number: u64,
+}
+
+impl<R, const N: usize> FrameRing<R, N> {
+    /// Begins the next frame on `scope`. Blocks until the frame that last
+    /// used the same resources, `N` frames ago, has finished.
+    pub fn begin_frame<'a, 'b>(&'a mut self, scope: &'a Scope<'b>) -> Frame<'a, 'b, R> {
+        let number = self.frames;
+        if number >= N as u64 {
+            self.event.synchronize(number - N as u64 + 1);
+        }
+        self.frames += 1;
+        Frame {
+            resources: &self.resources[(number % N as u64) as usize],
+            event: &self.event,
+            scope,
+            number,
+        }
+    }
+    /// Whether frame `number` has finished on the device.
+    pub fn is_completed(&self, number: u64) -> bool {
+        self.event.is_completed(number + 1)
+    }
+    /// Number of frames begun so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl<'a, 'b, R> Frame<'a, 'b, R> {
+    /// The resources of this frame, not in use by any frame in flight.
+    pub fn resources(&self) -> &'a R {
+        self.resources
+    }
+    /// Index of the frame, counting from zero.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+}
+
+impl<'a, 'b, R> Drop for Frame<'a, 'b, R> {
+    fn drop(&mut self) {
+        self.scope.signal(self.event, self.number + 1);
+    }
+}
+
+impl Device {
+    /// Creates a ring of `N` sets of per-frame resources, the `i`-th created
+    /// by `f(i)`. See [`FrameRing::begin_frame`].
+    pub fn create_frame_ring<R, const N: usize>(
+        &self,
+        f: impl FnMut(usize) -> R,
+    ) -> FrameRing<R, N> {
+        assert!(N > 0, "a frame ring needs at least one set of resources");
+        FrameRing {
+            resources: std::array::from_fn(f),
+            event: self.create_event(),
+            frames: 0,
+        }
+    }
+}
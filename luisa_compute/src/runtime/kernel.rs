@@ -405,7 +405,7 @@ impl KernelBuilder {
         &mut self,
         body: impl FnOnce(&mut Self) -> R,
     ) -> RawCallable {
-        let ret = body(self);
+        let ret = crate::lang::autodiff::with_isolated_ad_context(|| body(self));
         let ret_type = ret._return();
         let (rt, cpu_custom_ops, captures) = self.collect_module_info();
         let ret = with_recorder(|r| {
@@ -512,6 +512,8 @@ impl KernelBuilder {
                     device: self.device.clone(),
                     resource_tracker: rt,
                     module: CArc::new(module),
+                    asserts: r.device_asserts.take().map(Arc::new),
+                    num_callables: r.callables.len(),
                 },
                 _marker: PhantomData,
             }
@@ -523,12 +525,17 @@ impl KernelBuilder {
 
 /// Build options for kernel compilation
 /// * `enable_debug_info`: enable debug info, default true on debug build
-/// * `enable_optimization`: enable optimization, default true
+/// * `enable_optimization`: default true, see [`OptLevel`]. Not passed to
+///   the backend, which always optimizes
 /// * `async_compile`: compile the kernel asynchronously
-/// * `enable_cache`: enable cache for the compiled kernel
-/// * `enable_fast_math`: enable fast math in the compiled kernel
+/// * `enable_cache`: enable the persistent cache for the compiled kernel,
+///   keyed by [`Kernel::cache_key`]
+/// * `enable_fast_math`: enable fast math in the compiled kernel, see
+///   [`MathMode`]
 /// * `name`: name of the compiled kernel. On CUDA backend, this is the name of
 ///   the generated PTX kernel
+/// * `name_by_cache_key`: with the cache enabled, name unnamed kernels
+///   `kernel_{key}` after their cache key, default false
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct KernelBuildOptions {
     pub enable_debug_info: bool,
@@ -543,11 +550,15 @@ pub struct KernelBuildOptions {
     /// measure time spent during compilation
     pub time_trace: bool,
     pub name: Option<String>,
+    pub name_by_cache_key: bool,
     ///  Include code written in the native shading language.
     ///  If provided, backend will include this string into the generated
     ///   shader code. This field is useful for interoperation with external callables.
     /// see also  [`ExternalCallable`]
     pub native_include: Option<String>,
+    /// Upper bound on the number of threads of a dispatch, checked by every
+    /// dispatch of the kernel. See [`KernelBuildOptions::sized_over`].
+    pub max_dispatch_threads: Option<usize>,
 }
 
 impl Default for KernelBuildOptions {
@@ -565,10 +576,112 @@ impl Default for KernelBuildOptions {
             max_registers: 0,
             time_trace: false,
             name: None,
+            name_by_cache_key: false,
             native_include: None,
+            max_dispatch_threads: None,
         }
     }
 }
+/// Floating-point math mode of a compiled kernel.
+///
+/// * `Fast`: the backend may reassociate and contract floating-point
+///   arithmetic (e.g. fuse `a * b + c` into an fma) and use approximate
+///   transcendentals such as `rsqrt`, `sqrt`, `exp`, `log`, `sin` and `cos`.
+///   This is the default.
+/// * `Strict`: arithmetic is evaluated as written, which keeps results,
+///   including autodiff gradients, reproducible across backends.
+///
+/// Integer arithmetic and explicit `mul_add`/`fma` calls are not affected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum MathMode {
+    Strict,
+    #[default]
+    Fast,
+}
+
+/// Optimization level of a compiled kernel.
+///
+/// * `Release`: the backend's default compilation. This is the default.
+/// * `Debug`: the same compilation with debug info turned on.
+///
+/// The backend interface has no optimization flag, so both levels are
+/// optimized by the backend compiler alike: `Debug` only sets
+/// [`KernelBuildOptions::enable_debug_info`], and
+/// [`KernelBuildOptions::enable_optimization`] is not passed to the
+/// backend.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum OptLevel {
+    Debug,
+    #[default]
+    Release,
+}
+
+impl KernelBuildOptions {
+    pub fn opt_level(&self) -> OptLevel {
+        if self.enable_optimization {
+            OptLevel::Release
+        } else {
+            OptLevel::Debug
+        }
+    }
+    pub fn with_opt_level(self, level: OptLevel) -> Self {
+        Self {
+            enable_optimization: level == OptLevel::Release,
+            enable_debug_info: level == OptLevel::Debug || self.enable_debug_info,
+            ..self
+        }
+    }
+    pub fn math_mode(&self) -> MathMode {
+        if self.enable_fast_math {
+            MathMode::Fast
+        } else {
+            MathMode::Strict
+        }
+    }
+    pub fn with_math_mode(self, mode: MathMode) -> Self {
+        Self {
+            enable_fast_math: mode == MathMode::Fast,
+            ..self
+        }
+    }
+    /// Occupancy hint in the spirit of CUDA's `__launch_bounds__`: the kernel
+    /// is launched with at most `max_threads_per_block` threads per block
+    /// and `min_blocks` blocks should fit on a multiprocessor at once.
+    ///
+    /// The backend interface only exposes a register cap, so the hint is
+    /// translated into `max_registers` assuming a 64K-entry register file
+    /// per multiprocessor. Backends that ignore `max_registers` (e.g. CPU)
+    /// ignore the hint as well.
+    pub fn with_launch_bounds(self, max_threads_per_block: u32, min_blocks: u32) -> Self {
+        assert!(
+            max_threads_per_block > 0 && min_blocks > 0,
+            "launch bounds must be positive"
+        );
+        const REGISTER_FILE_SIZE: u32 = 65536;
+        let threads = max_threads_per_block.saturating_mul(min_blocks);
+        // registers are allocated in units of 8 per thread, between 16 and 255
+        let cap = (REGISTER_FILE_SIZE / threads / 8 * 8).clamp(16, 255);
+        let max_registers = if self.max_registers == 0 {
+            cap
+        } else {
+            self.max_registers.min(cap)
+        };
+        Self {
+            max_registers,
+            ..self
+        }
+    }
+    /// Records that the kernel is sized over `buffer`, i.e. runs at most one
+    /// thread per element of it. Dispatching more threads than `buffer` has
+    /// elements then panics instead of reading or writing out of bounds.
+    pub fn sized_over<T: Value>(self, buffer: &BufferView<T>) -> Self {
+        Self {
+            max_dispatch_threads: Some(buffer.len),
+            ..self
+        }
+    }
+}
+
 pub trait CallableBuildFn<S: CallableSignature> {
     fn build_callable(&self, args: Option<Rc<dyn Any>>, builder: &mut KernelBuilder)
         -> RawCallable;
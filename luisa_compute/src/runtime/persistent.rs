@@ -0,0 +1,104 @@
+use super::*;
+
+/// A queue of work items consumed by a [`PersistentKernel`].
+/// See [`Device::create_work_queue`].
+pub struct WorkQueue<T: Value> {
+    items: Buffer<T>,
+    /// `[index of the next item to hand out, number of items]`
+    counters: Buffer<u32>,
+    len: usize,
+}
+
+impl<T: Value> WorkQueue<T> {
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.items.len()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Appends `items` to the queue.
+    pub fn push(&mut self, items: &[T]) {
+        let end = self.len + items.len();
+        assert!(
+            end <= self.capacity(),
+            "work queue overflow: {} items, capacity {}",
+            end,
+            self.capacity()
+        );
+        self.items.view(self.len..end).copy_from(items);
+        self.len = end;
+        self.counters.view(1..2).copy_from(&[end as u32]);
+    }
+    /// Removes all items.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.counters.fill(0);
+    }
+}
+
+/// A grid of long-lived workers processing the items of a [`WorkQueue`].
+/// See [`Device::create_persistent_kernel`].
+pub struct PersistentKernel<T: Value> {
+    kernel: Kernel<fn(Buffer<T>, Buffer<u32>)>,
+    workers: u32,
+}
+
+impl<T: Value> PersistentKernel<T> {
+    #[inline]
+    pub fn workers(&self) -> u32 {
+        self.workers
+    }
+    /// Processes every item of `queue`, waits for the workers to finish and
+    /// empties the queue.
+    pub fn dispatch(&self, queue: &mut WorkQueue<T>) {
+        if !queue.is_empty() {
+            self.kernel
+                .dispatch([self.workers, 1, 1], &queue.items, &queue.counters);
+        }
+        queue.clear();
+    }
+}
+
+impl Device {
+    /// Creates an empty [`WorkQueue`] holding up to `capacity` items.
+    pub fn create_work_queue<T: Value>(&self, capacity: usize) -> WorkQueue<T> {
+        WorkQueue {
+            items: self.create_buffer(capacity),
+            counters: self.create_buffer_from_slice(&[0, 0]),
+            len: 0,
+        }
+    }
+    /// Creates a kernel of `workers` threads that repeatedly take the next
+    /// item of a [`WorkQueue`] and call `f` on it, until the queue drains.
+    ///
+    /// A single launch processes all the items, which amortizes the launch
+    /// overhead of many small, heterogeneous tasks. Each item is processed
+    /// exactly once, in no particular order.
+    pub fn create_persistent_kernel<T: Value>(
+        &self,
+        workers: u32,
+        f: impl Fn(Expr<T>),
+    ) -> PersistentKernel<T> {
+        assert!(workers > 0, "persistent kernel without workers");
+        let kernel = Kernel::<fn(Buffer<T>, Buffer<u32>)>::new(
+            self,
+            &track!(|items, counters| {
+                let len = counters.read(1);
+                loop {
+                    let i = counters.atomic_fetch_add(0, 1u32);
+                    if i >= len {
+                        break;
+                    }
+                    f(items.read(i));
+                }
+            }),
+        );
+        PersistentKernel { kernel, workers }
+    }
+}
@@ -0,0 +1,40 @@
+use super::*;
+use crate::lang::functions::clock;
+
+/// A kernel that measures how long each of its threads runs.
+/// See [`Device::create_kernel_profiled`].
+pub struct ProfiledKernel {
+    kernel: Kernel<fn(Buffer<u64>)>,
+}
+
+impl ProfiledKernel {
+    /// Dispatches the kernel, waits for it and returns the clock cycles
+    /// taken by each thread, as measured with [`clock`], indexed by
+    /// `id.x + size.x * (id.y + size.y * id.z)`.
+    pub fn dispatch(&self, dispatch_size: [u32; 3]) -> Buffer<u64> {
+        let count = dispatch_size.iter().map(|&x| x as usize).product::<usize>();
+        let timings = self.kernel.inner.device.create_buffer::<u64>(count);
+        self.kernel.dispatch(dispatch_size, &timings);
+        timings
+    }
+}
+
+impl Device {
+    /// Records `f` like [`Kernel::new`], reading [`clock`] before and after
+    /// the body of every thread. Threads that leave early with
+    /// [`return_`] are not timed.
+    /// The timings are in clock cycles, see [`clock`].
+    pub fn create_kernel_profiled(&self, f: &dyn Fn()) -> ProfiledKernel {
+        let kernel = Kernel::<fn(Buffer<u64>)>::new(self, &|timings| {
+            let start = clock();
+            f();
+            let end = clock();
+            track! {
+                let id = dispatch_id();
+                let size = dispatch_size();
+                timings.write(id.x + size.x * (id.y + size.y * id.z), end - start);
+            }
+        });
+        ProfiledKernel { kernel }
+    }
+}
@@ -0,0 +1,103 @@
+use super::*;
+
+/// Recording state of a kernel built with [`Device::create_kernel_checked`].
+pub(crate) struct RaceCheck {
+    races: BufferView<u32>,
+    /// Buffers captured by the kernel: device, length and, once the kernel
+    /// writes to it, the buffer holding the id of the thread that wrote each
+    /// element.
+    buffers: HashMap<NodeRef, (Device, usize, Option<Buffer<u32>>)>,
+}
+
+impl RaceCheck {
+    fn new(races: BufferView<u32>) -> Self {
+        Self {
+            races,
+            buffers: HashMap::new(),
+        }
+    }
+    pub(crate) fn register(&mut self, buffer: NodeRef, device: &Device, len: usize) {
+        self.buffers
+            .entry(buffer)
+            .or_insert_with(|| (device.clone(), len, None));
+    }
+    fn owner(&mut self, buffer: NodeRef) -> Option<BufferView<u32>> {
+        let (device, len, owner) = self.buffers.get_mut(&buffer)?;
+        Some(
+            owner
+                .get_or_insert_with(|| device.create_buffer(*len))
+                .view(..),
+        )
+    }
+    fn into_owners(self) -> impl Iterator<Item = Buffer<u32>> {
+        self.buffers.into_values().filter_map(|(_, _, owner)| owner)
+    }
+}
+
+/// Instruments a non-atomic write of element `index` of `buffer`: the
+/// writing thread claims the element, and a claim by a different thread of
+/// the same dispatch is counted as a race.
+pub(crate) fn check_buffer_write(buffer: NodeRef, index: Expr<u64>) {
+    let check = with_recorder(|r| {
+        let check = r.race_check.as_mut()?;
+        Some((check.owner(buffer)?, check.races.clone()))
+    });
+    let (owner, races) = match check {
+        Some(check) => check,
+        None => return,
+    };
+    track! {
+        let id = dispatch_id();
+        let size = dispatch_size();
+        let thread = id.x + size.x * (id.y + size.y * id.z) + 1u32;
+        let prev = owner.var().atomic_exchange(index, thread);
+        if prev != 0u32 && prev != thread {
+            races.var().atomic_fetch_add(0, 1u32);
+        }
+    }
+}
+
+/// A kernel whose buffer writes are checked for races between threads.
+/// See [`Device::create_kernel_checked`].
+pub struct CheckedKernel {
+    kernel: Kernel<fn()>,
+    owners: Vec<Buffer<u32>>,
+    races: Buffer<u32>,
+}
+
+impl CheckedKernel {
+    /// Dispatches the kernel, waits for it and returns the number of writes
+    /// to an element that another thread of the same dispatch also wrote.
+    pub fn dispatch(&self, dispatch_size: [u32; 3]) -> u32 {
+        for owner in &self.owners {
+            owner.fill(0);
+        }
+        self.races.fill(0);
+        self.kernel.dispatch(dispatch_size);
+        self.races.copy_to_vec()[0]
+    }
+}
+
+impl Device {
+    /// Records `f` like [`Kernel::new`], instrumenting every non-atomic
+    /// buffer write to detect two threads writing the same element.
+    ///
+    /// This is a debugging aid: each checked write becomes an atomic
+    /// exchange on an auxiliary buffer. Only writes to buffers captured by
+    /// `f` are checked; kernel arguments and writes inside callables are not.
+    pub fn create_kernel_checked(&self, f: &dyn Fn()) -> CheckedKernel {
+        let races = self.create_buffer::<u32>(1);
+        let owners = RefCell::new(Vec::new());
+        let kernel = Kernel::<fn()>::new(self, &|| {
+            with_recorder(|r| r.race_check = Some(RaceCheck::new(races.view(..))));
+            f();
+            let check = with_recorder(|r| r.race_check.take()).unwrap();
+            owners.borrow_mut().extend(check.into_owners());
+        });
+        CheckedKernel {
+            kernel,
+            owners: owners.into_inner(),
+            races,
+        }
+    }
+}
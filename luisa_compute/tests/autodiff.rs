@@ -2,6 +2,7 @@ use std::ops::Range;
 
 use alias::*;
 use luisa::lang::autodiff::*;
+use luisa::lang::nn;
 use luisa::lang::types::vector::*;
 use luisa::prelude::*;
 use luisa_compute as luisa;
@@ -291,6 +292,15 @@ autodiff_1!(autodiff_tanh, -10.0..10.0, |x: Expr<f32>| x.tanh());
 
 autodiff_2!(autodiff_div, 1.0..10.0, |x: Expr<f32>, y: Expr<f32>| x / y);
 
+autodiff_2!(autodiff_atan2, 0.1..10.0, |y: Expr<f32>, x: Expr<f32>| y
+    .atan2(x));
+autodiff_2!(autodiff_atan2_third_quadrant, -10.0..-0.1, |y: Expr<
+    f32,
+>,
+                                                         x: Expr<
+    f32,
+>| y.atan2(x));
+
 autodiff_2!(autodiff_pow, 1.0..10.0, |x: Expr<f32>, y: Expr<f32>| x
     .powf(y));
 autodiff_3!(
@@ -298,6 +308,389 @@ autodiff_3!(
     0.0..1.0,
     |x: Expr<f32>, y: Expr<f32>, z: Expr<f32>| x.lerp(y, z)
 );
+autodiff_1!(autodiff_linear_step, 0.05..0.95, |x: Expr<f32>| x
+    .linear_step(0.0f32, 1.0f32));
+autodiff_1!(autodiff_smoother_step, 0.05..0.95, |x: Expr<f32>| x
+    .smoother_step(0.0f32, 1.0f32));
+autodiff_3!(
+    autodiff_smoother_step_edges,
+    0.0..1.0,
+    |x: Expr<f32>, y: Expr<f32>, z: Expr<f32>| (x * 0.5 + 0.25)
+        .smoother_step(y * 0.2, z * 0.2 + 0.8)
+);
+// smooth_step is C1, so crossing the edges is fine
+autodiff_1!(autodiff_smooth_step, -0.5..1.5, |x: Expr<f32>| x
+    .smooth_step(0.0f32, 1.0f32));
+autodiff_3!(
+    autodiff_smooth_step_edges,
+    -1.0..1.0,
+    |x: Expr<f32>, y: Expr<f32>, z: Expr<f32>| (x * 3.0).smooth_step(y - 1.5, z + 1.5)
+);
+
+autodiff_1!(autodiff_sigmoid, -20.0..20.0, |x: Expr<f32>| nn::sigmoid(x));
+autodiff_1!(autodiff_softplus, -9.0..9.0, |x: Expr<f32>| nn::softplus(x));
+autodiff_1!(autodiff_silu, -20.0..20.0, |x: Expr<f32>| nn::silu(x));
+autodiff_1!(autodiff_gelu, -10.0..10.0, |x: Expr<f32>| nn::gelu(x));
+
+#[test]
+fn autodiff_activations_large_magnitude() {
+    let device = get_device();
+    let xs = [-1000.0f32, -100.0, 100.0, 1000.0];
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let values: Buffer<Float3> = device.create_buffer(xs.len());
+    let grads: Buffer<Float3> = device.create_buffer(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let v = Float3::expr(nn::sigmoid(x), nn::softplus(x), nn::silu(x));
+                backward(v.x + v.y + v.z);
+                values.var().write(tid, v);
+            });
+            let g = Float3::expr(0.0f32, 0.0f32, 0.0f32).var();
+            autodiff(|| {
+                requires_grad(x);
+                backward(nn::sigmoid(x));
+                *g.x = gradient(x);
+            });
+            autodiff(|| {
+                requires_grad(x);
+                backward(nn::softplus(x));
+                *g.y = gradient(x);
+            });
+            autodiff(|| {
+                requires_grad(x);
+                backward(nn::silu(x));
+                *g.z = gradient(x);
+            });
+            grads.var().write(tid, g);
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let values = values.copy_to_vec();
+    let grads = grads.copy_to_vec();
+    for i in 0..xs.len() {
+        let (v, g) = (values[i], grads[i]);
+        let pos = xs[i] > 0.0;
+        assert_eq!(v.x, if pos { 1.0 } else { 0.0 }, "sigmoid({})", xs[i]);
+        assert_eq!(v.y, if pos { xs[i] } else { 0.0 }, "softplus({})", xs[i]);
+        assert_eq!(v.z, if pos { xs[i] } else { 0.0 }, "silu({})", xs[i]);
+        assert_eq!(g.x, 0.0, "sigmoid'({})", xs[i]);
+        assert_eq!(g.y, if pos { 1.0 } else { 0.0 }, "softplus'({})", xs[i]);
+        assert_eq!(g.z, if pos { 1.0 } else { 0.0 }, "silu'({})", xs[i]);
+    }
+}
+
+#[test]
+fn softplus_small_values() {
+    let device = get_device();
+    // 1 + exp(x) rounds to 1 for these, so ln(1 + exp(x)) would be 0
+    let xs = [-20.0f32, -30.0, -50.0, -80.0];
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let out: Buffer<Float2> = device.create_buffer(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let v = nn::softplus(x);
+                backward(v);
+                out.var().write(tid, Float2::expr(v, gradient(x)));
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let out = out.copy_to_vec();
+    for i in 0..xs.len() {
+        // softplus(x) and its derivative sigmoid(x) are both exp(x) here
+        let expected = (xs[i] as f64).exp();
+        let close = |a: f32| (a as f64 - expected).abs() <= 1e-5 * expected;
+        assert!(close(out[i].x), "softplus({})", xs[i]);
+        assert!(close(out[i].y), "softplus'({})", xs[i]);
+    }
+}
+
+#[test]
+fn autodiff_step_family_clamped_gradient() {
+    let device = get_device();
+    let xs = [-1.0f32, -0.5, 0.0, 1.0, 1.5, 2.0];
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let d_linear: Buffer<f32> = device.create_buffer(xs.len());
+    let d_smoother: Buffer<f32> = device.create_buffer(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                backward(x.linear_step(0.0f32, 1.0f32));
+                d_linear.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                backward(x.smoother_step(0.0f32, 1.0f32));
+                d_smoother.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let d_linear = d_linear.copy_to_vec();
+    let d_smoother = d_smoother.copy_to_vec();
+    for i in 0..xs.len() {
+        assert_eq!(d_linear[i], 0.0, "linear_step at {}", xs[i]);
+        assert_eq!(d_smoother[i], 0.0, "smoother_step at {}", xs[i]);
+    }
+}
+
+#[test]
+fn autodiff_lut_lerp() {
+    use luisa::lang::functions::lut_lerp;
+    const LUT: [f32; 5] = [0.0, 2.0, 1.0, 4.0, 4.0];
+    let device = get_device();
+    let n = 1024;
+    let xs: Vec<f32> = (0..n).map(|i| (i as f32 + 0.5) / n as f32).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let y: Buffer<f32> = device.create_buffer(n);
+    let dx: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let v = lut_lerp(&LUT, x);
+                y.var().write(tid, v);
+                backward(v);
+                dx.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let y = y.copy_to_vec();
+    let dx = dx.copy_to_vec();
+    for i in 0..n {
+        let t = xs[i] * 4.0;
+        let seg = t as usize;
+        let (a, b) = (LUT[seg], LUT[seg + 1]);
+        let expected = a + (b - a) * (t - seg as f32);
+        assert!((y[i] - expected).abs() < 1e-5, "x = {}", xs[i]);
+        assert!((dx[i] - (b - a) * 4.0).abs() < 1e-4, "x = {}", xs[i]);
+    }
+}
+
+#[test]
+fn autodiff_bary_interp() {
+    use luisa::lang::functions::bary_interp;
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let mut rand_f3 = || Float3::new(rng.gen(), rng.gen(), rng.gen());
+    let vs: Vec<[Float3; 3]> = (0..n).map(|_| [rand_f3(), rand_f3(), rand_f3()]).collect();
+    let bs: Vec<Float3> = (0..n)
+        .map(|_| {
+            let b = rand_f3();
+            let s = b.x + b.y + b.z;
+            Float3::new(b.x / s, b.y / s, b.z / s)
+        })
+        .collect();
+    let v: Buffer<[Float3; 3]> = device.create_buffer_from_slice(&vs);
+    let b: Buffer<Float3> = device.create_buffer_from_slice(&bs);
+    let dv0: Buffer<Float3> = device.create_buffer(n);
+    let db: Buffer<Float3> = device.create_buffer(n);
+    let dt: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let v = v.var().read(tid);
+            let b = b.var().read(tid);
+            let (v0, v1, v2) = (v[0], v[1], v[2]);
+            autodiff(|| {
+                requires_grad(v0);
+                requires_grad(b);
+                let p = bary_interp(v0, v1, v2, b);
+                backward(p.x + p.y + p.z);
+                dv0.var().write(tid, gradient(v0));
+                db.var().write(tid, gradient(b));
+            });
+            // scalar attributes, e.g. a texture coordinate
+            let t = v0.x;
+            autodiff(|| {
+                requires_grad(t);
+                backward(bary_interp(t, v1.x, v2.x, b));
+                dt.var().write(tid, gradient(t));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let dv0 = dv0.copy_to_vec();
+    let db = db.copy_to_vec();
+    let dt = dt.copy_to_vec();
+    let sum = |v: Float3| v.x + v.y + v.z;
+    for i in 0..n {
+        let [v0, v1, v2] = vs[i];
+        let b = bs[i];
+        assert_eq!(dv0[i].x, b.x);
+        assert_eq!(dv0[i].y, b.x);
+        assert_eq!(dv0[i].z, b.x);
+        assert_eq!(dt[i], b.x);
+        assert!((db[i].x - sum(v0)).abs() < 1e-5);
+        assert!((db[i].y - sum(v1)).abs() < 1e-5);
+        assert!((db[i].z - sum(v2)).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn autodiff_math_mode() {
+    let device = get_device();
+    let n = 1024;
+    let xs: Vec<f32> = (0..n).map(|i| 0.5 + 4.0 * i as f32 / n as f32).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let grad = |mode: MathMode| {
+        let dx: Buffer<f32> = device.create_buffer(n);
+        let kernel = Kernel::<fn()>::new_with_options(
+            &device,
+            KernelBuildOptions::default().with_math_mode(mode),
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let x = x.var().read(tid);
+                autodiff(|| {
+                    requires_grad(x);
+                    backward(x.sin() * x.exp() * x.rsqrt());
+                    dx.var().write(tid, gradient(x));
+                });
+            }),
+        );
+        kernel.dispatch([n as u32, 1, 1]);
+        dx.copy_to_vec()
+    };
+    let strict = grad(MathMode::Strict);
+    let fast = grad(MathMode::Fast);
+    for i in 0..n {
+        let x = xs[i] as f64;
+        let expected = x.exp() / x.sqrt() * (x.cos() + x.sin() - 0.5 * x.sin() / x);
+        let close = |v: f32, tol: f64| {
+            let err = (v as f64 - expected).abs();
+            err < tol || err / (expected.abs() + 1e-6) < tol
+        };
+        assert!(close(strict[i], 1e-4), "strict at x = {}: {}", x, strict[i]);
+        assert!(close(fast[i], 1e-2), "fast at x = {}: {}", x, fast[i]);
+    }
+}
+
+#[test]
+fn autodiff_repeated_reads_accumulate() {
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let xs: Vec<f32> = (0..n).map(|_| rng.gen_range(-2.0..2.0)).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let d_sum: Buffer<f32> = device.create_buffer(n);
+    let d_cube: Buffer<f32> = device.create_buffer(n);
+    let d_branches: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let z = x + x + x;
+                backward(z);
+                d_sum.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                backward(x * x * x);
+                d_cube.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                // x reaches z through two intermediate values that recombine
+                let a = x * 2.0;
+                let b = x + 1.0;
+                let z = a * b + a;
+                backward(z);
+                d_branches.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let d_sum = d_sum.copy_to_vec();
+    let d_cube = d_cube.copy_to_vec();
+    let d_branches = d_branches.copy_to_vec();
+    for i in 0..n {
+        let x = xs[i];
+        assert_eq!(d_sum[i], 3.0);
+        assert!((d_cube[i] - 3.0 * x * x).abs() < 1e-5, "x = {}", x);
+        // z = 2x(x + 1) + 2x, dz/dx = 4x + 4
+        assert!((d_branches[i] - (4.0 * x + 4.0)).abs() < 1e-5, "x = {}", x);
+    }
+}
+
+#[test]
+fn autodiff_custom_vjp() {
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let xs: Vec<f32> = (0..n).map(|_| rng.gen_range(-2.0..2.0)).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let y: Buffer<f32> = device.create_buffer(n);
+    let d_custom: Buffer<f32> = device.create_buffer(n);
+    let d_fd: Buffer<f32> = device.create_buffer(n);
+    let d_vec: Buffer<Float2> = device.create_buffer(n);
+    let forward = track!(|x: Expr<f32>| x * x.sin());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                // the adjoint is the negated derivative of the forward pass
+                let v = custom_vjp(x, forward, |x, g| -g * (x.sin() + x * x.cos()));
+                y.var().write(tid, v);
+                backward(v * 2.0);
+                d_custom.var().write(tid, gradient(x));
+            });
+            let h = 1e-2f32;
+            d_fd.var()
+                .write(tid, (forward(x + h) - forward(x - h)) / (2.0 * h));
+            let p = Float2::expr(x, 1.0);
+            autodiff(|| {
+                requires_grad(p);
+                let v = custom_vjp(p, |p| p.x * p.y, |p, g| g * Float2::expr(p.y, p.x) * 3.0);
+                backward(v);
+                d_vec.var().write(tid, gradient(p));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let y = y.copy_to_vec();
+    let d_custom = d_custom.copy_to_vec();
+    let d_fd = d_fd.copy_to_vec();
+    let d_vec = d_vec.copy_to_vec();
+    for i in 0..n {
+        let x = xs[i];
+        assert!((y[i] - x * x.sin()).abs() < 1e-5, "x = {}", x);
+        assert!(
+            (d_custom[i] + 2.0 * d_fd[i]).abs() < 1e-2,
+            "x = {}, custom = {}, fd = {}",
+            x,
+            d_custom[i],
+            d_fd[i]
+        );
+        assert!((d_vec[i].x - 3.0).abs() < 1e-5, "x = {}", x);
+        assert!((d_vec[i].y - 3.0 * x).abs() < 1e-5, "x = {}", x);
+    }
+}
 
 #[test]
 fn autodiff_vec3_reduce_add_manual() {
@@ -687,6 +1080,43 @@ fn autodiff_mat_det() {
         }),
     );
 }
+#[test]
+fn autodiff_mat4_det() {
+    autodiff_helper(
+        -2.0..2.0,
+        1024 * 1024,
+        16,
+        track!(|inputs| {
+            let col = |i: usize| {
+                Float4::expr(
+                    inputs[i * 4],
+                    inputs[i * 4 + 1],
+                    inputs[i * 4 + 2],
+                    inputs[i * 4 + 3],
+                )
+            };
+            let m = Mat4::expr(col(0), col(1), col(2), col(3));
+            m.determinant()
+        }),
+    );
+}
+#[test]
+fn autodiff_mat_inverse() {
+    autodiff_helper(
+        -1.0..1.0,
+        1024 * 1024,
+        9,
+        track!(|inputs| {
+            // diagonally dominant, hence well conditioned
+            let a = Float3::expr(inputs[0] + 3.0f32, inputs[1], inputs[2]);
+            let b = Float3::expr(inputs[3], inputs[4] + 3.0f32, inputs[5]);
+            let c = Float3::expr(inputs[6], inputs[7], inputs[8] + 3.0f32);
+            let m = Mat3::expr(a, b, c);
+            let v = m.inverse() * Float3::expr(1.0f32, 2.0f32, 3.0f32);
+            v.x + v.y * 0.5f32 + v.z * 0.25f32
+        }),
+    );
+}
 // #[test]
 // fn autodiff_vec3_reduce_min(){
 //
@@ -1273,6 +1703,52 @@ fn autodiff_if_phi5() {
     }
 }
 #[test]
+fn autodiff_accumulate_if() {
+    let device = get_device();
+    let x: Buffer<f32> = device.create_buffer(1024);
+    let y: Buffer<f32> = device.create_buffer(1024);
+    let z: Buffer<f32> = device.create_buffer(1024);
+    let dx: Buffer<f32> = device.create_buffer(1024);
+    let dy: Buffer<f32> = device.create_buffer(1024);
+    let mut rng = rand::thread_rng();
+    x.view(..).fill_fn(|_| rng.gen());
+    y.view(..).fill_fn(|_| rng.gen());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            let y = y.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(y);
+                let acc = y.var();
+                accumulate_if(tid % 2 == 0, acc, x * x);
+                z.var().write(tid, **acc);
+                backward(**acc);
+                dx.var().write(tid, gradient(x));
+                dy.var().write(tid, gradient(y));
+            });
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let z = z.view(..).copy_to_vec();
+    let dx = dx.view(..).copy_to_vec();
+    let dy = dy.view(..).copy_to_vec();
+    let x = x.view(..).copy_to_vec();
+    let y = y.view(..).copy_to_vec();
+    for i in 0..1024 {
+        if i % 2 == 0 {
+            assert_eq!(z[i], y[i] + x[i] * x[i]);
+            assert_eq!(dx[i], 2.0 * x[i]);
+        } else {
+            assert_eq!(z[i], y[i]);
+            assert_eq!(dx[i], 0.0);
+        }
+        assert_eq!(dy[i], 1.0);
+    }
+}
+#[test]
 fn autodiff_switch() {
     let device = get_device();
     let t: Buffer<i32> = device.create_buffer(1024);
@@ -1481,3 +1957,2127 @@ fn autodiff_callable2() {
         }
     }
 }
+#[test]
+fn autodiff_callable_nested_if() {
+    let device = get_device();
+    let x: Buffer<f32> = device.create_buffer(1024);
+    let y: Buffer<f32> = device.create_buffer(1024);
+    let dx: Buffer<f32> = device.create_buffer(1024);
+    let dy: Buffer<f32> = device.create_buffer(1024);
+    let dx_inline: Buffer<f32> = device.create_buffer(1024);
+    let dy_inline: Buffer<f32> = device.create_buffer(1024);
+    let mut rng = rand::thread_rng();
+    x.view(..).fill_fn(|_| rng.gen());
+    y.view(..).fill_fn(|_| rng.gen());
+    let f = track!(|x: Expr<f32>, y: Expr<f32>| {
+        let v = 0.0f32.var();
+        if x > y {
+            if x > 0.5 {
+                *v = x * x;
+            } else {
+                *v = x * 3.0;
+            }
+        } else if y > 0.5 {
+            *v = y * 2.0;
+        } else {
+            *v = x * y;
+        }
+        **v
+    });
+    let callable = Callable::<fn(Expr<f32>, Expr<f32>) -> Expr<f32>>::new(&device, &f);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            let y = y.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(y);
+                let z = callable.call(x, y);
+                backward(z);
+                dx.var().write(tid, gradient(x));
+                dy.var().write(tid, gradient(y));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(y);
+                let z = f(x, y);
+                backward(z);
+                dx_inline.var().write(tid, gradient(x));
+                dy_inline.var().write(tid, gradient(y));
+            });
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let dx = dx.view(..).copy_to_vec();
+    let dy = dy.view(..).copy_to_vec();
+    let dx_inline = dx_inline.view(..).copy_to_vec();
+    let dy_inline = dy_inline.view(..).copy_to_vec();
+    let x = x.view(..).copy_to_vec();
+    let y = y.view(..).copy_to_vec();
+    let cache_dir = kernel.cache_dir();
+    for i in 0..1024 {
+        let (ex, ey) = if x[i] > y[i] {
+            if x[i] > 0.5 {
+                (2.0 * x[i], 0.0)
+            } else {
+                (3.0, 0.0)
+            }
+        } else if y[i] > 0.5 {
+            (0.0, 2.0)
+        } else {
+            (y[i], x[i])
+        };
+        assert_eq!(dx[i], dx_inline[i], "cache_dir: {:?}", cache_dir);
+        assert_eq!(dy[i], dy_inline[i], "cache_dir: {:?}", cache_dir);
+        assert!(
+            (dx[i] - ex).abs() < 1e-5,
+            "{} cache_dir: {:?}",
+            dx[i],
+            cache_dir
+        );
+        assert!(
+            (dy[i] - ey).abs() < 1e-5,
+            "{} cache_dir: {:?}",
+            dy[i],
+            cache_dir
+        );
+    }
+}
+#[test]
+fn autodiff_swizzle() {
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let vs: Vec<Float3> = (0..n)
+        .map(|_| {
+            Float3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+        })
+        .collect();
+    let v: Buffer<Float3> = device.create_buffer_from_slice(&vs);
+    let d_zyx: Buffer<Float3> = device.create_buffer(n);
+    let d_xy: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let v = v.var().read(tid);
+            autodiff(|| {
+                requires_grad(v);
+                backward(v.zyx().x);
+                d_zyx.var().write(tid, gradient(v));
+            });
+            autodiff(|| {
+                requires_grad(v);
+                let xy = v.xy();
+                backward(xy.x * xy.y + v.xxx().z);
+                d_xy.var().write(tid, gradient(v));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let d_zyx = d_zyx.copy_to_vec();
+    let d_xy = d_xy.copy_to_vec();
+    for i in 0..n {
+        let v = vs[i];
+        assert_eq!(d_zyx[i].x, 0.0);
+        assert_eq!(d_zyx[i].y, 0.0);
+        assert_eq!(d_zyx[i].z, 1.0);
+        // z = x * y + x
+        assert!((d_xy[i].x - (v.y + 1.0)).abs() < 1e-5, "v = {:?}", v);
+        assert!((d_xy[i].y - v.x).abs() < 1e-5, "v = {:?}", v);
+        assert_eq!(d_xy[i].z, 0.0);
+    }
+}
+#[derive(Clone, Copy, Debug, Value, Lerp)]
+#[repr(C)]
+struct Shape {
+    width: f32,
+    height: f32,
+}
+
+#[test]
+fn autodiff_blend_struct() {
+    use luisa::lang::functions::blend;
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let mut rand_shape = || Shape {
+        width: rng.gen_range(-1.0..1.0),
+        height: rng.gen_range(-1.0..1.0),
+    };
+    let shapes: Vec<(Shape, Shape)> = (0..n).map(|_| (rand_shape(), rand_shape())).collect();
+    let ts: Vec<f32> = (0..n).map(|_| rng.gen()).collect();
+    let a: Buffer<Shape> = device.create_buffer_from_fn(n, |i| shapes[i].0);
+    let b: Buffer<Shape> = device.create_buffer_from_fn(n, |i| shapes[i].1);
+    let t: Buffer<f32> = device.create_buffer_from_slice(&ts);
+    let blended: Buffer<Shape> = device.create_buffer(n);
+    let da: Buffer<Shape> = device.create_buffer(n);
+    let db: Buffer<Shape> = device.create_buffer(n);
+    let dt: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let a = a.var().read(tid);
+            let b = b.var().read(tid);
+            let t = t.var().read(tid);
+            autodiff(|| {
+                requires_grad(a);
+                requires_grad(b);
+                requires_grad(t);
+                let s = blend(a, b, t);
+                blended.var().write(tid, s);
+                backward(s.width + s.height * 2.0);
+                da.var().write(tid, gradient(a));
+                db.var().write(tid, gradient(b));
+                dt.var().write(tid, gradient(t));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let blended = blended.copy_to_vec();
+    let da = da.copy_to_vec();
+    let db = db.copy_to_vec();
+    let dt = dt.copy_to_vec();
+    for i in 0..n {
+        let (a, b) = shapes[i];
+        let t = ts[i];
+        let lerp = |x: f32, y: f32| x * (1.0 - t) + y * t;
+        assert!((blended[i].width - lerp(a.width, b.width)).abs() < 1e-5);
+        assert!((blended[i].height - lerp(a.height, b.height)).abs() < 1e-5);
+        // loss = width + 2 * height, each field follows the scalar lerp rule
+        assert!((da[i].width - (1.0 - t)).abs() < 1e-5, "t = {}", t);
+        assert!((da[i].height - 2.0 * (1.0 - t)).abs() < 1e-5, "t = {}", t);
+        assert!((db[i].width - t).abs() < 1e-5, "t = {}", t);
+        assert!((db[i].height - 2.0 * t).abs() < 1e-5, "t = {}", t);
+        let expected_dt = (b.width - a.width) + 2.0 * (b.height - a.height);
+        assert!((dt[i] - expected_dt).abs() < 1e-4, "t = {}", t);
+    }
+}
+#[test]
+fn autodiff_sh_dot() {
+    use luisa::lang::lighting::{sh_dot, sh_eval_l2};
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let dirs: Vec<Float3> = (0..n)
+        .map(|_| loop {
+            let d = [
+                rng.gen_range(-1.0f32..1.0),
+                rng.gen_range(-1.0f32..1.0),
+                rng.gen_range(-1.0f32..1.0),
+            ];
+            let l = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+            if l > 0.1 && l < 1.0 {
+                break Float3::new(d[0] / l, d[1] / l, d[2] / l);
+            }
+        })
+        .collect();
+    let coeffs: Vec<[f32; 9]> = (0..n)
+        .map(|_| std::array::from_fn(|_| rng.gen_range(-1.0..1.0)))
+        .collect();
+    let dir: Buffer<Float3> = device.create_buffer_from_slice(&dirs);
+    let coeff: Buffer<[f32; 9]> = device.create_buffer_from_slice(&coeffs);
+    let basis: Buffer<f32> = device.create_buffer(n * 9);
+    let d_coeff: Buffer<f32> = device.create_buffer(n * 9);
+    let d_dir: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let d = dir.var().read(tid);
+            let c = coeff.var().read(tid);
+            let cs: [Expr<f32>; 9] = std::array::from_fn(|i| c.read(i as u32));
+            let ys = sh_eval_l2(d);
+            for_unrolled(0..9, |i| {
+                basis.var().write(tid * 9u32 + i as u32, ys[i]);
+            });
+            autodiff(|| {
+                requires_grad(d);
+                for_unrolled(0..9, |i| requires_grad(cs[i]));
+                backward(sh_dot(cs, d));
+                d_dir.var().write(tid, gradient(d));
+                for_unrolled(0..9, |i| {
+                    d_coeff.var().write(tid * 9u32 + i as u32, gradient(cs[i]));
+                });
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let basis = basis.copy_to_vec();
+    let d_coeff = d_coeff.copy_to_vec();
+    let d_dir = d_dir.copy_to_vec();
+    let sh_dot_host = |c: &[f32; 9], d: [f64; 3]| -> f64 {
+        let (x, y, z) = (d[0], d[1], d[2]);
+        let ys = [
+            0.282095,
+            0.488603 * y,
+            0.488603 * z,
+            0.488603 * x,
+            1.092548 * x * y,
+            1.092548 * y * z,
+            0.315392 * (3.0 * z * z - 1.0),
+            1.092548 * x * z,
+            0.546274 * (x * x - y * y),
+        ];
+        (0..9).map(|i| c[i] as f64 * ys[i]).sum()
+    };
+    for i in 0..n {
+        let d = [dirs[i].x as f64, dirs[i].y as f64, dirs[i].z as f64];
+        // the basis is the gradient with respect to the coefficients
+        for k in 0..9 {
+            assert_eq!(d_coeff[i * 9 + k], basis[i * 9 + k]);
+        }
+        let ad = [d_dir[i].x, d_dir[i].y, d_dir[i].z];
+        for j in 0..3 {
+            let eps = 1e-4;
+            let mut dp = d;
+            let mut dm = d;
+            dp[j] += eps;
+            dm[j] -= eps;
+            let fd = (sh_dot_host(&coeffs[i], dp) - sh_dot_host(&coeffs[i], dm)) / (2.0 * eps);
+            assert!(
+                (ad[j] as f64 - fd).abs() < 1e-3,
+                "dir: {:?}, axis: {}, fd: {}, ad: {}",
+                dirs[i],
+                j,
+                fd,
+                ad[j]
+            );
+        }
+    }
+}
+#[test]
+fn autodiff_recip() {
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let xs: Vec<f32> = (0..n)
+        .map(|_| {
+            let x: f32 = rng.gen_range(0.1..4.0);
+            if rng.gen() {
+                x
+            } else {
+                -x
+            }
+        })
+        .collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let d_div: Buffer<f32> = device.create_buffer(n);
+    let d_recip: Buffer<f32> = device.create_buffer(n);
+    let recip_fast: Buffer<f32> = device.create_buffer(n);
+    let d_recip_fast: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                backward(1.0 / x);
+                d_div.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                backward(x.recip());
+                d_recip.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                let y = x.recip_fast();
+                recip_fast.var().write(tid, y);
+                backward(y);
+                d_recip_fast.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let d_div = d_div.copy_to_vec();
+    let d_recip = d_recip.copy_to_vec();
+    let recip_fast = recip_fast.copy_to_vec();
+    let d_recip_fast = d_recip_fast.copy_to_vec();
+    for i in 0..n {
+        let x = xs[i];
+        let expected = -1.0 / (x * x);
+        assert_eq!(d_recip[i], d_div[i], "x = {}", x);
+        let rel = |a: f32, b: f32| ((a - b) / b).abs();
+        assert!(rel(d_recip[i], expected) < 1e-5, "x = {}", x);
+        assert!(rel(recip_fast[i], 1.0 / x) < 1e-3, "x = {}", x);
+        assert!(rel(d_recip_fast[i], expected) < 1e-3, "x = {}", x);
+    }
+}
+#[test]
+#[cfg(feature = "autodiff-debug")]
+fn autodiff_tape_dump_mul() {
+    let device = get_device();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&[2.0]);
+    let y: Buffer<f32> = device.create_buffer_from_slice(&[3.0]);
+    let dx: Buffer<f32> = device.create_buffer(1);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let x = x.var().read(0);
+            let y = y.var().read(0);
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(y);
+                backward(x * y);
+                dx.var().write(0, gradient(x));
+            });
+        }),
+    );
+    let tape = autodiff_tape_dump();
+    let lines: Vec<_> = tape.lines().collect();
+    assert_eq!(lines[0], "requires_grad %0");
+    assert_eq!(lines[1], "requires_grad %1");
+    assert_eq!(lines[2], "%2 = Mul(%0, %1)");
+    assert!(lines[3..]
+        .iter()
+        .any(|l| l.starts_with("backward %2 with adjoint")));
+    kernel.dispatch([1, 1, 1]);
+    assert_eq!(dx.copy_to_vec()[0], 3.0);
+}
+#[test]
+fn autodiff_srgb_breakpoint() {
+    use luisa::lang::color::{linear_to_srgb, rgb_to_xyz, srgb_to_linear, xyz_to_rgb};
+    let device = get_device();
+    let n = 1024;
+    // offsets straddling each breakpoint, never exactly on it
+    let offsets: Vec<f32> = (0..n).map(|i| (i as f32 - 511.5) * 1e-6).collect();
+    let offset: Buffer<f32> = device.create_buffer_from_slice(&offsets);
+    let d_decode: Buffer<f32> = device.create_buffer(n);
+    let d_encode: Buffer<f32> = device.create_buffer(n);
+    let round_trip: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let o = offset.var().read(tid);
+            let x = 0.04045f32 + o;
+            autodiff(|| {
+                requires_grad(x);
+                backward(srgb_to_linear(Float3::expr(x, 0.0f32, 0.0f32)).x);
+                d_decode.var().write(tid, gradient(x));
+            });
+            let y = 0.0031308f32 + o;
+            autodiff(|| {
+                requires_grad(y);
+                backward(linear_to_srgb(Float3::expr(y, 0.0f32, 0.0f32)).x);
+                d_encode.var().write(tid, gradient(y));
+            });
+            let c = Float3::expr(x, y, 0.5f32);
+            round_trip.var().write(tid, xyz_to_rgb(rgb_to_xyz(c)));
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let d_decode = d_decode.copy_to_vec();
+    let d_encode = d_encode.copy_to_vec();
+    let round_trip = round_trip.copy_to_vec();
+    for i in 0..n {
+        let o = offsets[i] as f64;
+        let x = 0.04045 + o;
+        let expected = if x <= 0.04045 {
+            1.0 / 12.92
+        } else {
+            2.4 / 1.055 * ((x + 0.055) / 1.055).powf(1.4)
+        };
+        assert!(d_decode[i].is_finite());
+        assert!((d_decode[i] as f64 - expected).abs() < 1e-4, "x = {}", x);
+        let y = 0.0031308 + o;
+        let expected = if y <= 0.0031308 {
+            12.92
+        } else {
+            1.055 / 2.4 * y.powf(1.0 / 2.4 - 1.0)
+        };
+        assert!(d_encode[i].is_finite());
+        assert!(
+            (d_encode[i] as f64 - expected).abs() < 1e-2,
+            "y = {}, ad = {}, expected = {}",
+            y,
+            d_encode[i],
+            expected
+        );
+        let c = round_trip[i];
+        assert!((c.x as f64 - x).abs() < 1e-5);
+        assert!((c.y as f64 - y).abs() < 1e-5);
+        assert!((c.z - 0.5).abs() < 1e-5);
+    }
+}
+fn solve_host<const N: usize>(a: &[[f64; N]; N], b: &[f64; N]) -> [f64; N] {
+    let (mut a, mut b) = (*a, *b);
+    for k in 0..N {
+        let p = (k..N)
+            .max_by(|&i, &j| a[i][k].abs().partial_cmp(&a[j][k].abs()).unwrap())
+            .unwrap();
+        a.swap(k, p);
+        b.swap(k, p);
+        for i in k + 1..N {
+            let f = a[i][k] / a[k][k];
+            for j in k..N {
+                a[i][j] -= f * a[k][j];
+            }
+            b[i] -= f * b[k];
+        }
+    }
+    let mut x = [0.0; N];
+    for i in (0..N).rev() {
+        let s: f64 = (i + 1..N).map(|j| a[i][j] * x[j]).sum();
+        x[i] = (b[i] - s) / a[i][i];
+    }
+    x
+}
+
+#[test]
+fn autodiff_solve_6x6() {
+    use luisa::lang::linalg::{solve, MatN, VecN};
+    let device = get_device();
+    let n = 256;
+    let mut rng = rand::thread_rng();
+    // well conditioned, but with rows shuffled so that pivoting is needed
+    let systems: Vec<(MatN<6>, VecN<6>, VecN<6>)> = (0..n)
+        .map(|_| {
+            let mut a: MatN<6> = std::array::from_fn(|i| {
+                std::array::from_fn(|j| rng.gen_range(-1.0..1.0) + if i == j { 4.0 } else { 0.0 })
+            });
+            a.shuffle(&mut rng);
+            let b = std::array::from_fn(|_| rng.gen_range(-1.0..1.0));
+            let w = std::array::from_fn(|_| rng.gen_range(-1.0..1.0));
+            (a, b, w)
+        })
+        .collect();
+    let a: Buffer<MatN<6>> = device.create_buffer_from_fn(n, |i| systems[i].0);
+    let b: Buffer<VecN<6>> = device.create_buffer_from_fn(n, |i| systems[i].1);
+    let w: Buffer<VecN<6>> = device.create_buffer_from_fn(n, |i| systems[i].2);
+    let x: Buffer<VecN<6>> = device.create_buffer(n);
+    let db: Buffer<VecN<6>> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let a = a.var().read(tid);
+            let b = b.var().read(tid);
+            let w = w.var().read(tid);
+            autodiff(|| {
+                requires_grad(b);
+                let s = solve(a, b);
+                x.var().write(tid, s);
+                let loss = 0.0f32.var();
+                for_unrolled(0..6u32, |i| {
+                    *loss += w.read(i) * s.read(i);
+                });
+                backward(**loss);
+                db.var().write(tid, gradient(b));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let x = x.copy_to_vec();
+    let db = db.copy_to_vec();
+    for t in 0..n {
+        let (a, b, w) = &systems[t];
+        let a: [[f64; 6]; 6] = a.map(|row| row.map(|v| v as f64));
+        let b: [f64; 6] = b.map(|v| v as f64);
+        let loss = |b: &[f64; 6]| -> f64 {
+            let x = solve_host(&a, b);
+            (0..6).map(|i| w[i] as f64 * x[i]).sum()
+        };
+        let expected = solve_host(&a, &b);
+        for i in 0..6 {
+            assert!((x[t][i] as f64 - expected[i]).abs() < 1e-4);
+        }
+        for j in 0..6 {
+            let eps = 1e-4;
+            let (mut bp, mut bm) = (b, b);
+            bp[j] += eps;
+            bm[j] -= eps;
+            let fd = (loss(&bp) - loss(&bm)) / (2.0 * eps);
+            assert!(
+                (db[t][j] as f64 - fd).abs() < 1e-3,
+                "system {}, b[{}]: fd = {}, ad = {}",
+                t,
+                j,
+                fd,
+                db[t][j]
+            );
+        }
+    }
+}
+#[test]
+fn autodiff_erf() {
+    use luisa::lang::functions::{erf, erfc};
+    let device = get_device();
+    let n = 1024;
+    // multiples of 1/128 in [-4, 4)
+    let xs: Vec<f32> = (0..n).map(|i| (i as f32 - 512.0) / 128.0).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let v_erf: Buffer<f32> = device.create_buffer(n);
+    let v_erfc: Buffer<f32> = device.create_buffer(n);
+    let d_erf: Buffer<f32> = device.create_buffer(n);
+    let d_erfc: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let y = erf(x);
+                v_erf.var().write(tid, y);
+                backward(y);
+                d_erf.var().write(tid, gradient(x));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                let y = erfc(x);
+                v_erfc.var().write(tid, y);
+                backward(y);
+                d_erfc.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let v_erf = v_erf.copy_to_vec();
+    let v_erfc = v_erfc.copy_to_vec();
+    let d_erf = d_erf.copy_to_vec();
+    let d_erfc = d_erfc.copy_to_vec();
+    for i in 0..n {
+        let x = xs[i];
+        let expected = 2.0 / std::f32::consts::PI.sqrt() * (-x * x).exp();
+        assert!((d_erf[i] - expected).abs() < 1e-6, "x = {}", x);
+        assert!((d_erfc[i] + expected).abs() < 1e-6, "x = {}", x);
+        assert!((v_erf[i] + v_erfc[i] - 1.0).abs() < 1e-6, "x = {}", x);
+        if i > 0 {
+            assert!((v_erf[i] + v_erf[n - i]).abs() < 1e-6, "x = {}", x);
+        }
+    }
+    let erf_at = |x: f32| v_erf[(x * 128.0) as usize + 512];
+    assert!(erf_at(0.0).abs() < 1e-6);
+    assert!((erf_at(0.5) - 0.520_499_9).abs() < 1e-6);
+    assert!((erf_at(1.0) - 0.842_700_8).abs() < 1e-6);
+    assert!((erf_at(2.0) - 0.995_322_3).abs() < 1e-6);
+}
+#[test]
+fn erfc_tail_relative_error() {
+    use luisa::lang::functions::erfc;
+    let device = get_device();
+    // values from mpmath
+    let cases = [
+        (-2.0f32, 1.995_322_3f32),
+        (1.0, 0.157_299_2),
+        (3.0, 2.209_049_7e-5),
+        (6.0, 2.151_973_7e-17),
+        (9.0, 4.137_031_7e-37),
+    ];
+    let x: Buffer<f32> = device.create_buffer_from_fn(cases.len(), |i| cases[i].0);
+    let y: Buffer<f32> = device.create_buffer(cases.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            y.var().write(tid, erfc(x.var().read(tid)));
+        }),
+    );
+    kernel.dispatch([cases.len() as u32, 1, 1]);
+    for ((x, expected), y) in cases.iter().zip(y.copy_to_vec()) {
+        let err = ((y - expected) / expected).abs();
+        assert!(err < 2e-6, "erfc({}) = {}, expected {}", x, y, expected);
+    }
+}
+#[test]
+fn autodiff_angle_between() {
+    use luisa::lang::functions::{angle_between, wrap_angle};
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let mut rand3 = || -> [f32; 3] { std::array::from_fn(|_| rng.gen_range(-1.0f32..1.0)) };
+    // nearly parallel pairs in the first half, nearly antiparallel in the second
+    let pairs: Vec<([f32; 3], [f32; 3])> = (0..n)
+        .map(|i| {
+            let a = rand3();
+            let d = rand3();
+            let sign = if i < n / 2 { 1.0 } else { -1.0 };
+            let b = std::array::from_fn(|k| sign * a[k] + 1e-2 * d[k]);
+            (a, b)
+        })
+        .collect();
+    let to_float3 = |v: [f32; 3]| Float3::new(v[0], v[1], v[2]);
+    let a: Buffer<Float3> = device.create_buffer_from_fn(n, |i| to_float3(pairs[i].0));
+    let b: Buffer<Float3> = device.create_buffer_from_fn(n, |i| to_float3(pairs[i].1));
+    let angle: Buffer<f32> = device.create_buffer(n);
+    let d_a: Buffer<Float3> = device.create_buffer(n);
+    let d_b: Buffer<Float3> = device.create_buffer(n);
+    let wrapped: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let a_ = a.var().read(tid);
+            let b_ = b.var().read(tid);
+            autodiff(|| {
+                requires_grad(a_);
+                requires_grad(b_);
+                let t = angle_between(a_, b_);
+                angle.var().write(tid, t);
+                backward(t);
+                d_a.var().write(tid, gradient(a_));
+                d_b.var().write(tid, gradient(b_));
+            });
+            wrapped
+                .var()
+                .write(tid, wrap_angle((tid.as_f32() - 512.0f32) / 16.0f32));
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let angle = angle.copy_to_vec();
+    let d_a = d_a.copy_to_vec();
+    let d_b = d_b.copy_to_vec();
+    let wrapped = wrapped.copy_to_vec();
+    let angle_host = |a: [f64; 3], b: [f64; 3]| -> f64 {
+        let c = [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ];
+        let cross = (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt();
+        cross.atan2(a[0] * b[0] + a[1] * b[1] + a[2] * b[2])
+    };
+    let h = 1e-6;
+    for i in 0..n {
+        let a = pairs[i].0.map(|x| x as f64);
+        let b = pairs[i].1.map(|x| x as f64);
+        let expected = angle_host(a, b);
+        assert!((angle[i] as f64 - expected).abs() < 1e-4, "i = {}", i);
+        let ga = [d_a[i].x, d_a[i].y, d_a[i].z];
+        let gb = [d_b[i].x, d_b[i].y, d_b[i].z];
+        for k in 0..3 {
+            let (mut ap, mut am) = (a, a);
+            ap[k] += h;
+            am[k] -= h;
+            let fd_a = (angle_host(ap, b) - angle_host(am, b)) / (2.0 * h);
+            let (mut bp, mut bm) = (b, b);
+            bp[k] += h;
+            bm[k] -= h;
+            let fd_b = (angle_host(a, bp) - angle_host(a, bm)) / (2.0 * h);
+            let tol = 1e-2 * (1.0 + fd_a.abs().max(fd_b.abs()));
+            assert!(
+                (ga[k] as f64 - fd_a).abs() < tol,
+                "i = {}: {} vs {}",
+                i,
+                ga[k],
+                fd_a
+            );
+            assert!(
+                (gb[k] as f64 - fd_b).abs() < tol,
+                "i = {}: {} vs {}",
+                i,
+                gb[k],
+                fd_b
+            );
+        }
+    }
+    for i in 0..n {
+        let theta = (i as f32 - 512.0) / 16.0;
+        let w = wrapped[i];
+        assert!(w >= -std::f32::consts::PI - 1e-5 && w < std::f32::consts::PI + 1e-5);
+        let turns = (theta - w) / std::f32::consts::TAU;
+        assert!((turns - turns.round()).abs() < 1e-4, "theta = {}", theta);
+    }
+}
+#[test]
+fn autodiff_sampling_jacobian() {
+    use luisa::lang::sampling::*;
+    use std::f64::consts::PI;
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let us: Vec<Float2> = (0..n)
+        .map(|_| Float2::new(rng.gen_range(0.05..0.95), rng.gen_range(0.05..0.95)))
+        .collect();
+    let u_buf: Buffer<Float2> = device.create_buffer_from_slice(&us);
+    // rows of the Jacobians: disk x, y; hemisphere x, y, z; sphere x, y, z
+    let jac: Buffer<Float2> = device.create_buffer(n * 8);
+    let pdf: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let u = u_buf.var().read(tid);
+            let row = |k: u32, f: &dyn Fn(Expr<Float2>) -> Expr<f32>| {
+                autodiff(|| {
+                    requires_grad(u);
+                    backward(f(u));
+                    jac.var().write(tid * 8u32 + k, gradient(u));
+                });
+            };
+            row(0, &|u| sample_uniform_disk(u).0.x);
+            row(1, &|u| sample_uniform_disk(u).0.y);
+            row(2, &|u| sample_cosine_hemisphere(u).0.x);
+            row(3, &|u| sample_cosine_hemisphere(u).0.y);
+            row(4, &|u| sample_cosine_hemisphere(u).0.z);
+            row(5, &|u| sample_uniform_sphere(u).0.x);
+            row(6, &|u| sample_uniform_sphere(u).0.y);
+            row(7, &|u| sample_uniform_sphere(u).0.z);
+            let p = Float3::expr(
+                sample_uniform_disk(u).1,
+                sample_cosine_hemisphere(u).1,
+                sample_uniform_sphere(u).1,
+            );
+            pdf.var().write(tid, p);
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let jac = jac.copy_to_vec();
+    let pdf = pdf.copy_to_vec();
+    for i in 0..n {
+        let (u0, u1) = (us[i].x as f64, us[i].y as f64);
+        let (c, s) = ((2.0 * PI * u1).cos(), (2.0 * PI * u1).sin());
+        let r_disk = u0.sqrt();
+        let r_sphere = 2.0 * (u0 * (1.0 - u0)).sqrt();
+        let dr_sphere = (1.0 - 2.0 * u0) / (u0 * (1.0 - u0)).sqrt();
+        let expected: [[f64; 2]; 8] = [
+            [c / (2.0 * r_disk), -2.0 * PI * r_disk * s],
+            [s / (2.0 * r_disk), 2.0 * PI * r_disk * c],
+            [c / (2.0 * r_disk), -2.0 * PI * r_disk * s],
+            [s / (2.0 * r_disk), 2.0 * PI * r_disk * c],
+            [-0.5 / (1.0 - u0).sqrt(), 0.0],
+            [dr_sphere * c, -2.0 * PI * r_sphere * s],
+            [dr_sphere * s, 2.0 * PI * r_sphere * c],
+            [-2.0, 0.0],
+        ];
+        for k in 0..8 {
+            let actual = jac[i * 8 + k];
+            for (a, e) in [actual.x, actual.y].into_iter().zip(expected[k]) {
+                assert!(
+                    (a as f64 - e).abs() < 1e-3 * (1.0 + e.abs()),
+                    "u = {:?}, row {}: {:?} vs {:?}",
+                    us[i],
+                    k,
+                    actual,
+                    expected[k]
+                );
+            }
+        }
+        let cos_theta = (1.0 - u0).sqrt();
+        assert!((pdf[i].x as f64 - 1.0 / PI).abs() < 1e-6);
+        assert!((pdf[i].y as f64 - cos_theta / PI).abs() < 1e-4);
+        assert!((pdf[i].z as f64 - 0.25 / PI).abs() < 1e-6);
+    }
+}
+#[test]
+fn autodiff_ggx_roughness() {
+    use luisa::lang::material::ggx_brdf;
+    let device = get_device();
+    let deg = |d: f64| d.to_radians();
+    let mut cases = vec![];
+    for theta_o in [0.0, 30.0, 60.0, 80.0, 85.0, 89.0] {
+        for theta_i in [0.0, 45.0, 85.0, 89.0] {
+            for phi_i in [0.0, 180.0] {
+                for roughness in [0.2, 0.5, 0.9] {
+                    let wo = [deg(theta_o).sin(), 0.0, deg(theta_o).cos()];
+                    let wi = [
+                        deg(theta_i).sin() * deg(phi_i).cos(),
+                        deg(theta_i).sin() * deg(phi_i).sin(),
+                        deg(theta_i).cos(),
+                    ];
+                    cases.push((wo, wi, roughness));
+                }
+            }
+        }
+    }
+    let n = cases.len();
+    let f0 = [0.04, 0.5, 0.9];
+    let to_float3 = |v: [f64; 3]| Float3::new(v[0] as f32, v[1] as f32, v[2] as f32);
+    let wo_buf: Buffer<Float3> = device.create_buffer_from_fn(n, |i| to_float3(cases[i].0));
+    let wi_buf: Buffer<Float3> = device.create_buffer_from_fn(n, |i| to_float3(cases[i].1));
+    let r_buf: Buffer<f32> = device.create_buffer_from_fn(n, |i| cases[i].2 as f32);
+    let value: Buffer<f32> = device.create_buffer(n);
+    let d_r: Buffer<f32> = device.create_buffer(n);
+    let d_f0: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let wo = wo_buf.var().read(tid);
+            let wi = wi_buf.var().read(tid);
+            let r = r_buf.var().read(tid);
+            let f0 = to_float3(f0).expr();
+            autodiff(|| {
+                requires_grad(r);
+                requires_grad(f0);
+                let f = ggx_brdf(wo, wi, Float3::new(0.0, 0.0, 1.0), r, f0);
+                let y = f.x + f.y + f.z;
+                value.var().write(tid, y);
+                backward(y);
+                d_r.var().write(tid, gradient(r));
+                d_f0.var().write(tid, gradient(f0));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let d_r = d_r.copy_to_vec();
+    let d_f0 = d_f0.copy_to_vec();
+    let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let brdf_host = |wo: [f64; 3], wi: [f64; 3], r: f64, f0: [f64; 3]| -> f64 {
+        let (cos_o, cos_i) = (wo[2], wi[2]);
+        let h = [wo[0] + wi[0], wo[1] + wi[1], wo[2] + wi[2]];
+        let l = dot(h, h).sqrt();
+        let h = h.map(|x| x / l);
+        let a2 = r.powi(4);
+        let t = h[2] * h[2] * (a2 - 1.0) + 1.0;
+        let d = a2 / (std::f64::consts::PI * t * t);
+        let g1 = |c: f64| 2.0 * c / (c + (a2 + (1.0 - a2) * c * c).sqrt());
+        let m5 = (1.0 - dot(wo, h)).powi(5);
+        f0.iter()
+            .map(|f0| (f0 + (1.0 - f0) * m5) * d * g1(cos_o) * g1(cos_i) / (4.0 * cos_o * cos_i))
+            .sum()
+    };
+    let h = 1e-5;
+    for i in 0..n {
+        let (wo, wi, r) = cases[i];
+        let expected = brdf_host(wo, wi, r, f0);
+        let close = |a: f32, b: f64| (a as f64 - b).abs() <= 1e-2 * b.abs() + 1e-4;
+        assert!(
+            close(value[i], expected),
+            "{:?}: {} vs {}",
+            cases[i],
+            value[i],
+            expected
+        );
+        let fd = (brdf_host(wo, wi, r + h, f0) - brdf_host(wo, wi, r - h, f0)) / (2.0 * h);
+        assert!(close(d_r[i], fd), "{:?}: {} vs {}", cases[i], d_r[i], fd);
+        let g = [d_f0[i].x, d_f0[i].y, d_f0[i].z];
+        for c in 0..3 {
+            let (mut fp, mut fm) = (f0, f0);
+            fp[c] += h;
+            fm[c] -= h;
+            let fd = (brdf_host(wo, wi, r, fp) - brdf_host(wo, wi, r, fm)) / (2.0 * h);
+            assert!(close(g[c], fd), "{:?}: {} vs {}", cases[i], g[c], fd);
+        }
+    }
+}
+#[test]
+fn autodiff_tone_mapping() {
+    use luisa::lang::color::{aces_filmic, reinhard};
+    let device = get_device();
+    let n = 1024;
+    // luminances from 1e-5 to 1e3, spread evenly in log space
+    let xs: Vec<Float3> = (0..n)
+        .map(|i| {
+            let x = 10.0f32.powf(-5.0 + 8.0 * i as f32 / n as f32);
+            Float3::new(x, 2.0 * x, 0.5 * x)
+        })
+        .collect();
+    let x_buf: Buffer<Float3> = device.create_buffer_from_slice(&xs);
+    let v_reinhard: Buffer<Float3> = device.create_buffer(n);
+    let v_aces: Buffer<Float3> = device.create_buffer(n);
+    let d_reinhard: Buffer<Float3> = device.create_buffer(n);
+    let d_aces: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let v = x_buf.var().read(tid);
+            autodiff(|| {
+                requires_grad(v);
+                let y = reinhard(v);
+                v_reinhard.var().write(tid, y);
+                backward(y.x + y.y + y.z);
+                d_reinhard.var().write(tid, gradient(v));
+            });
+            autodiff(|| {
+                requires_grad(v);
+                let y = aces_filmic(v);
+                v_aces.var().write(tid, y);
+                backward(y.x + y.y + y.z);
+                d_aces.var().write(tid, gradient(v));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let v_reinhard = v_reinhard.copy_to_vec();
+    let v_aces = v_aces.copy_to_vec();
+    let d_reinhard = d_reinhard.copy_to_vec();
+    let d_aces = d_aces.copy_to_vec();
+    let reinhard_host = |x: f64| x / (1.0 + x);
+    let aces_host = |x: f64| (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).min(1.0);
+    let check = |f: &dyn Fn(f64) -> f64, x: f32, value: f32, grad: f32| {
+        let x = x as f64;
+        let h = 1e-4 * x.max(1e-3);
+        let fd = (f(x + h) - f(x - h)) / (2.0 * h);
+        assert!(
+            (value as f64 - f(x)).abs() < 1e-5 * (1.0 + f(x)),
+            "x = {}",
+            x
+        );
+        assert!(
+            (grad as f64 - fd).abs() < 1e-3 * (1.0 + fd.abs()),
+            "x = {}: {} vs {}",
+            x,
+            grad,
+            fd
+        );
+    };
+    for i in 0..n {
+        let x = [xs[i].x, xs[i].y, xs[i].z];
+        let value = [v_reinhard[i].x, v_reinhard[i].y, v_reinhard[i].z];
+        let grad = [d_reinhard[i].x, d_reinhard[i].y, d_reinhard[i].z];
+        for c in 0..3 {
+            check(&reinhard_host, x[c], value[c], grad[c]);
+        }
+        let value = [v_aces[i].x, v_aces[i].y, v_aces[i].z];
+        let grad = [d_aces[i].x, d_aces[i].y, d_aces[i].z];
+        for c in 0..3 {
+            // skip the kink where the curve reaches one
+            if (x[c] - 7.24).abs() > 0.05 {
+                check(&aces_host, x[c], value[c], grad[c]);
+            }
+        }
+    }
+}
+#[test]
+fn autodiff_tangent_to_world() {
+    use luisa::lang::geometry::tangent_to_world;
+    let device = get_device();
+    let n = 1024;
+    let mut rng = rand::thread_rng();
+    let mut rand3 =
+        |lo: f32, hi: f32| -> [f32; 3] { std::array::from_fn(|_| rng.gen_range(lo..hi)) };
+    // normal_ts, tangent, bitangent, normal
+    let frames: Vec<[[f32; 3]; 4]> = (0..n)
+        .map(|_| {
+            let mut ts = rand3(-0.7, 0.7);
+            ts[2] = ts[2].abs() + 0.3;
+            let normal = rand3(-1.0, 1.0);
+            let tangent = rand3(-1.0, 1.0);
+            let bitangent = rand3(-1.0, 1.0);
+            [ts, tangent, bitangent, normal]
+        })
+        .filter(|[_, t, b, n]| {
+            // keep the frame well conditioned
+            let c = [
+                n[1] * t[2] - n[2] * t[1],
+                n[2] * t[0] - n[0] * t[2],
+                n[0] * t[1] - n[1] * t[0],
+            ];
+            let norm = |v: &[f32; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+            let det = c[0] * b[0] + c[1] * b[1] + c[2] * b[2];
+            norm(&c) > 0.2 * norm(n) * norm(t) && det.abs() > 0.2 * norm(&c) * norm(b)
+        })
+        .collect();
+    let n = frames.len();
+    let to_float3 = |v: [f32; 3]| Float3::new(v[0], v[1], v[2]);
+    let frame_buf: Buffer<Float3> =
+        device.create_buffer_from_fn(n * 4, |i| to_float3(frames[i / 4][i % 4]));
+    let world: Buffer<Float3> = device.create_buffer(n);
+    // rows of the Jacobian of the world normal with respect to normal_ts
+    let jac: Buffer<Float3> = device.create_buffer(n * 3);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let ts = frame_buf.var().read(tid * 4u32);
+            let t = frame_buf.var().read(tid * 4u32 + 1u32);
+            let b = frame_buf.var().read(tid * 4u32 + 2u32);
+            let nn = frame_buf.var().read(tid * 4u32 + 3u32);
+            world.var().write(tid, tangent_to_world(ts, t, b, nn));
+            for_unrolled(0..3, |k| {
+                autodiff(|| {
+                    requires_grad(ts);
+                    let w = tangent_to_world(ts, t, b, nn);
+                    let y = [w.x, w.y, w.z][k];
+                    backward(y);
+                    jac.var().write(tid * 3u32 + k as u32, gradient(ts));
+                });
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let world = world.copy_to_vec();
+    let jac = jac.copy_to_vec();
+    fn normalize(v: [f64; 3]) -> [f64; 3] {
+        let l = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        v.map(|x| x / l)
+    }
+    fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+    fn axpy(a: f64, x: [f64; 3], y: [f64; 3]) -> [f64; 3] {
+        [a * x[0] + y[0], a * x[1] + y[1], a * x[2] + y[2]]
+    }
+    let host = |ts: [f64; 3], t: [f64; 3], b: [f64; 3], n: [f64; 3]| -> [f64; 3] {
+        let n = normalize(n);
+        let t = normalize(axpy(-dot(n, t), n, t));
+        let b = normalize(axpy(-dot(t, b), t, axpy(-dot(n, b), n, b)));
+        normalize(axpy(ts[2], n, axpy(ts[1], b, axpy(ts[0], t, [0.0; 3]))))
+    };
+    let h = 1e-6;
+    for i in 0..n {
+        let [ts, t, b, nn] = frames[i].map(|v| v.map(|x| x as f64));
+        let expected = host(ts, t, b, nn);
+        let actual = [world[i].x, world[i].y, world[i].z];
+        for k in 0..3 {
+            assert!((actual[k] as f64 - expected[k]).abs() < 1e-4, "i = {}", i);
+        }
+        for c in 0..3 {
+            let (mut tp, mut tm) = (ts, ts);
+            tp[c] += h;
+            tm[c] -= h;
+            let (wp, wm) = (host(tp, t, b, nn), host(tm, t, b, nn));
+            for k in 0..3 {
+                let fd = (wp[k] - wm[k]) / (2.0 * h);
+                let row = jac[i * 3 + k];
+                let ad = [row.x, row.y, row.z][c] as f64;
+                assert!(
+                    (ad - fd).abs() < 1e-3 * (1.0 + fd.abs()),
+                    "i = {}, d w[{}] / d ts[{}]: {} vs {}",
+                    i,
+                    k,
+                    c,
+                    ad,
+                    fd
+                );
+            }
+        }
+    }
+}
+#[test]
+fn autodiff_reproject_depth() {
+    use luisa::lang::camera::{project, unproject};
+    let device = get_device();
+    let (near, far) = (0.1f64, 100.0f64);
+    let f = 1.0 / (30.0f64.to_radians()).tan();
+    let (c, d) = (far / (near - far), near * far / (near - far));
+    // rows of a perspective projection mapping view depth near..far to 0..1
+    let proj = [
+        [f, 0.0, 0.0, 0.0],
+        [0.0, f, 0.0, 0.0],
+        [0.0, 0.0, c, d],
+        [0.0, 0.0, -1.0, 0.0],
+    ];
+    let inv_proj = [
+        [1.0 / f, 0.0, 0.0, 0.0],
+        [0.0, 1.0 / f, 0.0, 0.0],
+        [0.0, 0.0, 0.0, -1.0],
+        [0.0, 0.0, 1.0 / d, c / d],
+    ];
+    // the second camera is moved by `t`: proj_b = proj * translate(t)
+    let t = [0.3, -0.2, -0.05];
+    let mut proj_b = proj;
+    for r in 0..4 {
+        proj_b[r][3] = (0..3).map(|k| proj[r][k] * t[k]).sum::<f64>() + proj[r][3];
+    }
+    let to_mat4 = |m: [[f64; 4]; 4]| {
+        Mat4::from_column_array(&std::array::from_fn(|col| {
+            std::array::from_fn(|row| m[row][col] as f32)
+        }))
+    };
+    let mut rng = rand::thread_rng();
+    // view depths from just behind the near plane to just before the far one
+    let view_depths = [0.1001, 0.105, 0.3, 1.0, 10.0, 50.0, 90.0];
+    let cases: Vec<([f32; 2], f32)> = (0..1024)
+        .map(|i| {
+            let z = -view_depths[i % view_depths.len()];
+            let depth = ((c * z + d) / -z) as f32;
+            let uv = [rng.gen_range(0.1..0.9), rng.gen_range(0.1..0.9)];
+            (uv, depth)
+        })
+        .collect();
+    let n = cases.len();
+    let uv_buf: Buffer<Float2> =
+        device.create_buffer_from_fn(n, |i| Float2::new(cases[i].0[0], cases[i].0[1]));
+    let depth_buf: Buffer<f32> = device.create_buffer_from_fn(n, |i| cases[i].1);
+    let round_trip: Buffer<Float3> = device.create_buffer(n);
+    // derivatives of the reprojected u, v and depth with respect to depth
+    let grad: Buffer<Float3> = device.create_buffer(n);
+    let (proj_a, inv_a, proj_b) = (to_mat4(proj), to_mat4(inv_proj), to_mat4(proj_b));
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let uv = uv_buf.var().read(tid);
+            let depth = depth_buf.var().read(tid);
+            let (uv_a, depth_a) = project(unproject(uv, depth, inv_a), proj_a);
+            round_trip
+                .var()
+                .write(tid, Float3::expr(uv_a.x, uv_a.y, depth_a));
+            let g = [0.0f32.var(), 0.0f32.var(), 0.0f32.var()];
+            for_unrolled(0..3, |k| {
+                autodiff(|| {
+                    requires_grad(depth);
+                    let (uv_b, depth_b) = project(unproject(uv, depth, inv_a), proj_b);
+                    backward([uv_b.x, uv_b.y, depth_b][k]);
+                    *g[k] = gradient(depth);
+                });
+            });
+            grad.var().write(tid, Float3::expr(g[0], g[1], g[2]));
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let round_trip = round_trip.copy_to_vec();
+    let grad = grad.copy_to_vec();
+    let mul = |m: &[[f64; 4]; 4], v: [f64; 4]| -> [f64; 4] {
+        std::array::from_fn(|r| (0..4).map(|k| m[r][k] * v[k]).sum())
+    };
+    let reproject = |uv: [f64; 2], depth: f64| -> [f64; 3] {
+        let p = mul(
+            &inv_proj,
+            [2.0 * uv[0] - 1.0, 2.0 * uv[1] - 1.0, depth, 1.0],
+        );
+        let p = [p[0] / p[3], p[1] / p[3], p[2] / p[3], 1.0];
+        let q = mul(&proj_b, p);
+        [
+            q[0] / q[3] * 0.5 + 0.5,
+            q[1] / q[3] * 0.5 + 0.5,
+            q[2] / q[3],
+        ]
+    };
+    for i in 0..n {
+        let (uv, depth) = cases[i];
+        let rt = round_trip[i];
+        assert!((rt.x - uv[0]).abs() < 1e-4 && (rt.y - uv[1]).abs() < 1e-4);
+        assert!((rt.z - depth).abs() < 1e-5, "{:?}: {:?}", cases[i], rt);
+        let uv = uv.map(|x| x as f64);
+        let depth = depth as f64;
+        // stay inside the depth range at both ends
+        let h = 1e-7f64.min((1.0 - depth) / 4.0).min(depth / 4.0);
+        let (fp, fm) = (reproject(uv, depth + h), reproject(uv, depth - h));
+        let g = [grad[i].x, grad[i].y, grad[i].z];
+        for k in 0..3 {
+            let fd = (fp[k] - fm[k]) / (2.0 * h);
+            assert!(
+                (g[k] as f64 - fd).abs() < 1e-2 * fd.abs() + 1e-3,
+                "{:?}, component {}: {} vs {}",
+                cases[i],
+                k,
+                g[k],
+                fd
+            );
+        }
+    }
+}
+#[test]
+fn autodiff_aastep() {
+    use luisa::lang::functions::aastep;
+    let device = get_device();
+    let n = 1024;
+    let (threshold, width) = (0.5f32, 0.1f32);
+    // x sweeps [0.25, 0.75), across the transition band [0.45, 0.55]
+    let xs: Vec<f32> = (0..n).map(|i| 0.25 + 0.5 * i as f32 / n as f32).collect();
+    let x_buf: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let value: Buffer<f32> = device.create_buffer(n);
+    let d_threshold: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x_buf.var().read(tid);
+            let t = threshold.expr();
+            autodiff(|| {
+                requires_grad(t);
+                let y = aastep(t, x, width);
+                value.var().write(tid, y);
+                backward(y);
+                d_threshold.var().write(tid, gradient(t));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let d_threshold = d_threshold.copy_to_vec();
+    let host = |t: f64, x: f64| {
+        let s = ((x - t) / width as f64 + 0.5).clamp(0.0, 1.0);
+        s * s * (3.0 - 2.0 * s)
+    };
+    for i in 0..n {
+        let x = xs[i] as f64;
+        // distance from the threshold in units of half the band
+        let dist = (x - threshold as f64).abs() / (width as f64 / 2.0);
+        if x < 0.449 {
+            assert_eq!(value[i], 0.0, "x = {}", x);
+        } else if x > 0.551 {
+            assert_eq!(value[i], 1.0, "x = {}", x);
+        }
+        // smooth: the step between neighbouring samples is bounded by the
+        // maximum slope 1.5 / width times the sample spacing
+        if i > 0 {
+            let dx = (xs[i] - xs[i - 1]) as f64;
+            assert!(value[i] >= value[i - 1]);
+            assert!(((value[i] - value[i - 1]) as f64) <= 1.5 / width as f64 * dx + 1e-6);
+        }
+        let h = 1e-5;
+        let fd = (host(threshold as f64 + h, x) - host(threshold as f64 - h, x)) / (2.0 * h);
+        if dist < 0.98 {
+            assert!(d_threshold[i] < 0.0, "x = {}", x);
+            assert!(
+                (d_threshold[i] as f64 - fd).abs() < 1e-2 * fd.abs() + 1e-3,
+                "x = {}: {} vs {}",
+                x,
+                d_threshold[i],
+                fd
+            );
+        } else if dist > 1.02 {
+            assert_eq!(d_threshold[i], 0.0, "x = {}", x);
+        }
+    }
+}
+#[test]
+fn autodiff_soft_bin() {
+    use luisa::lang::stats::{soft_bin, soft_bin_backward};
+    let device = get_device();
+    let n = 256;
+    let bins = 16;
+    let bandwidth = 0.05f32;
+    let mut rng = StdRng::seed_from_u64(0);
+    let values: Vec<f32> = (0..n).map(|_| rng.gen::<f32>()).collect();
+    let centers: Vec<f32> = (0..bins).map(|k| (k as f32 + 0.5) / bins as f32).collect();
+    let target: Vec<f32> = (0..bins).map(|_| rng.gen::<f32>() * 16.0).collect();
+    let value_buf = device.create_buffer_from_slice(&values);
+    let center_buf = device.create_buffer_from_slice(&centers);
+    let hist = device.create_buffer::<f32>(bins);
+    hist.fill(0.0);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let v = value_buf.var().read(dispatch_id().x);
+            soft_bin(v, &center_buf.var(), bandwidth, &hist.var());
+        }),
+    )
+    .dispatch([n as u32, 1, 1]);
+    // loss = sum_k (hist_k - target_k)^2
+    let host_hist = |values: &[f32], moved: Option<(usize, f64)>| {
+        let mut h = vec![0.0f64; bins];
+        for (i, &v) in values.iter().enumerate() {
+            let v = match moved {
+                Some((j, dv)) if j == i => v as f64 + dv,
+                _ => v as f64,
+            };
+            for k in 0..bins {
+                let d = (v - centers[k] as f64) / bandwidth as f64;
+                h[k] += (-0.5 * d * d).exp();
+            }
+        }
+        h
+    };
+    let loss = |h: &[f64]| {
+        h.iter()
+            .zip(&target)
+            .map(|(h, &t)| (h - t as f64).powi(2))
+            .sum::<f64>()
+    };
+    let expected = host_hist(&values, None);
+    let hist = hist.copy_to_vec();
+    for k in 0..bins {
+        assert!((hist[k] as f64 - expected[k]).abs() < 1e-3 * expected[k] + 1e-4);
+    }
+    let grad_hist: Vec<f32> = (0..bins).map(|k| 2.0 * (hist[k] - target[k])).collect();
+    let grad_hist = device.create_buffer_from_slice(&grad_hist);
+    let grad = device.create_buffer::<f32>(n);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            let v = value_buf.var().read(i);
+            let g = soft_bin_backward(v, &center_buf.var(), bandwidth, &grad_hist.var());
+            grad.var().write(i, g);
+        }),
+    )
+    .dispatch([n as u32, 1, 1]);
+    let grad = grad.copy_to_vec();
+    // the gradient of the loss is in the hundreds; the absolute tolerance
+    // covers the f32 histogram the backward pass reads
+    let h = 1e-5;
+    for i in 0..n {
+        let fd = (loss(&host_hist(&values, Some((i, h))))
+            - loss(&host_hist(&values, Some((i, -h)))))
+            / (2.0 * h);
+        assert!(grad[i] != 0.0, "value {} = {}", i, values[i]);
+        assert!(
+            (grad[i] as f64 - fd).abs() < 1e-2 * fd.abs() + 0.5,
+            "value {} = {}: {} vs {}",
+            i,
+            values[i],
+            grad[i],
+            fd
+        );
+    }
+}
+#[test]
+fn autodiff_saturate_vec() {
+    use luisa::lang::functions::{clamp01, saturate_vec};
+    let device = get_device();
+    let n = 1024;
+    // x sweeps [-1, 2), avoiding the kinks at 0 and 1
+    let xs: Vec<f32> = (0..n)
+        .map(|i| -1.0 + 3.0 * (i as f32 + 0.5) / n as f32)
+        .collect();
+    let x_buf: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let value: Buffer<f32> = device.create_buffer(n);
+    let dx: Buffer<f32> = device.create_buffer(n);
+    let dv: Buffer<Float3> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x_buf.var().read(tid);
+            let v = Float3::expr(x, x - 0.5f32, x + 0.5f32);
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(v);
+                let y = clamp01(x);
+                value.var().write(tid, y);
+                backward(y + saturate_vec(v).reduce_sum());
+                dx.var().write(tid, gradient(x));
+                dv.var().write(tid, gradient(v));
+            });
+        }),
+    );
+    let dump = kernel.dump().to_lowercase();
+    assert!(dump.contains("min") && dump.contains("max"));
+    assert!(!dump.contains("select") && !dump.contains("clamp") && !dump.contains("if "));
+    kernel.dispatch([n as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let dx = dx.copy_to_vec();
+    let dv = dv.copy_to_vec();
+    let inside = |x: f32| if (0.0..=1.0).contains(&x) { 1.0 } else { 0.0 };
+    for i in 0..n {
+        let x = xs[i];
+        assert_eq!(value[i], x.clamp(0.0, 1.0));
+        assert_eq!(dx[i], inside(x), "x = {}", x);
+        assert_eq!(dv[i].x, inside(x), "x = {}", x);
+        assert_eq!(dv[i].y, inside(x - 0.5), "x = {}", x);
+        assert_eq!(dv[i].z, inside(x + 0.5), "x = {}", x);
+    }
+}
+#[test]
+fn autodiff_sdf_segment_line() {
+    use luisa::lang::sdf::{sdf_line, sdf_segment};
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    // (t, s): p = a + t (b - a) + s * normal; t near 0 and 1 puts p around
+    // the endpoints, on both sides of where the projection is clamped
+    let ts = [-0.3f64, -0.02, 0.02, 0.5, 0.98, 1.02, 1.3];
+    let cases: Vec<[f64; 6]> = (0..1024)
+        .map(|i| {
+            let a = [rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)];
+            let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+            let len = rng.gen_range(0.5..2.0);
+            let b = [a[0] + len * angle.cos(), a[1] + len * angle.sin()];
+            let t = ts[i % ts.len()];
+            let s = rng.gen_range(0.05..0.5) * if rng.gen::<bool>() { 1.0 } else { -1.0 };
+            let p = [
+                a[0] + t * (b[0] - a[0]) - s * angle.sin(),
+                a[1] + t * (b[1] - a[1]) + s * angle.cos(),
+            ];
+            [p[0], p[1], a[0], a[1], b[0], b[1]]
+        })
+        .collect();
+    let n = cases.len();
+    let input: Buffer<f32> = device.create_buffer_from_fn(n * 6, |i| cases[i / 6][i % 6] as f32);
+    // value and gradients with respect to p, a and b
+    let eval = |sdf: fn(Expr<Float2>, Expr<Float2>, Expr<Float2>) -> Expr<f32>| {
+        let out: Buffer<f32> = device.create_buffer(n * 7);
+        let kernel = Kernel::<fn()>::new(
+            &device,
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let x = |k: u32| input.var().read(tid * 6u32 + k);
+                let p = Float2::expr(x(0), x(1));
+                let a = Float2::expr(x(2), x(3));
+                let b = Float2::expr(x(4), x(5));
+                let write = |k: u32, v: Expr<f32>| out.var().write(tid * 7u32 + k, v);
+                autodiff(|| {
+                    requires_grad(p);
+                    requires_grad(a);
+                    requires_grad(b);
+                    let d = sdf(p, a, b);
+                    write(0, d);
+                    backward(d);
+                    let (gp, ga, gb) = (gradient(p), gradient(a), gradient(b));
+                    write(1, gp.x);
+                    write(2, gp.y);
+                    write(3, ga.x);
+                    write(4, ga.y);
+                    write(5, gb.x);
+                    write(6, gb.y);
+                });
+            }),
+        );
+        kernel.dispatch([n as u32, 1, 1]);
+        out.copy_to_vec()
+    };
+    let outputs = [
+        eval(|p, a, b| sdf_segment(p, a, b)),
+        eval(|p, a, b| sdf_line(p, a, b)),
+    ];
+    let segment = |x: &[f64; 6]| {
+        let (pa, ba) = ([x[0] - x[2], x[1] - x[3]], [x[4] - x[2], x[5] - x[3]]);
+        let h = ((pa[0] * ba[0] + pa[1] * ba[1]) / (ba[0] * ba[0] + ba[1] * ba[1])).clamp(0.0, 1.0);
+        (pa[0] - ba[0] * h).hypot(pa[1] - ba[1] * h)
+    };
+    let line = |x: &[f64; 6]| {
+        let (pa, ba) = ([x[0] - x[2], x[1] - x[3]], [x[4] - x[2], x[5] - x[3]]);
+        (ba[0] * pa[1] - ba[1] * pa[0]) / ba[0].hypot(ba[1])
+    };
+    for (i, case) in cases.iter().enumerate() {
+        for (f, host) in [&segment as &dyn Fn(&[f64; 6]) -> f64, &line]
+            .iter()
+            .enumerate()
+        {
+            let got = &outputs[f][i * 7..i * 7 + 7];
+            assert!(
+                (got[0] as f64 - host(case)).abs() < 1e-5,
+                "case {}: {:?}",
+                i,
+                case
+            );
+            for k in 0..6 {
+                let h = 1e-6;
+                let (mut plus, mut minus) = (*case, *case);
+                plus[k] += h;
+                minus[k] -= h;
+                let fd = (host(&plus) - host(&minus)) / (2.0 * h);
+                assert!(
+                    (got[1 + k] as f64 - fd).abs() < 1e-3 * fd.abs() + 1e-3,
+                    "case {} ({}), d/dx{}: {} vs {}",
+                    i,
+                    ["segment", "line"][f],
+                    k,
+                    got[1 + k],
+                    fd
+                );
+            }
+        }
+    }
+}
+#[test]
+fn autodiff_noise() {
+    use luisa::lang::noise::{perlin2, simplex3};
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    let point = |rng: &mut StdRng| {
+        Float3::new(
+            rng.gen_range(-8.0..8.0),
+            rng.gen_range(-8.0..8.0),
+            rng.gen_range(-8.0..8.0),
+        )
+    };
+    let points: Vec<Float3> = (0..1024).map(|_| point(&mut rng)).collect();
+    // C1 continuity: walk lines in small steps, crossing many cells, and
+    // bound the change of the gradient between neighbouring samples
+    let (lines, steps, step) = (128usize, 2048usize, 2e-4f32);
+    let walk: Vec<Float3> = (0..lines)
+        .flat_map(|_| {
+            let start = point(&mut rng);
+            let dir = point(&mut rng);
+            let len = (dir.x * dir.x + dir.y * dir.y + dir.z * dir.z).sqrt();
+            (0..steps).map(move |k| {
+                let t = k as f32 * step / len;
+                Float3::new(
+                    start.x + dir.x * t,
+                    start.y + dir.y * t,
+                    start.z + dir.z * t,
+                )
+            })
+        })
+        .collect();
+    let eval = |noise: fn(Expr<Float3>) -> Expr<f32>, points: &[Float3]| {
+        let n = points.len();
+        let input = device.create_buffer_from_slice(points);
+        let value_grad: Buffer<Float4> = device.create_buffer(n);
+        let fd: Buffer<Float3> = device.create_buffer(n);
+        let kernel = Kernel::<fn()>::new(
+            &device,
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let p = input.var().read(tid);
+                autodiff(|| {
+                    requires_grad(p);
+                    let v = noise(p);
+                    backward(v);
+                    let g = gradient(p);
+                    value_grad.var().write(tid, Float4::expr(v, g.x, g.y, g.z));
+                });
+                let h = 1e-3f32;
+                let d = |e: Expr<Float3>| (noise(p + e * h) - noise(p - e * h)) / (2.0f32 * h);
+                fd.var().write(
+                    tid,
+                    Float3::expr(
+                        d(Float3::expr(1.0f32, 0.0f32, 0.0f32)),
+                        d(Float3::expr(0.0f32, 1.0f32, 0.0f32)),
+                        d(Float3::expr(0.0f32, 0.0f32, 1.0f32)),
+                    ),
+                );
+            }),
+        );
+        kernel.dispatch([n as u32, 1, 1]);
+        (value_grad.copy_to_vec(), fd.copy_to_vec())
+    };
+    let noises: [(&str, fn(Expr<Float3>) -> Expr<f32>); 2] = [
+        ("perlin2", |p| perlin2(p.xy())),
+        ("simplex3", |p| simplex3(p)),
+    ];
+    for (name, noise) in noises {
+        let (value_grad, fd) = eval(noise, &points);
+        let mut nonzero = 0;
+        for i in 0..points.len() {
+            let v = value_grad[i];
+            assert!(v.x.abs() <= 1.5, "{} at {:?}: {}", name, points[i], v.x);
+            let grad = [v.y, v.z, v.w];
+            let fd = [fd[i].x, fd[i].y, fd[i].z];
+            for k in 0..3 {
+                assert!(
+                    (grad[k] - fd[k]).abs() < 1e-2 * (1.0 + fd[k].abs()),
+                    "{} at {:?}, d/dp{}: {} vs {}",
+                    name,
+                    points[i],
+                    k,
+                    grad[k],
+                    fd[k]
+                );
+            }
+            nonzero += grad.iter().any(|&g| g != 0.0) as usize;
+        }
+        assert!(nonzero > points.len() * 9 / 10, "{}", name);
+        let (value_grad, _) = eval(noise, &walk);
+        for line in value_grad.chunks(steps) {
+            for w in line.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                let jump = (a.y - b.y)
+                    .abs()
+                    .max((a.z - b.z).abs())
+                    .max((a.w - b.w).abs());
+                assert!(
+                    jump < 0.05,
+                    "{}: gradient jumps by {} between {:?} and {:?}",
+                    name,
+                    jump,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
+#[test]
+fn autodiff_soft_visibility() {
+    use luisa::lang::lighting::soft_visibility;
+    let device = get_device();
+    let n = 1024;
+    let (ray_length, light_radius) = (2.0f32, 0.1f32);
+    let cone = (ray_length * light_radius) as f64;
+    // the clearance sweeps [-2, 2) cone radii, across the penumbra
+    let hs: Vec<f32> = (0..n)
+        .map(|i| (-2.0 + 4.0 * (i as f64 + 0.5) / n as f64) as f32 * cone as f32)
+        .collect();
+    let h_buf: Buffer<f32> = device.create_buffer_from_slice(&hs);
+    let value: Buffer<f32> = device.create_buffer(n);
+    let d_h: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let h = h_buf.var().read(tid);
+            autodiff(|| {
+                requires_grad(h);
+                let v = soft_visibility(h, ray_length, light_radius);
+                value.var().write(tid, v);
+                backward(v);
+                d_h.var().write(tid, gradient(h));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let d_h = d_h.copy_to_vec();
+    let host = |h: f64| {
+        let s = (0.5 + 0.5 * h / cone).clamp(0.0, 1.0);
+        s * s * (3.0 - 2.0 * s)
+    };
+    for i in 0..n {
+        let h = hs[i] as f64;
+        let r = h.abs() / cone;
+        assert!((value[i] as f64 - host(h)).abs() < 1e-5, "h = {}", h);
+        if r < 0.98 {
+            // penumbra: visibility grows as the occluder moves off the ray
+            let eps = 1e-6;
+            let fd = (host(h + eps) - host(h - eps)) / (2.0 * eps);
+            assert!(d_h[i] > 0.0, "h = {}", h);
+            assert!(
+                (d_h[i] as f64 - fd).abs() < 1e-3 * fd,
+                "h = {}: {} vs {}",
+                h,
+                d_h[i],
+                fd
+            );
+        } else if r > 1.02 {
+            assert_eq!(d_h[i], 0.0, "h = {}", h);
+            assert_eq!(value[i], if h > 0.0 { 1.0 } else { 0.0 });
+        }
+    }
+}
+#[test]
+fn autodiff_quantize_ste() {
+    use luisa::lang::functions::quantize_ste;
+    let device = get_device();
+    let n = 1024;
+    let levels = 5u32;
+    let xs: Vec<f32> = (0..n)
+        .map(|i| -0.5 + 2.0 * (i as f32 + 0.5) / n as f32)
+        .collect();
+    let x_buf: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let value: Buffer<f32> = device.create_buffer(n);
+    let dx: Buffer<f32> = device.create_buffer(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x_buf.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let y = quantize_ste(x, levels);
+                value.var().write(tid, y);
+                backward(y);
+                dx.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let dx = dx.copy_to_vec();
+    let steps = (levels - 1) as f32;
+    for i in 0..n {
+        let expected = (xs[i].clamp(0.0, 1.0) * steps).round() / steps;
+        assert_eq!(value[i], expected, "x = {}", xs[i]);
+        assert_eq!(dx[i], 1.0, "x = {}", xs[i]);
+    }
+}
+#[test]
+fn autodiff_atan2_origin() {
+    let device = get_device();
+    let origin: Buffer<f32> = device.create_buffer_from_slice(&[0.0, 0.0]);
+    let out: Buffer<f32> = device.create_buffer(3);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let y = origin.var().read(0);
+            let x = origin.var().read(1);
+            autodiff(|| {
+                requires_grad(y);
+                requires_grad(x);
+                let a = y.atan2(x);
+                backward(a);
+                out.var().write(0, a);
+                out.var().write(1, gradient(y));
+                out.var().write(2, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([1, 1, 1]);
+    assert_eq!(out.copy_to_vec(), [0.0, 0.0, 0.0]);
+}
+#[test]
+fn autodiff_nested_second_derivative() {
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    let xs: Vec<f32> = (0..1024).map(|_| rng.gen_range(-2.0..2.0)).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let d1: Buffer<f32> = device.create_buffer(xs.len());
+    let d2: Buffer<f32> = device.create_buffer(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let dy = 0.0f32.var();
+                autodiff(|| {
+                    requires_grad(x);
+                    let y = x * x * x + x.sin();
+                    backward(y);
+                    *dy = gradient(x);
+                });
+                backward(**dy);
+                d1.var().write(tid, **dy);
+                d2.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let d1 = d1.copy_to_vec();
+    let d2 = d2.copy_to_vec();
+    for i in 0..xs.len() {
+        let x = xs[i];
+        let expected_d1 = 3.0 * x * x + x.cos();
+        let expected_d2 = 6.0 * x - x.sin();
+        assert!(
+            (d1[i] - expected_d1).abs() < 1e-3,
+            "{} {}",
+            d1[i],
+            expected_d1
+        );
+        assert!(
+            (d2[i] - expected_d2).abs() < 1e-3,
+            "{} {}",
+            d2[i],
+            expected_d2
+        );
+    }
+}
+#[test]
+#[should_panic(expected = "backward is not called")]
+fn autodiff_nested_requires_own_backward() {
+    let device = get_device();
+    let x: Buffer<f32> = device.create_buffer(1);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let x = x.var().read(0);
+            autodiff(|| {
+                requires_grad(x);
+                autodiff(|| {
+                    requires_grad(x);
+                });
+                backward(x);
+            });
+        }),
+    );
+}
+#[test]
+fn autodiff_forward_tangent() {
+    fn f(x: f64) -> [f64; 5] {
+        // columns of the matrix and the vector it multiplies
+        let a = [x, 2.0, x * x];
+        let b = [1.0, x, 3.0];
+        let c = [-x, 0.5, 1.0];
+        let v = [x, 1.0, 2.0];
+        let m = |i: usize| a[i] * v[0] + b[i] * v[1] + c[i] * v[2];
+        [x.sin(), x.exp(), m(0), m(1), m(2)]
+    }
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    let xs: Vec<f32> = (0..1024).map(|_| rng.gen_range(-2.0..2.0)).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let t: Buffer<f32> = device.create_buffer(xs.len() * 5);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x.var().read(tid);
+            forward_autodiff(1, || {
+                propagate_gradient(x, &[1.0f32.expr()]);
+                let m = Mat3::expr(
+                    Float3::expr(x, 2.0f32, x * x),
+                    Float3::expr(1.0f32, x, 3.0f32),
+                    Float3::expr(-x, 0.5f32, 1.0f32),
+                );
+                let mv = m * Float3::expr(x, 1.0f32, 2.0f32);
+                let t = t.var();
+                t.write(tid * 5u32, tangent(x.sin()));
+                t.write(tid * 5u32 + 1u32, tangent(x.exp()));
+                let tmv = tangent(mv);
+                t.write(tid * 5u32 + 2u32, tmv.x);
+                t.write(tid * 5u32 + 3u32, tmv.y);
+                t.write(tid * 5u32 + 4u32, tmv.z);
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let t = t.copy_to_vec();
+    let eps = 1e-4;
+    for i in 0..xs.len() {
+        let x = xs[i] as f64;
+        let (hi, lo) = (f(x + eps), f(x - eps));
+        for j in 0..5 {
+            let fd = (hi[j] - lo[j]) / (2.0 * eps);
+            let ad = t[i * 5 + j] as f64;
+            assert!(
+                (ad - fd).abs() < 1e-3 * fd.abs().max(1.0),
+                "x = {}, output {}: ad = {}, fd = {}",
+                x,
+                j,
+                ad,
+                fd
+            );
+        }
+    }
+}
+#[test]
+fn autodiff_bilateral_weight() {
+    use luisa::lang::filter::bilateral_weight;
+    // sigmas stay away from zero, where the weight is not differentiable
+    autodiff_helper(
+        0.5..2.0,
+        1024 * 1024,
+        9,
+        track!(|inputs| {
+            let center = Float3::expr(inputs[0], inputs[1], inputs[2]);
+            let neighbor = Float3::expr(inputs[3], inputs[4], inputs[5]);
+            let spatial_dist = inputs[6];
+            let sigma_spatial = inputs[7];
+            let sigma_range = inputs[8];
+            bilateral_weight(center, neighbor, spatial_dist, sigma_spatial, sigma_range)
+        }),
+    );
+}
+#[test]
+fn autodiff_buffer_read_grad_accumulates() {
+    let device = get_device();
+    let (n, m) = (1024u32, 16u32);
+    let mut rng = StdRng::seed_from_u64(0);
+    let xs: Vec<f32> = (0..m).map(|_| rng.gen_range(-2.0..2.0)).collect();
+    let x: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let dx: Buffer<f32> = device.create_buffer_from_fn(m as usize, |_| 0.0);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            autodiff(|| {
+                // n / m threads read each element
+                let v = x.var().read_grad(tid % m, &dx.var());
+                backward(v.sin() * (tid / m).as_f32());
+            });
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    let dx = dx.copy_to_vec();
+    let weight = (0..n / m).sum::<u32>() as f32;
+    for i in 0..m as usize {
+        let expected = xs[i].cos() * weight;
+        assert!(
+            (dx[i] - expected).abs() < 1e-4 * weight,
+            "{}: {} {}",
+            i,
+            dx[i],
+            expected
+        );
+    }
+}
+#[test]
+fn autodiff_tex2d_sample_fits_target() {
+    let device = get_device();
+    let tex = device.create_tex2d::<Float4>(PixelStorage::Float4, 2, 2, 1);
+    let grad: Buffer<f32> = device.create_buffer(2 * 2 * 4);
+    let target = Float4::new(0.2, 0.4, 0.6, 0.8);
+    let n = 8u32;
+    let (inv_n, inv_count) = (1.0 / n as f32, 1.0 / (n * n) as f32);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let p = dispatch_id().xy();
+            let uv = (p.as_float2() + 0.5f32) * inv_n;
+            autodiff(|| {
+                let s = tex
+                    .var()
+                    .sample_with_grad(uv, AddressMode::Clamp, &grad.var());
+                let d = s - target.expr();
+                backward(d.dot(d) * inv_count);
+            });
+        }),
+    );
+    let mut texels = vec![Float4::new(0.0, 0.0, 0.0, 0.0); 4];
+    for _ in 0..50 {
+        tex.view(0).copy_from(&texels);
+        grad.fill(0.0);
+        kernel.dispatch([n, n, 1]);
+        let g = grad.copy_to_vec();
+        for (i, t) in texels.iter_mut().enumerate() {
+            t.x -= g[i * 4];
+            t.y -= g[i * 4 + 1];
+            t.z -= g[i * 4 + 2];
+            t.w -= g[i * 4 + 3];
+        }
+    }
+    for t in &texels {
+        let d = [
+            t.x - target.x,
+            t.y - target.y,
+            t.z - target.z,
+            t.w - target.w,
+        ];
+        assert!(d.iter().all(|d| d.abs() < 1e-3), "{:?}", t);
+    }
+
+    // at the corner, wrapping blends all four texels while clamping
+    // returns the corner texel
+    let texels = [1.0f32, 2.0, 3.0, 4.0].map(|x| Float4::new(x, x, x, x));
+    tex.view(0).copy_from(&texels);
+    let out: Buffer<f32> = device.create_buffer(2);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let uv = Float2::splat_expr(0.0f32);
+            out.write(0, tex.var().sample(uv, AddressMode::Clamp).x);
+            out.write(1, tex.var().sample(uv, AddressMode::Wrap).x);
+        }),
+    );
+    kernel.dispatch([1, 1, 1]);
+    let out = out.copy_to_vec();
+    assert!((out[0] - 1.0).abs() < 1e-5, "{}", out[0]);
+    assert!((out[1] - 2.5).abs() < 1e-5, "{}", out[1]);
+}
+#[test]
+fn autodiff_clamp() {
+    let device = get_device();
+    // x below, at, inside, at and above [lo, hi]; the boundary counts as
+    // inside. Random inputs would land within the finite difference step
+    // of a kink, so the points are fixed.
+    let (lo, hi) = (-0.5f32, 1.0f32);
+    let xs = [-2.0f32, -1.0, lo, 0.0, 0.5, hi, 2.0];
+    let x_buf: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let bounds: Buffer<f32> = device.create_buffer_from_slice(&[lo, hi]);
+    let out: Buffer<f32> = device.create_buffer(xs.len() * 8);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x_buf.var().read(tid);
+            let lo = bounds.var().read(0);
+            let hi = bounds.var().read(1);
+            let out = out.var();
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(lo);
+                requires_grad(hi);
+                backward(x.clamp(lo, hi));
+                out.write(tid * 8u32, gradient(x));
+                out.write(tid * 8u32 + 1u32, gradient(lo));
+                out.write(tid * 8u32 + 2u32, gradient(hi));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(lo);
+                requires_grad(hi);
+                let v = Float3::splat_expr(x).clamp(Float3::splat_expr(lo), Float3::splat_expr(hi));
+                backward(v.x + v.y * 2.0f32 + v.z * 3.0f32);
+                out.write(tid * 8u32 + 3u32, gradient(x));
+                out.write(tid * 8u32 + 4u32, gradient(lo));
+                out.write(tid * 8u32 + 5u32, gradient(hi));
+            });
+            autodiff(|| {
+                requires_grad(x);
+                let y = x.saturate();
+                backward(y);
+                out.write(tid * 8u32 + 6u32, y);
+                out.write(tid * 8u32 + 7u32, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let out = out.copy_to_vec();
+    for (i, &x) in xs.iter().enumerate() {
+        let o = &out[i * 8..i * 8 + 8];
+        let expected = if x < lo {
+            [0.0, 1.0, 0.0]
+        } else if x > hi {
+            [0.0, 0.0, 1.0]
+        } else {
+            [1.0, 0.0, 0.0]
+        };
+        assert_eq!(o[0..3], expected, "clamp at {}", x);
+        assert_eq!(o[3..6], expected.map(|g| g * 6.0), "vector clamp at {}", x);
+        assert_eq!(o[6], x.clamp(0.0, 1.0), "saturate({})", x);
+        let inside = (0.0..=1.0).contains(&x);
+        assert_eq!(o[7], if inside { 1.0 } else { 0.0 }, "saturate'({})", x);
+    }
+}
+#[test]
+fn autodiff_step_zero_gradient() {
+    let device = get_device();
+    let xs = [-1.0f32, -0.5, 0.0, 0.5, 1.0];
+    let x_buf: Buffer<f32> = device.create_buffer_from_slice(&xs);
+    let value: Buffer<f32> = device.create_buffer(xs.len());
+    let d_x: Buffer<f32> = device.create_buffer(xs.len());
+    let d_edge: Buffer<f32> = device.create_buffer(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = x_buf.var().read(tid);
+            let edge = x_buf.var().read(2);
+            autodiff(|| {
+                requires_grad(x);
+                requires_grad(edge);
+                let y = x.step(edge);
+                backward(y);
+                value.var().write(tid, y);
+                d_x.var().write(tid, gradient(x));
+                d_edge.var().write(tid, gradient(edge));
+            });
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let value = value.copy_to_vec();
+    let d_x = d_x.copy_to_vec();
+    let d_edge = d_edge.copy_to_vec();
+    for i in 0..xs.len() {
+        assert_eq!(value[i], if xs[i] < 0.0 { 0.0 } else { 1.0 }, "{}", xs[i]);
+        assert_eq!(d_x[i], 0.0, "{}", xs[i]);
+        assert_eq!(d_edge[i], 0.0, "{}", xs[i]);
+    }
+}
+#[test]
+fn autodiff_quat_rotate() {
+    use luisa::lang::geometry::Quat;
+    autodiff_helper(
+        0.1..1.0,
+        1024 * 1024,
+        4,
+        track!(|inputs| {
+            let axis = Float3::expr(inputs[0], inputs[1], inputs[2]);
+            let q = Quat::from_axis_angle_expr(axis, inputs[3] * 3.0f32);
+            let p = q.rotate(Float3::expr(0.3f32, -1.0f32, 2.0f32));
+            p.x + p.y * 0.5f32 + p.z * 0.25f32
+        }),
+    );
+}
+#[test]
+fn autodiff_while_pow() {
+    let device = get_device();
+    let n = 1024;
+    let mut rng = StdRng::seed_from_u64(0);
+    let x_buf = device.create_buffer_from_fn(n, |_| rng.gen_range(0.5f32..1.5f32));
+    let value = device.create_buffer::<f32>(n);
+    let d_x = device.create_buffer::<f32>(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            // a trip count only known on the device
+            let power = tid % 8u32;
+            let x = x_buf.var().read(tid);
+            autodiff(|| {
+                requires_grad(x);
+                let y = autodiff_while(x, 1.0f32.expr(), 8, |i, _| i < power, |x, s| s * x);
+                backward(y);
+                value.var().write(tid, y);
+                d_x.var().write(tid, gradient(x));
+            });
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let x = x_buf.copy_to_vec();
+    let value = value.copy_to_vec();
+    let d_x = d_x.copy_to_vec();
+    for i in 0..n {
+        let power = (i % 8) as i32;
+        let expected = x[i].powi(power);
+        let expected_d = power as f32 * x[i].powi(power - 1);
+        assert!((value[i] - expected).abs() < 1e-4 * expected.abs().max(1.0));
+        assert!(
+            (d_x[i] - expected_d).abs() < 1e-3 * expected_d.abs().max(1.0),
+            "x = {}, n = {}: {} != {}",
+            x[i],
+            power,
+            d_x[i],
+            expected_d
+        );
+    }
+}
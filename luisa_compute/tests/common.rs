@@ -13,6 +13,11 @@ pub fn device_name() -> String {
         Err(_) => "cpu".to_string(),
     }
 }
+pub fn get_context() -> Context {
+    let curr_exe = current_exe().unwrap();
+    let runtime_dir = curr_exe.parent().unwrap().parent().unwrap();
+    Context::new(runtime_dir)
+}
 pub fn get_device() -> Device {
     let show_log = match std::env::var("LUISA_TEST_LOG") {
         Ok(log) => log == "1",
@@ -24,9 +29,7 @@ pub fn get_device() -> Device {
         }
         libc::signal(libc::SIGSEGV, _signal_handler as usize);
     });
-    let curr_exe = current_exe().unwrap();
-    let runtime_dir = curr_exe.parent().unwrap().parent().unwrap();
-    let ctx = Context::new(runtime_dir);
+    let ctx = get_context();
     let device = device_name();
     let device = ctx.create_device(&device);
     device.create_buffer_from_slice(&[1.0f32]);
@@ -1,9 +1,11 @@
 use std::cell::RefCell;
 
+use luisa::lang::functions::{block_id, thread_id, warp_ballot, warp_reduce_max, warp_reduce_sum};
 use luisa::lang::types::array::VLArrayVar;
 use luisa::lang::types::dynamic::*;
 use luisa::lang::types::vector::{alias::*, Mat2};
 use luisa::prelude::*;
+use luisa::runtime::{Features, KernelLoadError, Properties, RUN_KERNELS_PER_CLOSURE};
 use luisa_compute as luisa;
 use luisa_compute_api_types::StreamTag;
 use rand::prelude::*;
@@ -48,6 +50,80 @@ fn event() {
     assert_eq!(v[0], (1 + 3) * (4 + 5));
 }
 #[test]
+fn event_stream_signal_wait() {
+    let device = get_device();
+    let n = 1 << 20;
+    let x = device.create_buffer::<u32>(n);
+    let y = device.create_buffer::<u32>(n);
+    let fill = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            x.write(i, i * 3u32);
+        }),
+    );
+    let consume = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            y.write(i, x.read(i) + 1u32);
+        }),
+    );
+    let copy = device.create_stream(StreamTag::Compute);
+    let compute = device.create_stream(StreamTag::Compute);
+    let event = device.create_event();
+    assert_eq!(event.last_signaled(), 0);
+    let scope = copy.scope();
+    scope.submit([fill.dispatch_async([n as u32, 1, 1])]);
+    let ticket = copy.signal(&event);
+    assert_eq!(ticket, 1);
+    assert_eq!(event.last_signaled(), 1);
+    // the compute stream waits for the fill on the device
+    compute.wait(&event).with_scope(|s| {
+        s.submit([consume.dispatch_async([n as u32, 1, 1])]);
+    });
+    assert!(event.is_completed(ticket));
+    drop(scope);
+    let y = y.copy_to_vec();
+    for i in 0..n {
+        assert_eq!(y[i], i as u32 * 3 + 1);
+    }
+}
+#[test]
+fn stream_submit_async_join() {
+    let device = get_device();
+    let n = 1 << 20;
+    let a = device.create_buffer::<f32>(n);
+    let b = device.create_buffer::<f32>(n);
+    let fill_a = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            a.write(i, i.as_f32().sin());
+        }),
+    );
+    let fill_b = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            b.write(i, i.as_f32() * 2.0f32);
+        }),
+    );
+    let stream_a = device.create_stream(StreamTag::Compute);
+    let stream_b = device.create_stream(StreamTag::Compute);
+    let done_a = stream_a.submit_async([fill_a.dispatch_async([n as u32, 1, 1])]);
+    let done_b = stream_b.submit_async([fill_b.dispatch_async([n as u32, 1, 1])]);
+    done_a.wait();
+    done_b.wait();
+    assert!(done_a.is_completed() && done_b.is_completed());
+    let a = a.copy_to_vec();
+    let b = b.copy_to_vec();
+    for i in 0..n {
+        assert!((a[i] - (i as f32).sin()).abs() < 1e-3);
+        assert_eq!(b[i], i as f32 * 2.0);
+    }
+}
+#[test]
 fn nested_callable_capture_by_value() {
     let device = get_device();
     let add = track!(Callable::<fn(Expr<f32>, Expr<f32>) -> Expr<f32>>::new(
@@ -297,6 +373,58 @@ fn nested_callable_capture_buffer_var() {
     }
 }
 #[test]
+fn shared_callable_emitted_once() {
+    fn shade(x: Expr<f32>) -> Expr<f32> {
+        track!({
+            let y = x * x + 2.0f32 * x + 1.0f32;
+            (y.sin() + y.cos()) * y.sqrt()
+        })
+    }
+    type Shade = fn(Expr<f32>) -> Expr<f32>;
+    let device = get_device();
+    let out = device.create_buffer::<f32>(64);
+    let record = |callables: &[&Callable<Shade>]| {
+        KernelDef::<fn()>::new(&device, &|| {
+            let tid = dispatch_id().x;
+            let x = track!(tid.as_f32() * 0.01f32);
+            let mut acc = 0.0f32.expr();
+            for i in 0..10 {
+                let offset = i as f32 * 0.1;
+                let f = callables[i % callables.len()];
+                acc = track!(acc + f.call(x + offset));
+            }
+            out.var().write(tid, acc);
+        })
+    };
+    // callables recorded separately are defined separately
+    let a = Callable::<Shade>::new(&device, shade);
+    let b = Callable::<Shade>::new(&device, shade);
+    assert_eq!(record(&[&a, &b]).raw_def().num_callables(), 2);
+    // shared callables of the same function are defined once
+    let a = device.create_shared_callable::<Shade, _>(shade);
+    let b = device.create_shared_callable::<Shade, _>(shade);
+    let def = record(&[&a, &b]);
+    assert_eq!(def.raw_def().num_callables(), 1);
+    let kernel = device.compile_kernel_def(&def);
+    kernel.dispatch([64, 1, 1]);
+    let out = out.copy_to_vec();
+    for (tid, &v) in out.iter().enumerate() {
+        let expected = (0..10)
+            .map(|i| {
+                let x = tid as f32 * 0.01 + i as f32 * 0.1;
+                let y = x * x + 2.0 * x + 1.0;
+                (y.sin() + y.cos()) * y.sqrt()
+            })
+            .sum::<f32>();
+        assert!(
+            (v - expected).abs() < 1e-3 * expected.abs().max(1.0),
+            "{} vs {}",
+            v,
+            expected
+        );
+    }
+}
+#[test]
 #[should_panic]
 fn callable_different_device() {
     let device1 = get_device();
@@ -763,6 +891,30 @@ fn vec_bit_minmax() {
     }
 }
 #[test]
+fn recip_fast_edge_cases() {
+    let device = get_device();
+    // squaring these over- or underflows f32
+    let xs = [2e19f32, -1e30, 1e-20, -5e-25, 3.0, 0.0];
+    let x = device.create_buffer_from_slice(&xs);
+    let y = device.create_buffer::<f32>(xs.len());
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            y.write(tid, x.read(tid).recip_fast());
+        }),
+    );
+    kernel.dispatch([xs.len() as u32, 1, 1]);
+    let y = y.copy_to_vec();
+    for (x, y) in xs.iter().zip(y) {
+        if *x == 0.0 {
+            assert_eq!(y, f32::INFINITY);
+        } else {
+            assert!(((y - 1.0 / x) * x).abs() < 1e-3, "x = {}, y = {}", x, y);
+        }
+    }
+}
+#[test]
 fn vec_permute() {
     let device = get_device();
     let v2: Buffer<Int2> = device.create_buffer(1024);
@@ -995,6 +1147,72 @@ fn array_read_write4() {
     }
 }
 #[test]
+fn for_range_dynamic_count() {
+    let device = get_device();
+    let sums = device.create_buffer::<i32>(1024);
+    let fibs = device.create_buffer::<i32>(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            // the trip count is only known on the device
+            let n = (tid % 17u32).as_i32();
+            let sum = 0i32.var();
+            let a = 0i32.var();
+            let b = 1i32.var();
+            for_range(0i32.expr()..n, |i| {
+                *sum += i;
+                let next = a + b;
+                *a = b;
+                *b = next;
+            });
+            sums.write(tid, sum);
+            fibs.write(tid, a);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let sums = sums.copy_to_vec();
+    let fibs = fibs.copy_to_vec();
+    for i in 0..1024 {
+        let n = i as i32 % 17;
+        assert_eq!(sums[i], n * (n - 1) / 2);
+        let (mut a, mut b) = (0, 1);
+        for _ in 0..n {
+            (a, b) = (b, a + b);
+        }
+        assert_eq!(fibs[i], a);
+    }
+}
+#[test]
+fn for_unrolled_matches_rolled() {
+    let device = get_device();
+    let rolled: Buffer<[i32; 4]> = device.create_buffer(1024);
+    let unrolled: Buffer<[i32; 4]> = device.create_buffer(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let a = Var::<[i32; 4]>::zeroed();
+            let b = Var::<[i32; 4]>::zeroed();
+            for_range(0..6u32, |j| {
+                for_range(0..4u32, |i| {
+                    a.write(i, a.read(i) * 3i32 + tid.as_i32() + (i * j).as_i32());
+                });
+                for_unrolled(0..4u32, |i| {
+                    let i = i.expr();
+                    b.write(i, b.read(i) * 3i32 + tid.as_i32() + (i * j).as_i32());
+                });
+            });
+            rolled.var().write(tid, a);
+            unrolled.var().write(tid, b);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let rolled = rolled.copy_to_vec();
+    assert_eq!(rolled, unrolled.copy_to_vec());
+    assert_eq!(rolled[1], [364, 543, 722, 901]);
+}
+#[test]
 fn array_read_write2() {
     let device = get_device();
     let x: Buffer<[i32; 4]> = device.create_buffer(1024);
@@ -1192,6 +1410,55 @@ fn buffer_view_copy() {
     }
 }
 #[test]
+fn buffer_view_copy_from_view() {
+    let device = get_device();
+    let n = 1024;
+    let src = device.create_buffer::<f32>(n);
+    let dst = device.create_buffer::<f32>(n);
+    src.fill_fn(|i| i as f32);
+    dst.fill(-1.0);
+    dst.view(100..300).copy_from_view(&src.view(500..700));
+    let data = dst.copy_to_vec();
+    for i in 0..n {
+        if (100..300).contains(&i) {
+            assert_eq!(data[i], (i + 400) as f32);
+        } else {
+            assert_eq!(data[i], -1.0);
+        }
+    }
+}
+#[test]
+fn buffer_copy_to_buffer_double_buffering() {
+    let device = get_device();
+    let n = 1024;
+    let state = device.create_buffer_from_fn(n, |i| i as u32);
+    let prev = device.create_buffer::<u32>(n);
+    let step = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            state.write(i, prev.read(i) + 1u32);
+        }),
+    );
+    let stream = device.default_stream();
+    stream.with_scope(|s| {
+        for _ in 0..3 {
+            s.submit([
+                state.copy_to_buffer_async(&prev),
+                step.dispatch_async([n as u32, 1, 1]),
+            ]);
+        }
+    });
+    assert_eq!(
+        prev.copy_to_vec(),
+        (0..n as u32).map(|i| i + 2).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        state.copy_to_vec(),
+        (0..n as u32).map(|i| i + 3).collect::<Vec<_>>()
+    );
+}
+#[test]
 fn buffer_view() {
     let device = get_device();
     let n = 1024;
@@ -1290,6 +1557,38 @@ fn byte_buffer() {
     }
 }
 
+#[test]
+fn bindless_buffer_read() {
+    let device = get_device();
+    let lens = [16usize, 100, 1000];
+    let buffers = lens
+        .iter()
+        .enumerate()
+        .map(|(s, &len)| device.create_buffer_from_fn(len, |i| (s * 10000 + i) as u32))
+        .collect::<Vec<_>>();
+    let heap = device.create_bindless_array(4);
+    for (s, buffer) in buffers.iter().enumerate() {
+        heap.emplace_buffer(s, buffer);
+    }
+    let out = device.create_buffer::<u32>(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let heap = heap.var();
+            let tid = dispatch_id().x;
+            // the slot is only known on the device
+            let s = tid % 3u32;
+            let i = tid.as_u64() % heap.buffer::<u32>(s).len_expr();
+            out.write(tid, heap.buffer_read::<u32>(s, i));
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let out = out.copy_to_vec();
+    for (tid, v) in out.iter().enumerate() {
+        let s = tid % 3;
+        assert_eq!(*v, (s * 10000 + tid % lens[s]) as u32);
+    }
+}
 #[test]
 #[allow(unused_assignments)]
 fn bindless_byte_buffer() {
@@ -1481,6 +1780,29 @@ pub struct Foo2<T: Value> {
     m: Mat2,
 }
 #[test]
+fn struct_layout_matches_host() {
+    use luisa::lang::types::layout_of;
+    let layout = layout_of::<Foo>();
+    assert_eq!(layout.size, std::mem::size_of::<Foo>());
+    assert_eq!(layout.alignment, std::mem::align_of::<Foo>());
+    let offsets = layout.fields.iter().map(|f| f.offset).collect::<Vec<_>>();
+    assert_eq!(offsets, [0, 8, 16, 32]);
+    assert_eq!(
+        offsets,
+        [
+            std::mem::offset_of!(Foo, i),
+            std::mem::offset_of!(Foo, v),
+            std::mem::offset_of!(Foo, a),
+            std::mem::offset_of!(Foo, m),
+        ]
+    );
+    assert_eq!(layout.fields[2].size, 16);
+    let nested = layout_of::<Bar>();
+    assert_eq!(nested.fields[3].offset, std::mem::offset_of!(Bar, f));
+    assert_eq!(nested.fields[3].size, layout.size);
+    assert!(layout_of::<f32>().fields.is_empty());
+}
+#[test]
 fn soa() {
     let device = get_device();
     let mut rng = thread_rng();
@@ -1535,6 +1857,39 @@ fn soa_view() {
     assert_eq!(bars_data, also_bars_data);
 }
 #[test]
+fn buffer_var_atomics() {
+    let device = get_device();
+    let n = 1024u32;
+    let count = device.create_buffer_from_slice(&[0u32]);
+    let sum = device.create_buffer_from_slice(&[0.0f32]);
+    let min_max = device.create_buffer_from_slice(&[i32::MAX, i32::MIN]);
+    // the ids handed out by exchanging, with the initial value u32::MAX
+    let slot = device.create_buffer_from_slice(&[u32::MAX]);
+    let old = device.create_buffer::<u32>(n as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            count.var().atomic_fetch_add(0, 1u32);
+            sum.var().atomic_fetch_add(0, 1.0f32);
+            let v = i.as_i32() - 300i32;
+            min_max.var().atomic_fetch_min(0, v);
+            min_max.var().atomic_fetch_max(1, v);
+            old.var().write(i, slot.var().atomic_exchange(0, i));
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    assert_eq!(count.copy_to_vec(), [n]);
+    assert_eq!(sum.copy_to_vec(), [n as f32]);
+    assert_eq!(min_max.copy_to_vec(), [-300, n as i32 - 301]);
+    let mut seen = old.copy_to_vec();
+    seen.push(slot.copy_to_vec()[0]);
+    seen.sort();
+    let mut expected = (0..n).collect::<Vec<_>>();
+    expected.push(u32::MAX);
+    assert_eq!(seen, expected);
+}
+#[test]
 fn atomic() {
     let device = get_device();
     let mut rng = thread_rng();
@@ -1820,3 +2175,1774 @@ fn test_tracked() {
         panic!();
     }
 }
+#[test]
+fn convert_tex_rgba8_to_rgba16f() {
+    let device = get_device();
+    let (w, h) = (64u32, 32u32);
+    let src = device.create_tex2d::<Float4>(PixelStorage::Byte4, w, h, 1);
+    let dst = device.create_tex2d::<Float4>(PixelStorage::Half4, w, h, 1);
+    let data = (0..w * h)
+        .map(|i| {
+            let x = (i % w) as u8 * 4;
+            let y = (i / w) as u8 * 8;
+            Ubyte4::new(x, y, 255 - x, 255)
+        })
+        .collect::<Vec<_>>();
+    src.view(0).copy_from(&data);
+    device
+        .default_stream()
+        .scope()
+        .submit([Command::convert_tex(&src.view(0), &dst.view(0))]);
+    let out = dst.view(0).copy_to_vec::<Half4>();
+    for (i, (a, b)) in data.iter().zip(out.iter()).enumerate() {
+        let expected = [a.x, a.y, a.z, a.w].map(|v| v as f32 / 255.0);
+        let actual = [b.x, b.y, b.z, b.w].map(|v| v.to_f32());
+        for c in 0..4 {
+            assert!(
+                (expected[c] - actual[c]).abs() < 1e-3,
+                "texel {}: expected {:?}, got {:?}",
+                i,
+                expected,
+                actual
+            );
+        }
+    }
+    // a second conversion between the same formats reuses the kernel
+    let dst2 = device.create_tex2d::<Float4>(PixelStorage::Half4, w, h, 1);
+    device
+        .default_stream()
+        .scope()
+        .submit([Command::convert_tex(&src.view(0), &dst2.view(0))]);
+    assert_eq!(dst2.view(0).copy_to_vec::<Half4>(), out);
+}
+#[test]
+fn tex2d_generate_mipmaps_constant() {
+    let device = get_device();
+    let stream = device.default_stream();
+    // non-power-of-two: 13x7 -> 6x3 -> 3x1 -> 1x1
+    let (w, h, mips) = (13u32, 7u32, 4u32);
+
+    let gray = device.create_tex2d::<f32>(PixelStorage::Float1, w, h, mips);
+    gray.view(0).copy_from(&vec![0.25f32; (w * h) as usize]);
+    gray.generate_mipmaps(&stream);
+    for level in 0..mips {
+        let view = gray.view(level);
+        let texels = view.copy_to_vec::<f32>();
+        assert_eq!(texels.len(), view.texel_count() as usize);
+        for t in texels {
+            assert!((t - 0.25).abs() < 1e-6, "level {}: {}", level, t);
+        }
+    }
+
+    let color = Ubyte4::new(51, 102, 153, 255);
+    let rgba = device.create_tex2d::<Float4>(PixelStorage::Byte4, w, h, mips);
+    rgba.view(0).copy_from(&vec![color; (w * h) as usize]);
+    rgba.generate_mipmaps(&stream);
+    for level in 0..mips {
+        for t in rgba.view(level).copy_to_vec::<Ubyte4>() {
+            assert_eq!(t, color, "level {}", level);
+        }
+    }
+
+    // generate_mipmaps does not wait, so the texture may be dropped while
+    // its levels are still being filled
+    let dropped = device.create_tex2d::<f32>(PixelStorage::Float1, w, h, mips);
+    dropped.generate_mipmaps(&stream);
+    drop(dropped);
+    device.synchronize();
+}
+#[test]
+fn tex1d_and_tex2d_array_round_trip() {
+    let device = get_device();
+    let n = 37u32;
+    let lut = device.create_tex1d::<f32>(PixelStorage::Float1, n, 1);
+    let (w, h, layers) = (8u32, 4u32, 5u32);
+    let array = device.create_tex2d_array::<Float4>(PixelStorage::Float4, w, h, layers);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let p = dispatch_id();
+            if p.y == 0 && p.z == 0 {
+                lut.var().write(p.x, p.x.as_f32() * 0.5f32);
+            }
+            if p.x < w && p.y < h {
+                let v = Float4::expr(p.x.as_f32(), p.y.as_f32(), p.z.as_f32(), 1.0f32);
+                array.var().write(p.xy(), p.z, v);
+            }
+        }),
+    );
+    kernel.dispatch([n, h, layers]);
+    let lut_texels = lut.view(0).copy_to_vec::<f32>();
+    for (i, t) in lut_texels.iter().enumerate() {
+        assert_eq!(*t, i as f32 * 0.5);
+    }
+    // layers are stored one after another
+    let texels = array.view().copy_to_vec::<Float4>();
+    for layer in 0..layers {
+        for y in 0..h {
+            for x in 0..w {
+                let t = texels[(layer * h * w + y * w + x) as usize];
+                assert_eq!(
+                    [t.x, t.y, t.z, t.w],
+                    [x as f32, y as f32, layer as f32, 1.0]
+                );
+            }
+        }
+    }
+
+    // reading back through the layer index
+    let out = device.create_buffer::<f32>((w * h * layers) as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let p = dispatch_id();
+            let i = (p.z * h + p.y) * w + p.x;
+            out.write(i, array.var().read(p.xy(), p.z).z + lut.var().read(p.x));
+        }),
+    );
+    kernel.dispatch([w, h, layers]);
+    let out = out.copy_to_vec();
+    for (i, v) in out.iter().enumerate() {
+        let (x, layer) = (i as u32 % w, i as u32 / (w * h));
+        assert_eq!(*v, layer as f32 + x as f32 * 0.5);
+    }
+}
+#[test]
+#[cfg(feature = "image")]
+fn tex2d_copy_to_image() {
+    let device = get_device();
+    let (w, h) = (3u32, 2u32);
+    let ldr = device.create_tex2d::<Float4>(PixelStorage::Byte4, w, h, 1);
+    let data = (0..w * h)
+        .map(|i| Ubyte4::new(i as u8, 10 * i as u8, 100, 200))
+        .collect::<Vec<_>>();
+    ldr.view(0).copy_from(&data);
+    let image = ldr.copy_to_image();
+    assert_eq!(image.dimensions(), (w, h));
+    for (i, d) in data.iter().enumerate() {
+        let p = image.get_pixel(i as u32 % w, i as u32 / w);
+        assert_eq!(p.0, [d.x, d.y, d.z, d.w]);
+    }
+
+    // HDR values are clamped
+    let hdr = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    hdr.view(0)
+        .copy_from(&vec![Float4::new(2.0, 0.5, -1.0, 1.0); (w * h) as usize]);
+    for p in hdr.copy_to_image().pixels() {
+        assert_eq!(p.0, [255, 128, 0, 255]);
+    }
+
+    // one channel becomes gray
+    let gray = device.create_tex2d::<f32>(PixelStorage::Float1, w, h, 1);
+    gray.view(0).copy_from(&vec![0.2f32; (w * h) as usize]);
+    for p in gray.copy_to_image().pixels() {
+        assert_eq!(p.0, [51, 51, 51, 255]);
+    }
+}
+#[test]
+fn tex_buffer_round_trip() {
+    let device = get_device();
+    let (w, h) = (32u32, 16u32);
+    let data = device.create_buffer_from_fn((w * h) as usize, |i| {
+        let i = i as u32;
+        Float4::new(i as f32, (i % w) as f32, (i / w) as f32, 1.0)
+    });
+    let tex =
+        device.create_tex_from_buffer::<Float4, Float4>(&data.view(..), w, h, PixelStorage::Float4);
+    let back = device.create_buffer_from_tex::<Float4, Float4>(&tex.view(0));
+    assert_eq!(back.len(), data.len());
+    assert_eq!(back.copy_to_vec(), data.copy_to_vec());
+}
+#[test]
+fn download_buffer_readback() {
+    let device = get_device();
+    let buf = device.create_buffer_download::<u32>(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            buf.write(tid, tid * tid);
+        }),
+    );
+    for _ in 0..2 {
+        kernel.dispatch([1024, 1, 1]);
+        let data = buf.copy_to_vec();
+        for (i, x) in data.iter().enumerate() {
+            assert_eq!(*x, (i * i) as u32);
+        }
+    }
+}
+#[test]
+fn buffer_copy_at_offset() {
+    let device = get_device();
+    let buf = device.create_buffer_from_fn(1024, |i| i as u32);
+    let data = (0..100).map(|i| 10000 + i).collect::<Vec<u32>>();
+    buf.copy_from_at(500, &data);
+    let out = buf.copy_to_vec();
+    for (i, x) in out.iter().enumerate() {
+        if (500..600).contains(&i) {
+            assert_eq!(*x, 10000 + (i - 500) as u32);
+        } else {
+            assert_eq!(*x, i as u32);
+        }
+    }
+    let mut part = vec![0u32; 4];
+    buf.view(512..).copy_to_at(86, &mut part);
+    assert_eq!(part, [10098, 10099, 600, 601]);
+}
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn buffer_copy_at_out_of_bounds() {
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    buf.copy_from_at(1000, &[0u32; 25]);
+}
+#[test]
+fn device_with_scope_synchronizes() {
+    let device = get_device();
+    let buf: Buffer<u32> = device.create_buffer(4096);
+    let kernel = Kernel::<fn(Buffer<u32>, u32)>::new(&device, &|buf, v| {
+        let tid = dispatch_id().x;
+        track!(buf.write(tid, tid + v));
+    });
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    device.with_scope(|s| {
+        s.submit([kernel.dispatch_async([4096, 1, 1], &buf, &1)]);
+        let done = done.clone();
+        s.submit_with_callback([kernel.dispatch_async([4096, 1, 1], &buf, &7)], move || {
+            done.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+    });
+    // the callback runs once the commands have completed on the device
+    assert!(done.load(std::sync::atomic::Ordering::SeqCst));
+    let v = buf.view(..).copy_to_vec();
+    for i in 0..4096 {
+        assert_eq!(v[i], i as u32 + 7);
+    }
+}
+#[test]
+fn rng_dispatch_seed() {
+    use luisa::lang::rng::{dispatch_seed, lcg};
+    let device = get_device();
+    let buf: Buffer<f32> = device.create_buffer(1024);
+    let kernel = Kernel::<fn(Buffer<f32>, u32)>::new(&device, &|buf, seed| {
+        let state = dispatch_seed(seed).var();
+        let x = lcg(state);
+        let y = lcg(state);
+        track!(buf.write(dispatch_id().x, x + y));
+    });
+    let run = |seed: u32| {
+        kernel.dispatch([1024, 1, 1], &buf, &seed);
+        buf.view(..).copy_to_vec()
+    };
+    let a = run(1);
+    let b = run(2);
+    let c = run(1);
+    assert_eq!(a, c);
+    assert_ne!(a, b);
+    assert!(a.iter().all(|x| (0.0..2.0).contains(x)));
+}
+#[test]
+fn rng_integer_hashes() {
+    use luisa::lang::rng::{hash_to_float, pcg_hash, wang_hash};
+    let device = get_device();
+    let inputs = [0u32, 1, 2, 42, 0xdeadbeef, 0xffffffff];
+    // outputs of the reference C implementations
+    let wang = [
+        0xc0a9496au32,
+        0x27922c9d,
+        0xc6793575,
+        0x572f8d19,
+        0x572e7c2d,
+        0x70f499d3,
+    ];
+    let pcg = [
+        0x07bb2fe2u32,
+        0xa8beea3c,
+        0x7a7ecc88,
+        0x48f432ff,
+        0x67299972,
+        0xe62a4902,
+    ];
+    let x = device.create_buffer_from_slice(&inputs);
+    let out_wang = device.create_buffer::<u32>(inputs.len());
+    let out_pcg = device.create_buffer::<u32>(inputs.len());
+    let out_float = device.create_buffer::<f32>(inputs.len());
+    device.run(
+        [inputs.len() as u32, 1, 1],
+        track!(|| {
+            let tid = dispatch_id().x;
+            let v = x.read(tid);
+            out_wang.write(tid, wang_hash(v));
+            out_pcg.write(tid, pcg_hash(v));
+            out_float.write(tid, hash_to_float(v));
+        }),
+    );
+    assert_eq!(out_wang.copy_to_vec(), wang);
+    assert_eq!(out_pcg.copy_to_vec(), pcg);
+    let floats = out_float.copy_to_vec();
+    for (i, f) in floats.iter().enumerate() {
+        assert_eq!(*f, (inputs[i] >> 8) as f32 / 16777216.0);
+        assert!((0.0..1.0).contains(f));
+    }
+}
+#[test]
+fn frexp_ldexp_round_trip() {
+    use luisa::lang::functions::{frexp, ldexp};
+    let device = get_device();
+    let mut xs = vec![
+        0.0f32,
+        -0.0,
+        1.0,
+        -1.0,
+        0.5,
+        3.0,
+        f32::MAX,
+        f32::MIN_POSITIVE,
+    ];
+    xs.push(f32::MIN_POSITIVE / 1024.0);
+    xs.push(-f32::MIN_POSITIVE / 3.0);
+    let mut rng = rand::thread_rng();
+    for e in -40..40 {
+        xs.push(rng.gen_range(-1.0f32..1.0) * 2.0f32.powi(e * 3));
+    }
+    let n = xs.len();
+    let x = device.create_buffer_from_slice(&xs);
+    let mantissa = device.create_buffer::<f32>(n);
+    let exponent = device.create_buffer::<i32>(n);
+    let y = device.create_buffer::<f32>(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let v = x.var().read(tid);
+            let f = frexp(v);
+            mantissa.var().write(tid, f.mantissa);
+            exponent.var().write(tid, f.exponent);
+            y.var().write(tid, ldexp(f.mantissa, f.exponent));
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let mantissa = mantissa.copy_to_vec();
+    let exponent = exponent.copy_to_vec();
+    let y = y.copy_to_vec();
+    for i in 0..n {
+        assert_eq!(y[i], xs[i], "x: {}", xs[i]);
+        if xs[i] != 0.0 {
+            assert!((0.5..1.0).contains(&mantissa[i].abs()), "x: {}", xs[i]);
+            assert_eq!(
+                mantissa[i] as f64 * 2.0f64.powi(exponent[i]),
+                xs[i] as f64,
+                "x: {}",
+                xs[i]
+            );
+        }
+    }
+}
+#[test]
+fn scope_debug_markers() {
+    let device = get_device();
+    let buf: Buffer<u32> = device.create_buffer(64);
+    let kernel = Kernel::<fn(Buffer<u32>)>::new(&device, &|buf| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid);
+    });
+    device.with_scope(|s| {
+        s.push_debug_marker("outer");
+        s.push_debug_marker("inner")
+            .submit([kernel.dispatch_async([64, 1, 1], &buf)]);
+        assert_eq!(s.debug_markers(), vec!["outer", "inner"]);
+        s.pop_debug_marker().pop_debug_marker();
+        assert!(s.debug_markers().is_empty());
+    });
+    let v = buf.copy_to_vec();
+    assert_eq!(v, (0..64).collect::<Vec<u32>>());
+    // markers in a command buffer are opened and closed with its commands
+    let open = std::cell::RefCell::new(vec![]);
+    device.with_scope(|s| {
+        let mut cb = CommandBuffer::new();
+        cb.push_debug_marker("pass")
+            .push(kernel.dispatch_async([64, 1, 1], &buf))
+            .push_host(|| open.borrow_mut().push(s.debug_markers()))
+            .pop_debug_marker()
+            .push_host(|| open.borrow_mut().push(s.debug_markers()));
+        cb.commit(s);
+    });
+    assert_eq!(open.into_inner(), vec![vec!["pass".to_string()], vec![]]);
+}
+#[test]
+#[should_panic]
+fn scope_debug_marker_unbalanced() {
+    let device = get_device();
+    device.with_scope(|s| {
+        s.push_debug_marker("pass");
+        s.pop_debug_marker().pop_debug_marker();
+    });
+}
+#[test]
+fn unified_buffer() {
+    let device = get_device();
+    if device.name() != "cpu" {
+        assert_eq!(
+            device.create_unified_buffer::<u32>(256).err(),
+            Some(ResourceError::UnifiedMemoryUnsupported)
+        );
+        return;
+    }
+    let mut buf = device.create_unified_buffer::<u32>(256).unwrap();
+    buf.with_slice(|s| s.iter_mut().enumerate().for_each(|(i, x)| *x = i as u32));
+    let kernel = Kernel::<fn(Buffer<u32>)>::new(&device, &|buf| {
+        let tid = dispatch_id().x;
+        track!(buf.write(tid, buf.read(tid) * 2));
+    });
+    kernel.dispatch([256, 1, 1], buf.buffer());
+    buf.with_slice(|s| {
+        for (i, x) in s.iter_mut().enumerate() {
+            assert_eq!(*x, i as u32 * 2);
+            *x += 1;
+        }
+    });
+    kernel.dispatch([256, 1, 1], buf.buffer());
+    buf.with_slice(|s| {
+        for (i, x) in s.iter().enumerate() {
+            assert_eq!(*x, (i as u32 * 2 + 1) * 2);
+        }
+    });
+}
+#[test]
+fn import_buffer_without_ownership() {
+    let device = get_device();
+    let owner = device.create_buffer_from_fn(128, |i| i as u32);
+    {
+        let buf = unsafe {
+            device
+                .import_buffer::<u32>(owner.native_handle(), owner.len())
+                .unwrap()
+        };
+        let kernel = Kernel::<fn()>::new(
+            &device,
+            &track!(|| {
+                let i = dispatch_id().x;
+                buf.write(i, buf.read(i) + 1);
+            }),
+        );
+        kernel.dispatch([128, 1, 1]);
+    }
+    // The imported buffer is gone, the original memory must still be usable.
+    let data = owner.copy_to_vec();
+    for (i, x) in data.iter().enumerate() {
+        assert_eq!(*x, i as u32 + 1);
+    }
+}
+#[test]
+fn import_texture_unsupported() {
+    let device = get_device();
+    let owner = device.create_tex2d::<Float4>(PixelStorage::Float4, 16, 16, 1);
+    let imported = unsafe {
+        device.import_tex2d::<Float4>(owner.native_handle(), PixelStorage::Float4, 16, 16, 1)
+    };
+    assert_eq!(
+        imported.err(),
+        Some(ResourceError::TextureImportUnsupported)
+    );
+}
+#[test]
+fn array_var_local_histogram() {
+    let device = get_device();
+    let threads = 256;
+    let mut rng = thread_rng();
+    let values: Vec<u32> = (0..threads * 100).map(|_| rng.gen_range(0..16)).collect();
+    let x = device.create_buffer_from_slice(&values);
+    let out: Buffer<[u32; 16]> = device.create_buffer(threads);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let hist = Var::<[u32; 16]>::zeroed();
+            for_range(0..100u32, |j| {
+                let bin = x.read(tid * 100 + j);
+                hist.index(bin).store(hist.index(bin).load() + 1);
+            });
+            out.write(tid, hist);
+        }),
+    );
+    kernel.dispatch([threads as u32, 1, 1]);
+    let out = out.copy_to_vec();
+    for (t, hist) in out.iter().enumerate() {
+        let mut expected = [0u32; 16];
+        for v in &values[t * 100..(t + 1) * 100] {
+            expected[*v as usize] += 1;
+        }
+        assert_eq!(*hist, expected);
+    }
+}
+#[test]
+fn stream_with_priority() {
+    use luisa::runtime::Priority;
+    let device = get_device();
+    assert_eq!(device.default_stream().priority(), Priority::Normal);
+    assert_eq!(
+        device.create_stream(StreamTag::Compute).priority(),
+        Priority::Normal
+    );
+    let stream = device.create_stream_with_priority(StreamTag::Compute, Priority::High);
+    assert_eq!(stream.priority(), Priority::High);
+    let buf = device.create_buffer::<u32>(64);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            buf.write(tid, tid);
+        }),
+    );
+    stream.with_scope(|s| {
+        s.submit([kernel.dispatch_async([64, 1, 1])]);
+    });
+    let data = buf.copy_to_vec();
+    for (i, x) in data.iter().enumerate() {
+        assert_eq!(*x, i as u32);
+    }
+}
+#[test]
+fn approx_eq_in_kernel() {
+    use luisa::lang::functions::approx_eq;
+    let device = get_device();
+    let out = device.create_buffer::<u32>(6);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let x = 3.0f32.expr();
+            let v = Float3::expr(1.0, -2.0, 1000.0);
+            let checks = [
+                // exact match
+                approx_eq(x, x, 0.0f32, 0.0f32),
+                // within the absolute tolerance
+                approx_eq(x, x + 1e-3f32, 0.0f32, 1e-2f32),
+                // deliberate mismatch
+                approx_eq(x, x + 1.0f32, 1e-3f32, 1e-3f32),
+                approx_eq(v, v, 0.0f32, 0.0f32),
+                // within the relative tolerance on the largest lane
+                approx_eq(v, v + Float3::expr(0.0, 0.0, 0.5), 1e-3f32, 1e-3f32),
+                // one lane off
+                approx_eq(v, v + Float3::expr(0.0, 0.5, 0.0), 1e-3f32, 1e-3f32),
+            ];
+            for_unrolled(checks.into_iter().enumerate(), |(i, c)| {
+                out.write(i as u32, select(c, 1u32.expr(), 0u32.expr()));
+            });
+        }),
+    );
+    kernel.dispatch([1, 1, 1]);
+    assert_eq!(out.copy_to_vec(), vec![1, 1, 0, 1, 1, 0]);
+}
+#[test]
+fn buffer_hash_command() {
+    let device = get_device();
+    let n = 1 << 20;
+    let a = device.create_buffer_from_fn(n, |i| i as f32 * 0.5);
+    let b = device.create_buffer_from_fn(n, |i| i as f32 * 0.5);
+    let hash = |buf: &Buffer<f32>| {
+        let (commands, hash) = buf.hash_command();
+        device.with_scope(|s| {
+            s.submit(commands);
+        });
+        hash.copy_to_vec()[0]
+    };
+    // a view that fits in one chunk hashes to the FNV-1a of its bytes
+    let small = device.create_buffer_from_fn(100, |i| i as u32 * 7);
+    let fnv = small
+        .copy_to_vec()
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    let (commands, small_hash) = small.hash_command();
+    device.with_scope(|s| {
+        s.submit(commands);
+    });
+    assert_eq!(small_hash.copy_to_vec()[0], fnv);
+    let ha = hash(&a);
+    assert_eq!(ha, hash(&b));
+    b.view(n / 2..n / 2 + 1).copy_from(&[-1.0]);
+    assert_ne!(ha, hash(&b));
+    // swapping two elements changes the hash too
+    let c = device.create_buffer_from_fn(n, |i| match i {
+        0 => 0.5,
+        1 => 0.0,
+        _ => i as f32 * 0.5,
+    });
+    assert_ne!(ha, hash(&c));
+}
+#[test]
+fn device_run() {
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    device.run(
+        [1024, 1, 1],
+        track!(|| {
+            let tid = dispatch_id().x;
+            buf.write(tid, tid * tid);
+        }),
+    );
+    let data = buf.copy_to_vec();
+    for (i, x) in data.iter().enumerate() {
+        assert_eq!(*x, (i * i) as u32);
+    }
+}
+#[test]
+fn device_run_reuses_kernel() {
+    let device = get_device();
+    let a = device.create_buffer::<u32>(256);
+    let b = device.create_buffer::<u32>(256);
+    let count = device.shared_kernel_count();
+    for _ in 0..2 {
+        // the same closure, with other captured values and resources
+        for (buf, k) in [(&a, 1u32), (&b, 2), (&a, 3), (&b, 3)] {
+            device.run(
+                [256, 1, 1],
+                track!(|| {
+                    let tid = dispatch_id().x;
+                    buf.write(tid, tid * k);
+                }),
+            );
+            let data = buf.copy_to_vec();
+            for (i, x) in data.iter().enumerate() {
+                assert_eq!(*x, i as u32 * k);
+            }
+        }
+        // compiled in the first round, reused in the second
+        assert_eq!(device.shared_kernel_count(), count + 4);
+    }
+    // a value that changes on every call does not accumulate kernels
+    for k in 0..2 * RUN_KERNELS_PER_CLOSURE as u32 {
+        device.run(
+            [256, 1, 1],
+            track!(|| {
+                let tid = dispatch_id().x;
+                a.write(tid, tid + k);
+            }),
+        );
+    }
+    assert_eq!(a.copy_to_vec()[1], 2 * RUN_KERNELS_PER_CLOSURE as u32);
+    assert_eq!(
+        device.shared_kernel_count(),
+        count + 4 + RUN_KERNELS_PER_CLOSURE
+    );
+}
+#[test]
+fn create_kernel_checked_detects_races() {
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    let racy = device.create_kernel_checked(&track!(|| {
+        let tid = dispatch_id().x;
+        buf.write(tid / 2, tid);
+    }));
+    assert!(racy.dispatch([1024, 1, 1]) > 0);
+    let disjoint = device.create_kernel_checked(&track!(|| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid);
+    }));
+    assert_eq!(disjoint.dispatch([1024, 1, 1]), 0);
+    assert_eq!(disjoint.dispatch([1024, 1, 1]), 0);
+    let data = buf.copy_to_vec();
+    for (i, x) in data.iter().enumerate() {
+        assert_eq!(*x, i as u32);
+    }
+}
+#[test]
+fn create_kernel_profiled_heatmap() {
+    let device = get_device();
+    let sink = device.create_buffer::<f32>(1024);
+    let kernel = device.create_kernel_profiled(&track!(|| {
+        let tid = dispatch_id().x;
+        // odd threads do a lot more work than even ones
+        let iters = select(tid % 2u32 == 1u32, 20000u32.expr(), 0u32.expr());
+        let acc = 0.0f32.var();
+        for_range(0u32.expr()..iters, |i| {
+            *acc = (acc + i.as_f32()).sin();
+        });
+        sink.write(tid, acc);
+    }));
+    let timings = kernel.dispatch([1024, 1, 1]).copy_to_vec();
+    assert_eq!(timings.len(), 1024);
+    let mean = |parity: usize| timings.iter().skip(parity).step_by(2).sum::<u64>() as f64 / 512.0;
+    assert!(mean(1) > 2.0 * mean(0), "{} vs {}", mean(1), mean(0));
+}
+#[test]
+fn kernel_clone_for_device() {
+    let device_a = get_device();
+    let device_b = get_device();
+    let kernel_a = Kernel::<fn(Buffer<f32>, Buffer<f32>)>::new(
+        &device_a,
+        &track!(|x, y| {
+            let tid = dispatch_id().x;
+            y.write(tid, x.read(tid) * 2.0 + 1.0);
+        }),
+    );
+    let kernel_b = kernel_a.clone_for(&device_b);
+    // the same backend has seen the kernel through kernel_a
+    assert_eq!(kernel_b.cache_key(), kernel_a.cache_key());
+    if device_a.name() == device_b.name() && kernel_a.cache_dir().is_some() {
+        assert!(kernel_b.seen_before());
+    }
+    // on the same device, nothing is compiled again
+    let kernel_a2 = kernel_a.clone_for(&device_a);
+    assert!(std::ptr::eq(kernel_a.raw(), kernel_a2.raw()));
+    let xs: Vec<f32> = (0..1024).map(|i| i as f32).collect();
+    let run = |device: &Device, kernel: &Kernel<fn(Buffer<f32>, Buffer<f32>)>| {
+        let x = device.create_buffer_from_slice(&xs);
+        let y = device.create_buffer::<f32>(1024);
+        kernel.dispatch([1024, 1, 1], &x, &y);
+        y.copy_to_vec()
+    };
+    let ys_a = run(&device_a, &kernel_a);
+    let ys_b = run(&device_b, &kernel_b);
+    assert_eq!(ys_a, ys_b);
+    for (x, y) in xs.iter().zip(ys_b.iter()) {
+        assert_eq!(*y, x * 2.0 + 1.0);
+    }
+}
+#[test]
+#[should_panic]
+#[cfg(debug_assertions)]
+fn texture_read_out_of_bounds() {
+    let device = get_device();
+    let t = device.create_tex2d::<f32>(PixelStorage::Float1, 16, 16, 1);
+    let out = device.create_buffer::<f32>(16 * 16);
+    let k = device.create_kernel::<fn()>(track!(&|| {
+        let tid = dispatch_id().xy();
+        // columns 8..16 fetch past the right edge
+        let v = t.read(tid + Uint2::expr(8u32, 0u32));
+        out.write(tid.x + tid.y * 16u32, v);
+    }));
+    k.dispatch([16, 16, 1]);
+}
+#[test]
+fn command_buffer_host_callback_order() {
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    let fill = |value: u32| {
+        Kernel::<fn(u32)>::new(
+            &device,
+            &track!(|v| {
+                buf.write(dispatch_id().x, v);
+            }),
+        )
+        .dispatch_async([1024, 1, 1], &value)
+    };
+    let seen = RefCell::new(vec![]);
+    let mut commands = CommandBuffer::new();
+    commands
+        .push(fill(1))
+        .push_host(|| seen.borrow_mut().push(buf.copy_to_vec()))
+        .push(fill(2))
+        .push_host(|| seen.borrow_mut().push(buf.copy_to_vec()));
+    device.default_stream().with_scope(|s| commands.commit(s));
+    let seen = seen.into_inner();
+    assert_eq!(seen.len(), 2);
+    assert!(seen[0].iter().all(|x| *x == 1));
+    assert!(seen[1].iter().all(|x| *x == 2));
+}
+#[test]
+fn kernel_launch_bounds() {
+    let options = KernelBuildOptions::default().with_launch_bounds(256, 2);
+    assert_eq!(options.max_registers, 128);
+    let options = KernelBuildOptions {
+        max_registers: 64,
+        ..Default::default()
+    }
+    .with_launch_bounds(128, 1);
+    assert_eq!(options.max_registers, 64);
+
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    let kernel = Kernel::<fn()>::new_with_options(
+        &device,
+        KernelBuildOptions::default().with_launch_bounds(256, 4),
+        &track!(|| {
+            set_block_size([256, 1, 1]);
+            let tid = dispatch_id().x;
+            buf.write(tid, tid * 3u32);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let data = buf.copy_to_vec();
+    for (i, x) in data.iter().enumerate() {
+        assert_eq!(*x, i as u32 * 3);
+    }
+}
+#[test]
+#[should_panic(expected = "sized over")]
+fn kernel_sized_over_rejects_oversized_dispatch() {
+    let device = get_device();
+    let buf = device.create_buffer::<u32>(1024);
+    let kernel = Kernel::<fn()>::new_with_options(
+        &device,
+        KernelBuildOptions::default().sized_over(&buf.view(..)),
+        &track!(|| {
+            let tid = dispatch_id().x;
+            buf.write(tid, tid);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    kernel.dispatch([512, 2, 1]);
+    kernel.dispatch([1025, 1, 1]);
+}
+#[test]
+fn device_cache_dir_and_no_cache() {
+    let salt: u32 = rand::random();
+    let build = |device: &Device| {
+        Kernel::<fn(Buffer<u32>)>::new(
+            device,
+            &track!(|buf| {
+                let tid = dispatch_id().x;
+                buf.write(tid, tid * salt);
+            }),
+        )
+    };
+    let device = get_device();
+    let dir = std::env::temp_dir().join(format!("luisa_cache_{:08x}", salt));
+    device.set_cache_dir(&dir);
+    let first = build(&device);
+    assert_eq!(first.cache_dir(), Some(dir.clone()));
+    // records are kept apart from the backend's files
+    let record = format!("luisa_compute_rs/{:016x}.key", first.cache_key());
+    assert!(dir.join(record).exists());
+    // the backend keeps the binary there as well
+    let binary = format!("kernel_{:016x}", first.cache_key());
+    let names = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    assert!(names.iter().any(|n| n.starts_with(&binary)), "{:?}", names);
+    assert!(!first.seen_before());
+    assert!(build(&device).seen_before());
+    // kernels compiled before keep their directory
+    device.set_cache_dir(dir.join("moved"));
+    assert_eq!(first.cache_dir(), Some(dir.clone()));
+    assert_eq!(build(&device).cache_dir(), Some(dir.join("moved")));
+    // a disabled cache neither reads nor records entries
+    device.set_cache_enabled(false);
+    let uncached = build(&device);
+    assert!(!uncached.seen_before());
+    // nor can its kernels be saved
+    let err = uncached.save(dir.join("uncached.json")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    let buf = device.create_buffer::<u32>(16);
+    uncached.dispatch([16, 1, 1], &buf);
+    let expected = (0..16u32).map(|i| i.wrapping_mul(salt)).collect::<Vec<_>>();
+    assert_eq!(buf.copy_to_vec(), expected);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+#[test]
+fn kernel_save_load() {
+    let salt: u32 = rand::random();
+    let kernel = get_device().create_kernel::<fn(Buffer<u32>)>(&track!(|buf| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid ^ salt);
+    }));
+    if kernel.cache_dir().is_none() {
+        return;
+    }
+    let path = std::env::temp_dir().join("luisa_kernel_save_load.json");
+    kernel.save(&path).unwrap();
+    // a fresh device stands in for the next run
+    let device = get_device();
+    let loaded = device
+        .load_kernel::<fn(Buffer<u32>)>(
+            &path,
+            &track!(|buf| {
+                let tid = dispatch_id().x;
+                buf.write(tid, tid ^ salt);
+            }),
+        )
+        .unwrap();
+    assert!(loaded.seen_before());
+    let buf = device.create_buffer::<u32>(16);
+    loaded.dispatch([16, 1, 1], &buf);
+    let expected = (0..16).map(|i| i ^ salt).collect::<Vec<_>>();
+    assert_eq!(buf.copy_to_vec(), expected);
+
+    let err = device
+        .load_kernel::<fn(Buffer<f32>)>(&path, &|buf| {
+            buf.write(dispatch_id().x, 0.0f32);
+        })
+        .err()
+        .unwrap();
+    assert!(matches!(err, KernelLoadError::SignatureMismatch { .. }));
+    let err = device
+        .load_kernel::<fn(Buffer<u32>)>(
+            &path,
+            &track!(|buf| {
+                let tid = dispatch_id().x;
+                buf.write(tid, tid + salt);
+            }),
+        )
+        .err()
+        .unwrap();
+    assert!(matches!(err, KernelLoadError::Stale));
+}
+#[test]
+fn kernel_preload_cache() {
+    let salt: u32 = rand::random();
+    let kernel = get_device().create_kernel::<fn(Buffer<u32>)>(&track!(|buf| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid ^ salt);
+    }));
+    if kernel.cache_dir().is_none() {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("luisa_preload_{:08x}", salt));
+    std::fs::create_dir_all(&dir).unwrap();
+    kernel.save(dir.join("xor.json")).unwrap();
+    let ctx = get_context();
+    assert_eq!(ctx.preload_cache(&dir).unwrap(), 1);
+    let device = ctx.create_device(device_name());
+    let compiles = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    {
+        let compiles = compiles.clone();
+        device.set_compile_progress_callback(move |_, state| {
+            if state == CompileState::Started {
+                compiles.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+    }
+    // the preloaded shader is picked up without compiling again
+    let preloaded = device.create_kernel_async::<fn(Buffer<u32>)>(&track!(|buf| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid ^ salt);
+    }));
+    assert!(preloaded.is_ready());
+    assert_eq!(compiles.load(std::sync::atomic::Ordering::SeqCst), 0);
+    let buf = device.create_buffer::<u32>(16);
+    preloaded.dispatch([16, 1, 1], &buf);
+    let expected = (0..16).map(|i| i ^ salt).collect::<Vec<_>>();
+    assert_eq!(buf.copy_to_vec(), expected);
+    // while any other kernel is still compiled
+    let other = device.create_kernel_async::<fn(Buffer<u32>)>(&track!(|buf| {
+        let tid = dispatch_id().x;
+        buf.write(tid, tid + salt);
+    }));
+    other.wait_for_compile();
+    assert_eq!(compiles.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    std::fs::write(dir.join("broken.json"), "{}").unwrap();
+    assert!(matches!(
+        ctx.preload_cache(&dir),
+        Err(KernelLoadError::Malformed)
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+#[test]
+fn kernel_seen_before_across_devices() {
+    // a fresh salt makes the kernel new to the cache on every test run
+    let salt: u32 = rand::random();
+    let build = |device: &Device| {
+        Kernel::<fn(Buffer<u32>)>::new(
+            device,
+            &track!(|buf| {
+                let tid = dispatch_id().x;
+                buf.write(tid, tid ^ salt);
+            }),
+        )
+    };
+    // each device stands in for a separate process sharing the cache
+    let first = build(&get_device());
+    if first.cache_dir().is_none() {
+        return;
+    }
+    assert!(!first.seen_before());
+    let device = get_device();
+    let second = build(&device);
+    assert_eq!(first.cache_key(), second.cache_key());
+    assert!(second.seen_before());
+    let buf = device.create_buffer::<u32>(16);
+    second.dispatch([16, 1, 1], &buf);
+    let expected = (0..16).map(|i| i ^ salt).collect::<Vec<_>>();
+    assert_eq!(buf.copy_to_vec(), expected);
+}
+#[test]
+fn frame_ring_waits_before_reuse() {
+    let device = get_device();
+    let stream = device.create_stream(StreamTag::Compute);
+    let mut ring = device.create_frame_ring::<Buffer<u32>, 3>(|_| {
+        let buf = device.create_buffer(1 << 16);
+        buf.fill(0);
+        buf
+    });
+    let kernel = Kernel::<fn(Buffer<u32>, u32)>::new(
+        &device,
+        &track!(|buf, v| {
+            buf.write(dispatch_id().x, v);
+        }),
+    );
+    let scope = stream.scope();
+    for k in 0..12u32 {
+        let frame = ring.begin_frame(&scope);
+        assert_eq!(frame.number(), k as u64);
+        // the resources hold what frame k - 3 wrote, so that frame has
+        // finished before they are handed out again
+        let expected = if k >= 3 { k - 2 } else { 0 };
+        assert!(frame
+            .resources()
+            .copy_to_vec()
+            .iter()
+            .all(|&x| x == expected));
+        let value = k + 1;
+        scope.submit([kernel.dispatch_async([1 << 16, 1, 1], frame.resources(), &value)]);
+    }
+    scope.synchronize();
+    assert_eq!(ring.frame_count(), 12);
+    assert!((0..12).all(|k| ring.is_completed(k)));
+}
+#[test]
+fn kernel_opt_levels_agree() {
+    let device = get_device();
+    let run = |level: OptLevel| {
+        let out = device.create_buffer::<u32>(1024);
+        let kernel = device.create_kernel_with_opt::<fn(Buffer<u32>)>(level, &|buf| {
+            track!({
+                let tid = dispatch_id().x;
+                let acc = tid.var();
+                for_range(0..16u32, |i| {
+                    *acc = acc * 31u32 + i ^ (acc >> 3u32);
+                });
+                buf.write(tid, acc);
+            })
+        });
+        kernel.dispatch([1024, 1, 1], &out);
+        out.copy_to_vec()
+    };
+    assert_eq!(
+        KernelBuildOptions::default()
+            .with_opt_level(OptLevel::Debug)
+            .opt_level(),
+        OptLevel::Debug
+    );
+    assert_eq!(KernelBuildOptions::default().opt_level(), OptLevel::Release);
+    let debug = run(OptLevel::Debug);
+    let release = run(OptLevel::Release);
+    assert_eq!(debug, release);
+    let expected = (0..1024u32)
+        .map(|tid| {
+            (0..16u32).fold(tid, |acc, i| {
+                acc.wrapping_mul(31).wrapping_add(i) ^ (acc >> 3)
+            })
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(release, expected);
+}
+#[test]
+fn buffer_image2d_indexing() {
+    let device = get_device();
+    let (width, height) = (37u32, 19u32);
+    let n = (width * height) as usize;
+    let src = device.create_buffer_from_fn(n, |i| Float4::new(i as f32, 0.0, 0.0, 1.0));
+    let image = device.create_buffer::<Float4>(n);
+    let linear = device.create_buffer::<Float4>(n);
+    let src_image = src.as_image2d(width, height);
+    let image = image.as_image2d(width, height);
+    assert_eq!((image.width(), image.height()), (width, height));
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let p = dispatch_id().xy();
+            // copy through (x, y), tagging each pixel with its coordinates
+            let v = src_image.var().read(p.x, p.y);
+            image.var().write(
+                p.x,
+                p.y,
+                v + Float4::expr(0.0f32, p.x.as_f32(), p.y.as_f32(), 0.0f32),
+            );
+            let i = p.y * width + p.x;
+            linear.var().write(
+                i,
+                src.var().read(i) + Float4::expr(0.0f32, p.x.as_f32(), p.y.as_f32(), 0.0f32),
+            );
+        }),
+    );
+    kernel.dispatch([width, height, 1]);
+    let got = image.copy_to_vec();
+    assert_eq!(got, linear.copy_to_vec());
+    for y in 0..height {
+        for x in 0..width {
+            let v = got[(y * width + x) as usize];
+            assert_eq!(
+                v,
+                Float4::new((y * width + x) as f32, x as f32, y as f32, 1.0)
+            );
+        }
+    }
+}
+#[test]
+fn async_compile_progress_callback() {
+    let device = get_device();
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    {
+        let events = events.clone();
+        device.set_compile_progress_callback(move |name, state| {
+            events.lock().unwrap().push((name.to_string(), state));
+        });
+    }
+    let buf = device.create_buffer::<u32>(1024);
+    let kernel = Kernel::<fn()>::new_with_options(
+        &device,
+        KernelBuildOptions {
+            async_compile: true,
+            name: Some("progress_test".to_string()),
+            ..Default::default()
+        },
+        &track!(|| {
+            let tid = dispatch_id().x;
+            buf.write(tid, tid);
+        }),
+    );
+    kernel.ensure_ready();
+    assert!(kernel.is_ready());
+    let name = "progress_test".to_string();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            (name.clone(), CompileState::Started),
+            (name, CompileState::Finished)
+        ]
+    );
+    kernel.dispatch([1024, 1, 1]);
+    assert_eq!(buf.copy_to_vec()[1023], 1023);
+}
+#[test]
+fn kernel_name_by_cache_key() {
+    let device = get_device();
+    let names = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    {
+        let names = names.clone();
+        device.set_compile_progress_callback(move |name, state| {
+            if state == CompileState::Finished {
+                names.lock().unwrap().push(name.to_string());
+            }
+        });
+    }
+    let buf = device.create_buffer::<u32>(16);
+    let build = |name_by_cache_key: bool| {
+        let kernel = Kernel::<fn()>::new_with_options(
+            &device,
+            KernelBuildOptions {
+                async_compile: true,
+                name_by_cache_key,
+                ..Default::default()
+            },
+            &track!(|| {
+                let tid = dispatch_id().x;
+                buf.write(tid, tid);
+            }),
+        );
+        kernel.ensure_ready();
+        kernel
+    };
+    // unnamed kernels keep no name unless asked to
+    build(false);
+    let named = build(true);
+    assert_eq!(
+        *names.lock().unwrap(),
+        ["".to_string(), format!("kernel_{:016x}", named.cache_key())]
+    );
+}
+#[test]
+fn gather_scatter() {
+    let device = get_device();
+    let n = 1024;
+    let src = device.create_buffer_from_fn(n, |i| i as f32 * 0.5);
+    let perm: Vec<u32> = (0..n as u32).map(|i| (i * 37 + 11) % n as u32).collect();
+    let index = device.create_buffer_from_slice(&perm);
+    let dst = device.create_buffer::<f32>(n);
+
+    let gather = device.create_gather::<f32>();
+    gather.dispatch([n as u32, 1, 1], &src, &index, &dst);
+    let gathered = dst.copy_to_vec();
+    for i in 0..n {
+        assert_eq!(gathered[i], perm[i] as f32 * 0.5);
+    }
+
+    let scatter = device.create_scatter::<f32>();
+    scatter.dispatch([n as u32, 1, 1], &src, &index, &dst);
+    let scattered = dst.copy_to_vec();
+    for i in 0..n {
+        assert_eq!(scattered[perm[i] as usize], i as f32 * 0.5);
+    }
+
+    // every bucket receives 4 elements
+    let buckets: Vec<u32> = (0..n as u32).map(|i| i % 256).collect();
+    let index = device.create_buffer_from_slice(&buckets);
+    let ones = device.create_buffer_from_fn(n, |_| 1u32);
+    let counts = device.create_buffer::<u32>(256);
+    counts.fill(0);
+    let scatter_add = device.create_scatter_add::<u32>();
+    scatter_add.dispatch([n as u32, 1, 1], &ones, &index, &counts);
+    assert!(counts.copy_to_vec().iter().all(|c| *c == 4));
+}
+#[test]
+fn kernel_record_verify_io() {
+    let device = get_device();
+    let x = device.create_buffer_from_fn(1024, |i| i as f32);
+    let y = device.create_buffer::<f32>(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            y.write(tid, x.read(tid).sin());
+        }),
+    );
+    let path = std::env::temp_dir().join("luisa_kernel_record_verify_io.bin");
+    kernel.dispatch([1024, 1, 1]);
+    kernel.record_io(&path).unwrap();
+    y.fill(0.0);
+    kernel.dispatch([1024, 1, 1]);
+    kernel.verify_io(&path).unwrap();
+}
+#[test]
+fn kernel_verify_io_detects_nondeterminism() {
+    let device = get_device();
+    let counter = device.create_buffer_from_slice(&[0u32]);
+    let y = device.create_buffer::<u32>(1024);
+    // the counter is never reset, so each run hands out different tickets
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            y.write(tid, counter.atomic_fetch_add(0, 1u32));
+        }),
+    );
+    let path = std::env::temp_dir().join("luisa_kernel_verify_io_nondeterminism.bin");
+    kernel.dispatch([1024, 1, 1]);
+    kernel.record_io(&path).unwrap();
+    kernel.dispatch([1024, 1, 1]);
+    let err = kernel.verify_io(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("differs at byte"), "{}", err);
+    let missing = std::env::temp_dir().join("luisa_kernel_verify_io_missing.bin");
+    assert_eq!(
+        kernel.verify_io(missing).unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+#[test]
+fn device_features() {
+    let device = get_device();
+    let features = device.features();
+    match device.name().as_str() {
+        "cpu" => assert_eq!(
+            features,
+            Features {
+                ray_tracing: Some(true),
+                f16: Some(false),
+                f64: Some(true),
+                int64_atomics: Some(true),
+                subgroup_ops: Some(false),
+                shared_memory: Some(true),
+                bindless: Some(true),
+                printf: Some(true),
+            }
+        ),
+        "cuda" | "dx" | "metal" => {
+            assert_eq!(features.subgroup_ops, Some(true));
+            assert_eq!(features.shared_memory, Some(true));
+            assert_eq!(features.bindless, Some(true));
+        }
+        _ => {}
+    }
+}
+#[test]
+fn device_properties() {
+    let device = get_device();
+    let properties = device.properties();
+    match device.name().as_str() {
+        "cpu" => assert_eq!(
+            properties,
+            Properties {
+                max_buffer_size: Some(isize::MAX as usize),
+                max_texture_2d_size: Some(16384),
+                max_texture_3d_size: Some(2048),
+                max_threads_per_block: Some(1),
+                ray_tracing: Some(true),
+            }
+        ),
+        "cuda" | "dx" | "metal" => {
+            assert_eq!(properties.max_threads_per_block, Some(1024));
+            assert_eq!(properties.max_buffer_size, None);
+        }
+        _ => {}
+    }
+}
+#[test]
+fn persistent_kernel_processes_each_item_once() {
+    let device = get_device();
+    let n = 10000;
+    let counts = device.create_buffer_from_fn(n, |_| 0u32);
+    let kernel = device.create_persistent_kernel::<u32>(64, |item| {
+        counts.var().atomic_fetch_add(item, 1u32);
+    });
+    let mut queue = device.create_work_queue::<u32>(n);
+    let items = (0..n as u32).collect::<Vec<_>>();
+    queue.push(&items[..n / 3]);
+    queue.push(&items[n / 3..]);
+    assert_eq!(queue.len(), n);
+    kernel.dispatch(&mut queue);
+    assert!(queue.is_empty());
+    assert!(counts.copy_to_vec().iter().all(|&c| c == 1));
+    // the drained queue can be refilled
+    queue.push(&items[..100]);
+    kernel.dispatch(&mut queue);
+    let counts = counts.copy_to_vec();
+    for i in 0..n {
+        assert_eq!(counts[i], if i < 100 { 2 } else { 1 }, "item {}", i);
+    }
+}
+#[test]
+fn mat3_inverse() {
+    use luisa::lang::types::vector::Mat3;
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    let n = 1024;
+    let ms: Vec<[f32; 9]> = (0..n)
+        .map(|_| {
+            let mut m: [f32; 9] = std::array::from_fn(|_| rng.gen_range(-1.0..1.0));
+            // diagonally dominant, hence well conditioned
+            for i in 0..3 {
+                m[i * 4] += 3.0;
+            }
+            m
+        })
+        .collect();
+    let m_buf = device.create_buffer_from_fn(n, |i| {
+        let m = &ms[i];
+        Mat3::from_column_array(&[[m[0], m[1], m[2]], [m[3], m[4], m[5]], [m[6], m[7], m[8]]])
+    });
+    let err: Buffer<f32> = device.create_buffer(n);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let m = m_buf.var().read(tid);
+            let p = m * m.inverse();
+            let d0 = p.col(0) - Float3::expr(1.0f32, 0.0f32, 0.0f32);
+            let d1 = p.col(1) - Float3::expr(0.0f32, 1.0f32, 0.0f32);
+            let d2 = p.col(2) - Float3::expr(0.0f32, 0.0f32, 1.0f32);
+            err.var().write(
+                tid,
+                d0.abs()
+                    .reduce_max()
+                    .max(d1.abs().reduce_max())
+                    .max(d2.abs().reduce_max()),
+            );
+        }),
+    )
+    .dispatch([n as u32, 1, 1]);
+    for (i, e) in err.copy_to_vec().into_iter().enumerate() {
+        assert!(e < 1e-5, "{:?}: {}", ms[i], e);
+    }
+}
+#[test]
+fn mat4_ops() {
+    use luisa::lang::types::vector::Mat4;
+    type M = [[f32; 4]; 4];
+    let device = get_device();
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut random =
+        || -> M { std::array::from_fn(|_| std::array::from_fn(|_| rng.gen_range(-2.0..2.0))) };
+    let (a, b) = (random(), random());
+    let v: [f32; 4] = random()[0];
+    let ab = device
+        .create_buffer_from_slice(&[Mat4::from_column_array(&a), Mat4::from_column_array(&b)]);
+    let v_buf = device.create_buffer_from_slice(&[Float4::from(v)]);
+    let mats: Buffer<Mat4> = device.create_buffer(3);
+    let mv: Buffer<Float4> = device.create_buffer(1);
+    let det: Buffer<f32> = device.create_buffer(1);
+    let kernel = device.create_kernel::<fn()>(&track!(|| {
+        let a = ab.var().read(0);
+        let b = ab.var().read(1);
+        mats.var().write(0, a * b);
+        mats.var().write(1, a.transpose());
+        mats.var().write(2, a.comp_mul(b));
+        mv.var().write(0, a * v_buf.var().read(0));
+        det.var().write(0, a.determinant());
+    }));
+    kernel.dispatch([1, 1, 1]);
+    let mats = mats.copy_to_vec();
+    let (mul, transpose, comp_mul) = (
+        mats[0].to_column_array(),
+        mats[1].to_column_array(),
+        mats[2].to_column_array(),
+    );
+    let mv: [f32; 4] = mv.copy_to_vec()[0].into();
+    let close = |x: f32, y: f64| (x as f64 - y).abs() < 1e-4 * (1.0 + y.abs());
+    // element (row r, column c) is a[c][r]
+    for c in 0..4 {
+        for r in 0..4 {
+            let expected: f64 = (0..4).map(|k| a[k][r] as f64 * b[c][k] as f64).sum();
+            assert!(close(mul[c][r], expected), "a * b [{}][{}]", r, c);
+            assert_eq!(transpose[c][r], a[r][c]);
+            assert_eq!(comp_mul[c][r], a[c][r] * b[c][r]);
+        }
+        let expected: f64 = (0..4).map(|k| a[k][c] as f64 * v[k] as f64).sum();
+        assert!(close(mv[c], expected), "a * v [{}]", c);
+    }
+    // Laplace expansion along the first column
+    let det3 = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[2][1] * m[1][2])
+            - m[1][0] * (m[0][1] * m[2][2] - m[2][1] * m[0][2])
+            + m[2][0] * (m[0][1] * m[1][2] - m[1][1] * m[0][2])
+    };
+    let expected: f64 = (0..4)
+        .map(|r| {
+            let minor: [[f64; 3]; 3] = std::array::from_fn(|c| {
+                std::array::from_fn(|i| a[c + 1][if i < r { i } else { i + 1 }] as f64)
+            });
+            let sign = if r % 2 == 0 { 1.0 } else { -1.0 };
+            sign * a[0][r] as f64 * det3(minor)
+        })
+        .sum();
+    assert!(
+        close(det.copy_to_vec()[0], expected),
+        "{} {}",
+        det.copy_to_vec()[0],
+        expected
+    );
+}
+#[test]
+fn quat_rotations() {
+    use luisa::lang::geometry::Quat;
+    use std::f32::consts::FRAC_PI_2;
+    let device = get_device();
+    // 90 degrees about x, y and z
+    let axes = [
+        Float3::new(1.0, 0.0, 0.0),
+        Float3::new(0.0, 1.0, 0.0),
+        Float3::new(0.0, 0.0, 1.0),
+    ];
+    let axes_buf = device.create_buffer_from_slice(&axes);
+    let qs = device.create_buffer_from_fn(3, |i| Quat::from_axis_angle(axes[i], FRAC_PI_2));
+    let out: Buffer<Float3> = device.create_buffer(3 * 4);
+    device
+        .create_kernel::<fn()>(&track!(|| {
+            let i = dispatch_id().x;
+            let q = qs.var().read(i);
+            let axis = axes_buf.var().read(i);
+            // the next axis in cyclic order
+            let p = axis.zxy();
+            let out = out.var();
+            out.write(i * 4u32, q.rotate(p));
+            // the same rotation built in the kernel, from an unnormalized axis
+            let q2 = Quat::from_axis_angle_expr(axis * 2.0f32, FRAC_PI_2);
+            out.write(i * 4u32 + 1u32, q2.rotate(p));
+            // twice 90 degrees is 180 degrees
+            out.write(i * 4u32 + 2u32, (q * q).normalize().rotate(p));
+            out.write(i * 4u32 + 3u32, q.conjugate().rotate(q.rotate(p)));
+        }))
+        .dispatch([3, 1, 1]);
+    let out = out.copy_to_vec();
+    let close = |a: Float3, b: [f32; 3]| {
+        (a.x - b[0]).abs() < 1e-5 && (a.y - b[1]).abs() < 1e-5 && (a.z - b[2]).abs() < 1e-5
+    };
+    // rotating by 90 degrees about an axis maps the next axis to the one after
+    let expected = [
+        ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+        ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    for (i, (p, rotated)) in expected.into_iter().enumerate() {
+        let o = &out[i * 4..i * 4 + 4];
+        assert!(close(o[0], rotated), "axis {}: {:?}", i, o[0]);
+        assert!(close(o[1], rotated), "axis {}: {:?}", i, o[1]);
+        assert!(close(o[2], p.map(|c| -c)), "axis {}: {:?}", i, o[2]);
+        assert!(close(o[3], p), "axis {}: {:?}", i, o[3]);
+    }
+}
+#[test]
+fn try_create_buffer_from_slice() {
+    let device = get_device();
+    let data = (0..4096).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+    let buffer = device.try_create_buffer_from_slice(&data).unwrap();
+    assert_eq!(buffer.len(), data.len());
+    assert_eq!(buffer.copy_to_vec(), data);
+}
+#[test]
+fn try_create_buffer_impossible_size() {
+    let device = get_device();
+    // bytes, so that no backend rejects the element type or count first
+    let size = 1usize << 60;
+    assert_eq!(
+        device.try_create_buffer::<u8>(size).err(),
+        Some(ResourceError::AllocationFailed { size_bytes: size })
+    );
+    // the device is still usable afterwards
+    let buffer = device.try_create_buffer::<u32>(16).unwrap();
+    assert_eq!(buffer.len(), 16);
+}
+#[test]
+fn recorded_commands_replay() {
+    let device = get_device();
+    let buf = device.create_buffer_from_fn(1024, |_| 0u32);
+    let inc = Kernel::<fn(u32)>::new(
+        &device,
+        &track!(|v| {
+            let i = dispatch_id().x;
+            buf.write(i, buf.read(i) + v);
+        }),
+    );
+    let mut commands = RecordedCommands::new();
+    commands
+        .push(inc.dispatch_async([1024, 1, 1], &1))
+        .push(inc.dispatch_async([1024, 1, 1], &2));
+    assert_eq!(commands.len(), 2);
+    let stream = device.default_stream();
+    for frame in 1..=4u32 {
+        stream.with_scope(|s| commands.replay(s));
+        assert!(buf.copy_to_vec().iter().all(|&x| x == frame * 3));
+    }
+}
+#[test]
+fn buffer_view_at() {
+    use luisa::runtime::ResourceError;
+    let device = get_device();
+    let buf = device.create_buffer_from_fn(100, |i| i as u32);
+    let view = buf.view_at(10, 20).unwrap();
+    assert_eq!(view.len(), 20);
+    assert_eq!(view.copy_to_vec(), (10..30).collect::<Vec<u32>>());
+    view.copy_from(&[7u32; 20]);
+    let data = buf.copy_to_vec();
+    for i in 0..100 {
+        assert_eq!(data[i], if (10..30).contains(&i) { 7 } else { i as u32 });
+    }
+    // views of views are relative to the outer view
+    assert_eq!(view.view_at(5, 5).unwrap().copy_to_vec(), [7u32; 5]);
+    assert!(buf.view_at(100, 0).is_ok());
+    assert_eq!(
+        buf.view_at(90, 11).err(),
+        Some(ResourceError::ViewOutOfBounds {
+            offset: 90,
+            len: 11,
+            view_len: 100,
+        })
+    );
+    assert!(view.view_at(usize::MAX, 2).is_err());
+}
+#[test]
+fn buffer_view_of_view_offset() {
+    let device = get_device();
+    let buf = device.create_buffer_from_fn(100, |i| i as u32);
+    // a view of a view starts at the offset of the outer view
+    let outer = buf.view(40..80);
+    let inner = outer.view(10..20);
+    assert_eq!(inner.copy_to_vec(), (50..60).collect::<Vec<u32>>());
+    assert_eq!(
+        inner.view(5..).copy_to_vec(),
+        (55..60).collect::<Vec<u32>>()
+    );
+    inner.copy_from(&[0u32; 10]);
+    let data = buf.copy_to_vec();
+    for i in 0..100 {
+        assert_eq!(data[i], if (50..60).contains(&i) { 0 } else { i as u32 });
+    }
+}
+#[test]
+fn shared_block_reduce() {
+    let device = get_device();
+    // the cpu backend only runs blocks of a single thread
+    let block = if device.name() == "cpu" { 1 } else { 64u32 };
+    let n_blocks = 16u32;
+    let mut rng = StdRng::seed_from_u64(0);
+    let x = device.create_buffer_from_fn((block * n_blocks) as usize, |_| rng.gen::<f32>());
+    let sums = device.create_buffer::<f32>(n_blocks as usize);
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        set_block_size([block, 1, 1]);
+        let tid = thread_id().x;
+        let partial = shared::<f32>(block as usize);
+        partial.write(tid, x.read(dispatch_id().x));
+        sync_block();
+        let mut stride = block / 2;
+        while stride > 0 {
+            track!(if tid < stride {
+                partial.write(tid, partial.read(tid) + partial.read(tid + stride));
+            });
+            sync_block();
+            stride /= 2;
+        }
+        track!(if tid == 0u32 {
+            sums.write(block_id().x, partial.read(0u32));
+        });
+    });
+    kernel.dispatch([block * n_blocks, 1, 1]);
+    let x = x.copy_to_vec();
+    let sums = sums.copy_to_vec();
+    for (i, sum) in sums.iter().enumerate() {
+        let expected: f32 = x[i * block as usize..(i + 1) * block as usize].iter().sum();
+        assert!(
+            (sum - expected).abs() < 1e-4,
+            "block {}: {} != {}",
+            i,
+            sum,
+            expected
+        );
+    }
+}
+#[test]
+fn sync_block_orders_shared_writes() {
+    let device = get_device();
+    let block = if device.name() == "cpu" { 1 } else { 128u32 };
+    let out = device.create_buffer::<u32>(block as usize * 4);
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        set_block_size([block, 1, 1]);
+        let tid = thread_id().x;
+        let produced = shared::<u32>(block as usize);
+        track!(produced.write(tid, tid * tid + block_id().x));
+        sync_block();
+        // consume what the next thread of the block produced
+        let next = track!((tid + 1u32) % block);
+        out.write(dispatch_id().x, produced.read(next));
+    });
+    kernel.dispatch([block * 4, 1, 1]);
+    let out = out.copy_to_vec();
+    for (i, x) in out.iter().enumerate() {
+        let (b, t) = (i as u32 / block, i as u32 % block);
+        let next = (t + 1) % block;
+        assert_eq!(*x, next * next + b);
+    }
+}
+#[test]
+fn warp_reductions() {
+    let device = get_device();
+    let cpu = device.name() == "cpu";
+    if !cpu && device.features().subgroup_ops != Some(true) {
+        return;
+    }
+    let block = if cpu { 1 } else { 64u32 };
+    let n = block * 4;
+    let lanes = device.create_buffer::<u32>(n as usize);
+    let max_id = device.create_buffer::<u32>(n as usize);
+    let even = device.create_buffer::<u32>(n as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            set_block_size([block, 1, 1]);
+            let i = dispatch_id().x;
+            lanes.write(i, warp_reduce_sum(1u32.expr()));
+            max_id.write(i, warp_reduce_max(i));
+            let mask = warp_ballot(thread_id().x % 2u32 == 0u32);
+            let count = mask.x.count_ones() + mask.y.count_ones();
+            even.write(i, count + mask.z.count_ones() + mask.w.count_ones());
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    let lanes = lanes.copy_to_vec();
+    let max_id = max_id.copy_to_vec();
+    let even = even.copy_to_vec();
+    // every lane is active, so the sum of ones is the warp size
+    let warp = lanes[0];
+    assert!(warp.is_power_of_two() && warp <= block);
+    for i in 0..n as usize {
+        assert_eq!(lanes[i], warp);
+        assert_eq!(max_id[i], (i as u32 / warp + 1) * warp - 1);
+        assert_eq!(even[i], (warp + 1) / 2);
+    }
+}
+#[test]
+fn while_loop_break_continue() {
+    let device = get_device();
+    let out = device.create_buffer::<Int4>(1024);
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        let tid = dispatch_id().x;
+        let n = track!((tid % 100u32).as_i32());
+        // 1 + 2 + ... + n
+        let i = 0i32.var();
+        let sum = 0i32.var();
+        while_!(track!(i < n), {
+            track! {
+                *i += 1i32;
+                *sum += i;
+            }
+        });
+        // the odd numbers up to n
+        let k = 0i32.var();
+        let odd = 0i32.var();
+        while_!(track!(k < n), {
+            track!(*k += 1i32);
+            if_!(track!(k % 2i32 == 0i32), {
+                continue_!();
+            });
+            track!(*odd += k);
+        });
+        // a break in a nested if only leaves the inner loop
+        let outer = 0i32.var();
+        let total = 0i32.var();
+        while_!(track!(outer < 3i32), {
+            track!(*outer += 1i32);
+            let j = 0i32.var();
+            loop_!({
+                if_!(track!(j >= n), {
+                    if_!(true.expr(), {
+                        break_!();
+                    });
+                });
+                track! {
+                    *j += 1i32;
+                    *total += 1i32;
+                }
+            });
+        });
+        out.write(tid, Int4::expr(sum, odd, outer, total));
+    });
+    kernel.dispatch([1024, 1, 1]);
+    let out = out.copy_to_vec();
+    for (i, v) in out.iter().enumerate() {
+        let n = i as i32 % 100;
+        let odd = (n + 1) / 2;
+        assert_eq!(*v, Int4::new(n * (n + 1) / 2, odd * odd, 3, 3 * n));
+    }
+}
+#[test]
+fn device_print_formats() {
+    let device = get_device();
+    let foos = device.create_buffer_from_fn(4, |i| Foo {
+        i: i as u32,
+        v: Float2::new(i as f32, 0.5),
+        a: [1, 2, 3, 4],
+        m: Mat2::from_column_array(&[[1.0, 0.0], [0.0, 1.0]]),
+    });
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let foo = foos.read(tid);
+            device_print!("scalar {}", tid);
+            device_print!("vector {} and struct {}", foo.v, foo);
+            device_print!("no arguments");
+        }),
+    );
+    kernel.dispatch([4, 1, 1]);
+    device.default_stream().synchronize();
+}
+#[test]
+#[cfg(feature = "device-assert")]
+fn device_assert_reports_failure() {
+    let device = get_device();
+    let x = device.create_buffer_from_fn(1024, |i| i as f32);
+    let out = device.create_buffer_from_fn(1024, |_| 0.0f32);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let v = x.read(tid);
+            device_assert!(v.ne(700.0f32), "v is 700");
+            out.write(tid, v * 2.0f32);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let err = kernel.check_asserts().unwrap_err();
+    assert_eq!(err.dispatch_id, [700, 0, 0]);
+    assert!(err.message.starts_with("v is 700 at "), "{}", err);
+    // only the failing thread stopped
+    let out = out.copy_to_vec();
+    assert_eq!(out[700], 0.0);
+    assert_eq!(out[701], 1402.0);
+    assert_eq!(kernel.check_asserts(), Ok(()));
+}
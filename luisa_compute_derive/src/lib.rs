@@ -32,6 +32,13 @@ pub fn derive_kernel_arg(item: TokenStream) -> TokenStream {
     compiler.derive_kernel_arg(&item).into()
 }
 
+#[proc_macro_derive(Lerp, attributes(luisa))]
+pub fn derive_lerp(item: TokenStream) -> TokenStream {
+    let item: syn::ItemStruct = syn::parse(item).unwrap();
+    let mut compiler = luisa_compute_derive_impl::Compiler::new();
+    compiler.derive_lerp(&item).into()
+}
+
 #[proc_macro_derive(Aggregate, attributes(luisa))]
 pub fn derive_aggregate(item: TokenStream) -> TokenStream {
     let item: syn::Item = syn::parse(item).unwrap();
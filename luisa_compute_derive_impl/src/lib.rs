@@ -711,6 +711,32 @@ impl Compiler {
             #new_expr
         }
     }
+    pub fn derive_lerp(&mut self, struct_: &ItemStruct) -> TokenStream {
+        let attrs = self.parse_luisa_attributes(&struct_.attrs);
+        self.set_crate_path_from_attrs(&attrs);
+        let span = struct_.span();
+        let lang_path = self.lang_path();
+        let generics = &struct_.generics;
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        let name = &struct_.ident;
+        let comps_name = syn::Ident::new(&format!("{}Comps", name), name.span());
+        let fields: Vec<_> = struct_.fields.iter().collect();
+        let field_types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+        let field_names: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+        quote_spanned!(span=>
+            impl #impl_generics #lang_path::functions::Lerp for #name #ty_generics #where_clause {
+                fn lerp_expr(
+                    a: #lang_path::types::Expr<Self>,
+                    b: #lang_path::types::Expr<Self>,
+                    t: #lang_path::types::Expr<f32>,
+                ) -> #lang_path::types::Expr<Self> {
+                    #name::from_comps_expr(#comps_name {
+                        #(#field_names: <#field_types as #lang_path::functions::Lerp>::lerp_expr(a.#field_names, b.#field_names, t),)*
+                    })
+                }
+            }
+        )
+    }
     pub fn derive_aggregate_for_struct(&mut self, struct_: &ItemStruct) -> TokenStream {
         let attrs = self.parse_luisa_attributes(&struct_.attrs);
         self.set_crate_path_from_attrs(&attrs);